@@ -0,0 +1,73 @@
+//! The shipped examples, decoding every instruction when executing it,
+//! keeping the decoded instructions in the cache, and running translated
+//! blocks.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use interpreter::{Console, Machine};
+
+const EXAMPLES: &[(&str, &[u8], &[u8])] = &[
+    (
+        "99bottles",
+        include_bytes!("../examples/99bottles.bin"),
+        b"",
+    ),
+    ("count", include_bytes!("../examples/count.bin"), b""),
+    (
+        "factorial",
+        include_bytes!("../examples/factorial.bin"),
+        b"",
+    ),
+    (
+        "fibonacci",
+        include_bytes!("../examples/fibonacci.bin"),
+        b"",
+    ),
+    (
+        "guess",
+        include_bytes!("../examples/guess.bin"),
+        b"50 25 37",
+    ),
+    (
+        "hello_world",
+        include_bytes!("../examples/hello_world.bin"),
+        b"",
+    ),
+];
+
+fn run(program: &[u8], input: &[u8], cache: bool) -> usize {
+    let mut machine = Machine::new(program);
+    if !cache {
+        machine.disable_decode_cache();
+    }
+    let mut console = Console::new(input, Vec::new());
+    machine.run_on(&mut console).unwrap();
+    console.into_output().len()
+}
+
+fn run_threaded(program: &[u8], input: &[u8]) -> usize {
+    let mut machine = Machine::new(program);
+    let mut console = Console::new(input, Vec::new());
+    machine.run_threaded(&mut console).unwrap();
+    console.into_output().len()
+}
+
+fn examples(c: &mut Criterion) {
+    let mut group = c.benchmark_group("examples");
+    for &(name, program, input) in EXAMPLES {
+        group.bench_with_input(BenchmarkId::new("decode", name), &program, |b, program| {
+            b.iter(|| run(program, input, false))
+        });
+        group.bench_with_input(BenchmarkId::new("cached", name), &program, |b, program| {
+            b.iter(|| run(program, input, true))
+        });
+        group.bench_with_input(
+            BenchmarkId::new("threaded", name),
+            &program,
+            |b, program| b.iter(|| run_threaded(program, input)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, examples);
+criterion_main!(benches);
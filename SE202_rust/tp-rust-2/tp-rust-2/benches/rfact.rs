@@ -0,0 +1,36 @@
+//! Recursive factorial written with plain loads and stores (`rfact.bin`)
+//! against the same program using `push`, `pop`, `call` and `ret`
+//! (`rfact_stack.bin`).
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use interpreter::{Machine, MachineConfig, StackConfig};
+
+const RFACT: &[u8] = include_bytes!("../tests/rfact.bin");
+const RFACT_STACK: &[u8] = include_bytes!("../tests/rfact_stack.bin");
+
+fn run(code: &[u8], n: u32) -> u32 {
+    let config = MachineConfig {
+        stack: Some(StackConfig::default()),
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(code, config);
+    machine.set_reg(10, n).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    machine.regs()[11]
+}
+
+fn rfact(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rfact");
+    for n in [5, 12] {
+        group.bench_with_input(BenchmarkId::new("load-store", n), &n, |b, &n| {
+            b.iter(|| run(RFACT, n))
+        });
+        group.bench_with_input(BenchmarkId::new("push-pop", n), &n, |b, &n| {
+            b.iter(|| run(RFACT_STACK, n))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, rfact);
+criterion_main!(benches);
@@ -0,0 +1,121 @@
+//! Run arbitrary memory images and register states, with an arbitrary
+//! configuration, for a bounded number of steps. Every fault must come back
+//! as a `MachineError`: any panic is a bug.
+//!
+//! Run with `cargo fuzz run machine` from the crate directory. The
+//! `random_states` test of `tests/fuzz.rs` checks the same property with
+//! proptest.
+
+#![no_main]
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use interpreter::{
+    Console, Instruction, Isa, Machine, MachineConfig, Permissions, Protection, Region, StackConfig,
+};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_STEPS: u64 = 1000;
+
+#[derive(Debug)]
+struct State {
+    config: MachineConfig,
+    regs: Vec<u32>,
+    memory: Vec<u8>,
+    input: Vec<u8>,
+    traps: bool,
+    history: bool,
+}
+
+fn permissions(u: &mut Unstructured) -> Result<Permissions> {
+    Ok(Permissions::new(
+        u.arbitrary()?,
+        u.arbitrary()?,
+        u.arbitrary()?,
+    ))
+}
+
+impl<'a> Arbitrary<'a> for State {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let memory_size = u.int_in_range(0..=8192)?;
+        let nregs = u.int_in_range(1..=256)?;
+        let stack = if nregs > 1 && u.arbitrary()? {
+            Some(StackConfig {
+                sp: u.int_in_range(1..=nregs - 1)? as u8,
+                limit: u.arbitrary()?,
+            })
+        } else {
+            None
+        };
+        let protection = if u.arbitrary()? {
+            let mut regions = Vec::new();
+            for _ in 0..u.int_in_range(0..=4)? {
+                regions.push(Region {
+                    range: u.arbitrary()?..u.arbitrary()?,
+                    permissions: permissions(u)?,
+                });
+            }
+            Some(Protection {
+                default: permissions(u)?,
+                regions,
+                protect_image: u.arbitrary()?,
+            })
+        } else {
+            None
+        };
+        let config = MachineConfig {
+            memory_size,
+            nregs,
+            isa: if u.arbitrary()? {
+                Isa::Extended
+            } else {
+                Isa::Base
+            },
+            stack,
+            input: u.arbitrary()?,
+            traps: u.arbitrary()?,
+            syscalls: u.arbitrary()?,
+            protection,
+        };
+        let regs = u.arbitrary()?;
+        let input = u.arbitrary()?;
+        let (traps, history) = (u.arbitrary()?, u.arbitrary()?);
+        let mut memory = u.bytes(u.len())?.to_vec();
+        memory.truncate(memory_size);
+        Ok(State {
+            config,
+            regs,
+            memory,
+            input,
+            traps,
+            history,
+        })
+    }
+}
+
+fuzz_target!(|state: State| {
+    if let Ok((instruction, size)) = Instruction::decode(&state.memory) {
+        assert_eq!(&state.memory[..size], &instruction.encode()[..]);
+    }
+
+    let mut machine = Machine::with_config(&state.memory, state.config);
+    for (reg, &value) in state.regs.iter().enumerate() {
+        let _ = machine.set_reg(reg, value);
+    }
+    if state.history {
+        machine.enable_history(64);
+    }
+    let mut console = Console::new(&state.input[..], Vec::new());
+    if state.traps {
+        for _ in 0..MAX_STEPS {
+            match machine.step_with_traps(&mut console) {
+                Ok(false) => (),
+                Ok(true) | Err(_) => break,
+            }
+        }
+    } else {
+        let _ = machine.run_with_limit(&mut console, MAX_STEPS);
+    }
+    if state.history {
+        machine.run_back_until(|_| false);
+    }
+});
@@ -0,0 +1,742 @@
+use crate::{AluOp, Instruction, Object, Relocation, Width, MAX_SYMBOL_NAME};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::ops::Range;
+
+/// Result of assembling a source text: the memory image, the address of
+/// every label defined in the source, and the parts of the image following
+/// a `.data` directive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub code: Vec<u8>,
+    pub labels: BTreeMap<String, u32>,
+    pub data: Vec<Range<u32>>,
+    /// Labels exported with `.global`.
+    pub globals: BTreeSet<String>,
+    /// Every label reference made by a `loadimm` or `call` instruction.
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssemblerErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblerErrorKind {
+    Expected(&'static str),
+    UnknownMnemonic(String),
+    InvalidRegister(String),
+    ImmediateOutOfRange(i64),
+    InvalidNumber(String),
+    InvalidByteString,
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    LabelTooLong(String),
+    AddressMismatch { expected: u32, found: u32 },
+    TrailingCharacters,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl fmt::Display for AssemblerErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssemblerErrorKind::Expected(what) => write!(f, "expected {what}"),
+            AssemblerErrorKind::UnknownMnemonic(m) => write!(f, "unknown instruction `{m}`"),
+            AssemblerErrorKind::InvalidRegister(r) => write!(f, "invalid register `{r}`"),
+            AssemblerErrorKind::ImmediateOutOfRange(v) => {
+                write!(f, "immediate {v} does not fit in 16 bits")
+            }
+            AssemblerErrorKind::InvalidNumber(n) => write!(f, "invalid number `{n}`"),
+            AssemblerErrorKind::InvalidByteString => write!(f, "invalid byte string"),
+            AssemblerErrorKind::DuplicateLabel(l) => write!(f, "label `{l}` is already defined"),
+            AssemblerErrorKind::UndefinedLabel(l) => write!(f, "label `{l}` is not defined"),
+            AssemblerErrorKind::LabelTooLong(l) => {
+                write!(f, "label `{l}` is longer than {MAX_SYMBOL_NAME} bytes")
+            }
+            AssemblerErrorKind::AddressMismatch { expected, found } => write!(
+                f,
+                "address {found:04} does not match the current address {expected:04}"
+            ),
+            AssemblerErrorKind::TrailingCharacters => write!(f, "unexpected trailing characters"),
+        }
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+/// Assemble `source`, written in the syntax of the `.dis` listings, into a
+/// memory image suitable for [Machine::new](crate::Machine::new).
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    assemble_program(source).map(|program| program.code)
+}
+
+/// Same as [assemble], but also returns the address of every label.
+pub fn assemble_program(source: &str) -> Result<Program, AssemblerError> {
+    assemble_with(source, false)
+}
+
+/// Assemble `source` into an object for the [linker](crate::linker).
+/// Labels which are not defined in the source are left for the linker to
+/// resolve, and the ones declared with `.global` are visible from the other
+/// objects.
+pub fn assemble_object(source: &str) -> Result<Object, AssemblerError> {
+    assemble_with(source, true).map(|program| Object::from_program(&program))
+}
+
+/// Assemble `source`, references to undefined labels being assembled as 0
+/// when `relocatable` is set.
+fn assemble_with(source: &str, relocatable: bool) -> Result<Program, AssemblerError> {
+    // First pass: parse every line and assign addresses to labels.
+    let mut statements = Vec::new();
+    let mut labels = BTreeMap::new();
+    let mut address: u32 = 0;
+    let mut data = Vec::new();
+    let mut data_start = None;
+    let mut globals = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let mut cursor = Cursor::new(index + 1, text);
+        // `.data` and `.code` lines switch between data and code.
+        match cursor.directive()? {
+            Some(Directive::Global(names)) => {
+                globals.extend(
+                    names
+                        .into_iter()
+                        .map(|(name, column)| (name, index + 1, column)),
+                );
+                continue;
+            }
+            Some(Directive::Data) => {
+                data_start.get_or_insert(address);
+                continue;
+            }
+            Some(Directive::Code) => {
+                if let Some(start) = data_start.take() {
+                    data.push(start..address);
+                }
+                continue;
+            }
+            None => (),
+        }
+        for (label, column) in cursor.labels()? {
+            if labels.insert(label.clone(), address).is_some() {
+                return Err(cursor.error_at(column, AssemblerErrorKind::DuplicateLabel(label)));
+            }
+        }
+        if let Some(statement) = cursor.statement(address)? {
+            address += statement.size();
+            statements.push(statement);
+        }
+    }
+
+    // Second pass: resolve label references and emit the bytes.
+    let mut code = Vec::with_capacity(address as usize);
+    let mut relocations = Vec::new();
+    for statement in statements {
+        statement.emit(&labels, relocatable, &mut code, &mut relocations)?;
+    }
+    if let Some(start) = data_start {
+        data.push(start..address);
+    }
+    data.retain(|range| !range.is_empty());
+    if let Some((name, line, column)) = globals.iter().find(|(name, ..)| !labels.contains_key(name))
+    {
+        return Err(AssemblerError {
+            line: *line,
+            column: *column,
+            kind: AssemblerErrorKind::UndefinedLabel(name.clone()),
+        });
+    }
+    let globals = globals.into_iter().map(|(name, ..)| name).collect();
+    Ok(Program {
+        code,
+        labels,
+        data,
+        globals,
+        relocations,
+    })
+}
+
+enum Directive {
+    Code,
+    Data,
+    /// Labels to export, with their column.
+    Global(Vec<(String, usize)>),
+}
+
+/// Immediate operand of `loadimm` or `call`, either a literal or a label
+/// reference.
+enum Immediate {
+    Value(i64),
+    Label(String),
+}
+
+struct Statement {
+    line: usize,
+    column: usize,
+    kind: StatementKind,
+}
+
+enum StatementKind {
+    Bytes(Vec<u8>),
+    Instruction(Instruction),
+    LoadImm { reg: u8, value: Immediate },
+    Call { target: Immediate },
+}
+
+impl Statement {
+    fn size(&self) -> u32 {
+        match &self.kind {
+            StatementKind::Bytes(bytes) => bytes.len() as u32,
+            StatementKind::Instruction(instruction) => instruction.size() as u32,
+            StatementKind::LoadImm { .. } => 4,
+            StatementKind::Call { .. } => 3,
+        }
+    }
+
+    /// Append the bytes of the statement to `code`, and its label
+    /// reference to `relocations`.
+    fn emit(
+        self,
+        labels: &BTreeMap<String, u32>,
+        relocatable: bool,
+        code: &mut Vec<u8>,
+        relocations: &mut Vec<Relocation>,
+    ) -> Result<(), AssemblerError> {
+        let address = code.len() as u32;
+        let mut resolve = |immediate: Immediate, min, max_address| {
+            if let Immediate::Label(symbol) = &immediate {
+                relocations.push(Relocation {
+                    address,
+                    symbol: symbol.clone(),
+                });
+                if relocatable && !labels.contains_key(symbol) {
+                    return Ok(0);
+                }
+            }
+            immediate
+                .resolve(labels, min, max_address)
+                .map_err(|kind| AssemblerError {
+                    line: self.line,
+                    column: self.column,
+                    kind,
+                })
+        };
+        match self.kind {
+            StatementKind::Bytes(bytes) => code.extend(bytes),
+            StatementKind::Instruction(instruction) => code.extend(instruction.encode()),
+            StatementKind::LoadImm { reg, value } => {
+                // The immediate is sign-extended, addresses must stay positive.
+                let value = resolve(value, -0x8000, 0x7fff)? as i16;
+                code.extend(Instruction::LoadImm { reg, value }.encode());
+            }
+            StatementKind::Call { target } => {
+                let target = resolve(target, 0, 0xffff)?;
+                code.extend(Instruction::Call { target }.encode());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Immediate {
+    /// Value of the immediate, which must be between `min` and 0xffff, or
+    /// at most `max_address` for a label.
+    fn resolve(
+        self,
+        labels: &BTreeMap<String, u32>,
+        min: i64,
+        max_address: i64,
+    ) -> Result<u16, AssemblerErrorKind> {
+        let (value, max) = match self {
+            Immediate::Value(v) => (v, 0xffff),
+            Immediate::Label(label) => match labels.get(&label) {
+                Some(&address) => (address as i64, max_address),
+                None => return Err(AssemblerErrorKind::UndefinedLabel(label)),
+            },
+        };
+        if !(min..=max).contains(&value) {
+            return Err(AssemblerErrorKind::ImmediateOutOfRange(value));
+        }
+        Ok(value as u16)
+    }
+}
+
+/// Character cursor over a single source line. Columns are 1-based and
+/// counted in characters.
+struct Cursor<'a> {
+    line: usize,
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(line: usize, text: &'a str) -> Self {
+        // Everything after a `;` is a comment, unless it is inside a byte string.
+        let mut end = text.len();
+        let mut quote = None;
+        let mut escaped = false;
+        for (i, c) in text.char_indices() {
+            match quote {
+                Some(q) if !escaped && c == q => quote = None,
+                Some(_) => escaped = !escaped && c == '\\',
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None if c == ';' => {
+                    end = i;
+                    break;
+                }
+                None => (),
+            }
+        }
+        Cursor {
+            line,
+            text: &text[..end],
+            pos: 0,
+        }
+    }
+
+    fn column(&self) -> usize {
+        self.text[..self.pos].chars().count() + 1
+    }
+
+    fn error(&self, kind: AssemblerErrorKind) -> AssemblerError {
+        self.error_at(self.column(), kind)
+    }
+
+    fn error_at(&self, column: usize, kind: AssemblerErrorKind) -> AssemblerError {
+        AssemblerError {
+            line: self.line,
+            column,
+            kind,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.rest().is_empty()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &'static str) -> Result<(), AssemblerError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(AssemblerErrorKind::Expected(token)))
+        }
+    }
+
+    /// Consume characters while `pred` holds and return them.
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| !pred(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                Some(self.take_while(|c| c.is_ascii_alphanumeric() || c == '_'))
+            }
+            _ => None,
+        }
+    }
+
+    /// Check that the label `name` found at `column` fits in an object file.
+    fn label(&self, name: &str, column: usize) -> Result<String, AssemblerError> {
+        if name.len() > MAX_SYMBOL_NAME {
+            return Err(self.error_at(column, AssemblerErrorKind::LabelTooLong(name.to_string())));
+        }
+        Ok(name.to_string())
+    }
+
+    /// Parse a line made of a `.code`, `.data` or `.global` directive.
+    fn directive(&mut self) -> Result<Option<Directive>, AssemblerError> {
+        if !self.eat(".") {
+            return Ok(None);
+        }
+        let directive = match self.identifier() {
+            Some("code") => Directive::Code,
+            Some("data") => Directive::Data,
+            Some("global") => {
+                let mut names = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    let column = self.column();
+                    let name = self
+                        .identifier()
+                        .ok_or_else(|| self.error(AssemblerErrorKind::Expected("label")))?;
+                    names.push((self.label(name, column)?, column));
+                    if !self.eat(",") {
+                        break;
+                    }
+                }
+                Directive::Global(names)
+            }
+            _ => {
+                return Err(self.error(AssemblerErrorKind::Expected(
+                    "`.code`, `.data` or `.global`",
+                )))
+            }
+        };
+        if !self.at_end() {
+            return Err(self.error(AssemblerErrorKind::TrailingCharacters));
+        }
+        Ok(Some(directive))
+    }
+
+    /// Parse the `label:` definitions found at the beginning of the line.
+    fn labels(&mut self) -> Result<Vec<(String, usize)>, AssemblerError> {
+        let mut labels = Vec::new();
+        loop {
+            let start = self.pos;
+            self.skip_whitespace();
+            let column = self.column();
+            match self.identifier() {
+                Some(name) if self.eat(":") => labels.push((self.label(name, column)?, column)),
+                _ => {
+                    self.pos = start;
+                    return Ok(labels);
+                }
+            }
+        }
+    }
+
+    /// Parse the (optional) instruction or data found on the rest of the line.
+    /// A leading address, as found in the listings, must match `address`.
+    fn statement(&mut self, address: u32) -> Result<Option<Statement>, AssemblerError> {
+        if self.at_end() {
+            return Ok(None);
+        }
+        let column = self.column();
+        if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            let digits = self.take_while(|c| c.is_ascii_digit());
+            let found = digits.parse().map_err(|_| {
+                self.error_at(
+                    column,
+                    AssemblerErrorKind::InvalidNumber(digits.to_string()),
+                )
+            })?;
+            if found != address {
+                return Err(self.error_at(
+                    column,
+                    AssemblerErrorKind::AddressMismatch {
+                        expected: address,
+                        found,
+                    },
+                ));
+            }
+        } else {
+            self.eat("????");
+        }
+        if self.at_end() {
+            return Err(self.error(AssemblerErrorKind::Expected("instruction or data")));
+        }
+
+        let column = self.column();
+        let kind = match self.peek() {
+            Some('[') => StatementKind::Bytes(self.byte_list()?),
+            Some('b') if matches!(self.rest()[1..].chars().next(), Some('\'' | '"')) => {
+                StatementKind::Bytes(self.byte_string()?)
+            }
+            _ => self.instruction()?,
+        };
+        if !self.at_end() {
+            return Err(self.error(AssemblerErrorKind::TrailingCharacters));
+        }
+        Ok(Some(Statement {
+            line: self.line,
+            column,
+            kind,
+        }))
+    }
+
+    fn instruction(&mut self) -> Result<StatementKind, AssemblerError> {
+        let column = self.column();
+        let mnemonic = self
+            .identifier()
+            .ok_or_else(|| self.error(AssemblerErrorKind::Expected("instruction")))?;
+        let instruction = match mnemonic {
+            "move" => {
+                let a = self.register()?;
+                self.expect("<-")?;
+                let b = self.register()?;
+                self.expect("if")?;
+                let c = self.register()?;
+                self.expect("!=")?;
+                self.expect("0")?;
+                Instruction::MoveIf { a, b, c }
+            }
+            "store" => {
+                self.expect("[")?;
+                let a = self.register()?;
+                self.expect("]")?;
+                self.expect("<-")?;
+                let b = self.register()?;
+                Instruction::Store { a, b }
+            }
+            "load" => {
+                let a = self.register()?;
+                self.expect("<-")?;
+                self.expect("[")?;
+                let b = self.register()?;
+                self.expect("]")?;
+                Instruction::Load { a, b }
+            }
+            "loadimm" => {
+                let reg = self.register()?;
+                self.expect("<-")?;
+                let value = self.immediate()?;
+                return Ok(StatementKind::LoadImm { reg, value });
+            }
+            "sub" => {
+                let a = self.register()?;
+                self.expect("<-")?;
+                let b = self.register()?;
+                self.expect("-")?;
+                let c = self.register()?;
+                Instruction::Sub { a, b, c }
+            }
+            "out" => Instruction::Out {
+                reg: self.register()?,
+            },
+            "exit" => Instruction::Exit,
+            "out_number" => Instruction::OutNumber {
+                reg: self.register()?,
+            },
+            "in" => Instruction::In {
+                reg: self.register()?,
+            },
+            "in_number" => Instruction::InNumber {
+                reg: self.register()?,
+            },
+            "push" => Instruction::Push {
+                reg: self.register()?,
+            },
+            "pop" => Instruction::Pop {
+                reg: self.register()?,
+            },
+            "call" => {
+                let target = self.immediate()?;
+                return Ok(StatementKind::Call { target });
+            }
+            "ret" => Instruction::Ret,
+            "getctl" => {
+                let a = self.register()?;
+                self.expect("<-")?;
+                let c = self.control_register()?;
+                Instruction::GetCtl { a, c }
+            }
+            "setctl" => {
+                let c = self.control_register()?;
+                self.expect("<-")?;
+                let a = self.register()?;
+                Instruction::SetCtl { c, a }
+            }
+            "iret" => Instruction::Iret,
+            "syscall" => Instruction::Syscall,
+            "store8" | "store16" => {
+                self.expect("[")?;
+                let a = self.register()?;
+                self.expect("]")?;
+                self.expect("<-")?;
+                let b = self.register()?;
+                let width = if mnemonic == "store8" {
+                    Width::Byte
+                } else {
+                    Width::Half
+                };
+                Instruction::StoreNarrow { width, a, b }
+            }
+            "load8" | "load8s" | "load16" | "load16s" => {
+                let a = self.register()?;
+                self.expect("<-")?;
+                self.expect("[")?;
+                let b = self.register()?;
+                self.expect("]")?;
+                Instruction::LoadNarrow {
+                    width: if mnemonic.starts_with("load8") {
+                        Width::Byte
+                    } else {
+                        Width::Half
+                    },
+                    signed: mnemonic.ends_with('s'),
+                    a,
+                    b,
+                }
+            }
+            _ => {
+                let Some(op) = AluOp::from_mnemonic(mnemonic) else {
+                    return Err(self.error_at(
+                        column,
+                        AssemblerErrorKind::UnknownMnemonic(mnemonic.to_string()),
+                    ));
+                };
+                let a = self.register()?;
+                self.expect("<-")?;
+                let b = self.register()?;
+                self.expect(op.operator())?;
+                let c = self.register()?;
+                Instruction::Alu { op, a, b, c }
+            }
+        };
+        Ok(StatementKind::Instruction(instruction))
+    }
+
+    fn register(&mut self) -> Result<u8, AssemblerError> {
+        self.prefixed_register('r', "register")
+    }
+
+    fn control_register(&mut self) -> Result<u8, AssemblerError> {
+        self.prefixed_register('c', "control register")
+    }
+
+    /// Parse a register name made of `prefix` and a number.
+    fn prefixed_register(
+        &mut self,
+        prefix: char,
+        what: &'static str,
+    ) -> Result<u8, AssemblerError> {
+        self.skip_whitespace();
+        let column = self.column();
+        let name = self
+            .identifier()
+            .ok_or_else(|| self.error(AssemblerErrorKind::Expected(what)))?;
+        name.strip_prefix(prefix)
+            .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| {
+                self.error_at(
+                    column,
+                    AssemblerErrorKind::InvalidRegister(name.to_string()),
+                )
+            })
+    }
+
+    fn immediate(&mut self) -> Result<Immediate, AssemblerError> {
+        self.expect("#")?;
+        self.skip_whitespace();
+        let column = self.column();
+        if let Some(label) = self.identifier() {
+            return self.label(label, column).map(Immediate::Label);
+        }
+        Ok(Immediate::Value(self.number()?))
+    }
+
+    /// Parse a decimal or `0x`-prefixed hexadecimal number, possibly negative.
+    fn number(&mut self) -> Result<i64, AssemblerError> {
+        let column = self.column();
+        let negative = self.eat("-");
+        let text = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        let value = match text.strip_prefix("0x") {
+            Some(hex) => i64::from_str_radix(hex, 16),
+            None => text.parse(),
+        };
+        match value {
+            Ok(v) if negative => Ok(-v),
+            Ok(v) => Ok(v),
+            Err(_) if text.is_empty() => {
+                Err(self.error_at(column, AssemblerErrorKind::Expected("number")))
+            }
+            Err(_) => {
+                Err(self.error_at(column, AssemblerErrorKind::InvalidNumber(text.to_string())))
+            }
+        }
+    }
+
+    /// Parse a list of bytes such as `[0, 0, 0, 0]`.
+    fn byte_list(&mut self) -> Result<Vec<u8>, AssemblerError> {
+        self.expect("[")?;
+        let mut bytes = Vec::new();
+        if self.eat("]") {
+            return Ok(bytes);
+        }
+        loop {
+            self.skip_whitespace();
+            let column = self.column();
+            let value = self.number()?;
+            let byte = u8::try_from(value).map_err(|_| {
+                self.error_at(column, AssemblerErrorKind::InvalidNumber(value.to_string()))
+            })?;
+            bytes.push(byte);
+            if self.eat("]") {
+                return Ok(bytes);
+            }
+            self.expect(",")?;
+        }
+    }
+
+    /// Parse a Python-style byte string such as `b'Hello\n'`.
+    fn byte_string(&mut self) -> Result<Vec<u8>, AssemblerError> {
+        let column = self.column();
+        let invalid =
+            |cursor: &Self| cursor.error_at(column, AssemblerErrorKind::InvalidByteString);
+        self.pos += 1;
+        let quote = self.peek().ok_or_else(|| invalid(self))?;
+        self.pos += 1;
+        let mut bytes = Vec::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                c if c == quote => {
+                    self.pos += i + 1;
+                    return Ok(bytes);
+                }
+                '\\' => {
+                    let escaped = match chars.next() {
+                        Some((_, 'n')) => b'\n',
+                        Some((_, 't')) => b'\t',
+                        Some((_, 'r')) => b'\r',
+                        Some((_, '0')) => 0,
+                        Some((_, '\\')) => b'\\',
+                        Some((_, '\'')) => b'\'',
+                        Some((_, '"')) => b'"',
+                        Some((_, 'x')) => {
+                            let hex: String = chars.by_ref().take(2).map(|(_, c)| c).collect();
+                            u8::from_str_radix(&hex, 16).map_err(|_| invalid(self))?
+                        }
+                        _ => return Err(invalid(self)),
+                    };
+                    bytes.push(escaped);
+                }
+                c if c.is_ascii() => bytes.push(c as u8),
+                _ => return Err(invalid(self)),
+            }
+        }
+        Err(invalid(self))
+    }
+}
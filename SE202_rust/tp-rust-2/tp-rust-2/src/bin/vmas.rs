@@ -0,0 +1,36 @@
+use interpreter::assembler::{assemble, assemble_object};
+use std::process::exit;
+
+fn main() {
+    // Assemble a `.dis` listing into an object file, or into a raw memory
+    // image with --raw.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (raw, source, output) = match &args[..] {
+        [source, output] => (false, source, output),
+        [option, source, output] if option == "--raw" => (true, source, output),
+        _ => {
+            eprintln!("usage: vmas [--raw] <program.dis> <output>");
+            exit(2);
+        }
+    };
+    // Undefined labels are left to the linker in objects.
+    let bytes = std::fs::read_to_string(source)
+        .map_err(|e| e.to_string())
+        .and_then(|text| {
+            if raw {
+                assemble(&text).map_err(|e| e.to_string())
+            } else {
+                assemble_object(&text)
+                    .map_err(|e| e.to_string())
+                    .and_then(|object| object.to_bytes().map_err(|e| e.to_string()))
+            }
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("{source}: {e}");
+            exit(1);
+        });
+    if let Err(e) = std::fs::write(output, bytes) {
+        eprintln!("{output}: {e}");
+        exit(1);
+    }
+}
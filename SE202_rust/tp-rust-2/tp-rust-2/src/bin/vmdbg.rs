@@ -0,0 +1,70 @@
+use interpreter::assembler::assemble_program;
+use interpreter::debugger::Debugger;
+use interpreter::options::{labels, MachineOptions, USAGE as MACHINE_USAGE};
+use interpreter::{Machine, MachineConfig, Object};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::process::exit;
+
+const USAGE: &str = "\
+usage: vmdbg [OPTIONS] program.bin|program.obj|program.dis < commands
+options:";
+
+/// Load a program from a `.dis` listing, an object file or a binary image.
+/// For a binary image, labels are taken from the listing next to it when it
+/// matches.
+fn load(filename: &str, config: MachineConfig) -> Result<(Machine, BTreeMap<String, u32>), String> {
+    let path = Path::new(filename);
+    let error = |e: &dyn std::fmt::Display| format!("{filename}: {e}");
+    if path.extension().is_some_and(|ext| ext == "dis") {
+        let source = std::fs::read_to_string(path).map_err(|e| error(&e))?;
+        let program = assemble_program(&source).map_err(|e| error(&e))?;
+        let machine =
+            Machine::load_object(&Object::from_program(&program), config).map_err(|e| error(&e))?;
+        return Ok((machine, program.labels));
+    }
+    let code = std::fs::read(path).map_err(|e| error(&e))?;
+    let machine = Machine::from_object_with_config(&code, config).map_err(|e| error(&e))?;
+    let labels = labels(path, &code, &machine);
+    Ok((machine, labels))
+}
+
+fn parse_args() -> Result<(MachineOptions, String), String> {
+    let mut args = std::env::args().skip(1);
+    let mut options = MachineOptions::default();
+    let mut filename = None;
+    while let Some(arg) = args.next() {
+        if options.parse(&arg, &mut args)? {
+            continue;
+        }
+        match arg {
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+            _ if filename.is_none() => filename = Some(arg),
+            _ => return Err("only one program can be given".to_string()),
+        }
+    }
+    let filename = filename.ok_or("a program must be given")?;
+    Ok((options, filename))
+}
+
+fn main() {
+    // Take a filename and the machine options as arguments on the command
+    // line, commands are read from standard input.
+    let (options, filename) = parse_args().unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}\n{MACHINE_USAGE}");
+        exit(2);
+    });
+    let (mut machine, labels) = load(&filename, options.config()).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1);
+    });
+    options.attach(&mut machine);
+    let mut debugger = Debugger::new(machine, labels);
+    let stdin = io::stdin();
+    let prompt = stdin.is_terminal();
+    if let Err(e) = debugger.repl(stdin.lock(), &mut io::stdout().lock(), prompt) {
+        eprintln!("{e}");
+        exit(1);
+    }
+}
@@ -0,0 +1,56 @@
+use interpreter::linker::link;
+use interpreter::{MachineConfig, Object};
+use std::process::exit;
+
+const USAGE: &str = "usage: vmld -o output.obj [-m output.map] input.obj...";
+
+fn main() {
+    // Link object files into a program, optionally writing a map file.
+    let mut args = std::env::args().skip(1);
+    let mut output = None;
+    let mut map = None;
+    let mut inputs = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            "-m" => map = args.next(),
+            _ => inputs.push(arg),
+        }
+    }
+    let Some(output) = output.filter(|_| !inputs.is_empty()) else {
+        eprintln!("{USAGE}");
+        exit(2);
+    };
+
+    let objects = inputs
+        .into_iter()
+        .map(|name| {
+            let object = std::fs::read(&name)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| Object::from_bytes(&bytes).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| {
+                    eprintln!("{name}: {e}");
+                    exit(1);
+                });
+            (name, object)
+        })
+        .collect::<Vec<_>>();
+    let linked = link(&objects, MachineConfig::default().memory_size).unwrap_or_else(|e| {
+        eprintln!("{e}");
+        exit(1);
+    });
+    let write = |path: &str, bytes: &[u8]| {
+        if let Err(e) = std::fs::write(path, bytes) {
+            eprintln!("{path}: {e}");
+            exit(1);
+        }
+    };
+    let bytes = linked.object.to_bytes().unwrap_or_else(|e| {
+        eprintln!("{output}: {e}");
+        exit(1);
+    });
+    write(&output, &bytes);
+    if let Some(map) = map {
+        write(&map, linked.map().as_bytes());
+    }
+}
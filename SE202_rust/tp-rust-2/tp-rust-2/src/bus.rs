@@ -0,0 +1,150 @@
+use crate::{IoDevice, MachineError};
+
+/// Default address of the [Devices], close to the end of the default
+/// 4096-byte memory.
+pub const DEVICE_BASE: u32 = 0xff0;
+
+/// Offset of the console port from the device base. Storing writes the low
+/// byte to the output as is, loading reads one byte of input, or -1 at the
+/// end of the input.
+pub const CONSOLE_PORT: u32 = 0;
+/// Offset of the cycle counter from the device base. Loading gives the
+/// number of steps executed since the devices were attached, storing sets
+/// the counter.
+pub const CYCLE_COUNTER: u32 = 4;
+/// Offset of the random source from the device base. Loading gives a
+/// pseudo-random number, storing sets the seed.
+pub const RANDOM_SOURCE: u32 = 8;
+
+/// Devices mapped in the address space of a [Machine](crate::Machine).
+///
+/// When a bus is attached, loads and stores touching an address it
+/// [maps](Bus::maps) are sent to it instead of the memory. Input and output
+/// go through the [IoDevice] the machine is run on.
+pub trait Bus {
+    /// Whether any of the `width` bytes starting at `addr` belong to a
+    /// device.
+    fn maps(&self, addr: u32, width: usize) -> bool;
+
+    /// Read `width` bytes at `addr`, in the low bits of the result.
+    fn load(&mut self, addr: u32, width: usize, io: &mut dyn IoDevice)
+        -> Result<u32, MachineError>;
+
+    /// Write the `width` low bytes of `value` at `addr`.
+    fn store(
+        &mut self,
+        addr: u32,
+        width: usize,
+        value: u32,
+        io: &mut dyn IoDevice,
+    ) -> Result<(), MachineError>;
+
+    /// Called after every executed step.
+    fn tick(&mut self) {}
+}
+
+/// The standard devices: a console port, a cycle counter and a random
+/// source, each one a 32-bit word starting at `base` (see [CONSOLE_PORT],
+/// [CYCLE_COUNTER] and [RANDOM_SOURCE]). A device must be accessed at its
+/// first byte.
+///
+/// Programs using the devices at [DEVICE_BASE] must keep their stack below
+/// it.
+pub struct Devices {
+    base: u32,
+    cycles: u32,
+    random: u32,
+}
+
+/// Seed of the random source, used until the program stores its own.
+const DEFAULT_SEED: u32 = 0x2545_f491;
+
+impl Devices {
+    pub fn new(base: u32) -> Self {
+        Devices {
+            base,
+            cycles: 0,
+            random: DEFAULT_SEED,
+        }
+    }
+
+    /// Offset of the device accessed at `addr`.
+    fn port(&self, addr: u32, width: usize) -> Result<u32, MachineError> {
+        let offset = addr.wrapping_sub(self.base);
+        if !offset.is_multiple_of(4) || offset > RANDOM_SOURCE || width > 4 {
+            return Err(MachineError::InvalidDeviceAccess {
+                addr,
+                width,
+                site: None,
+            });
+        }
+        Ok(offset)
+    }
+}
+
+impl Default for Devices {
+    fn default() -> Self {
+        Devices::new(DEVICE_BASE)
+    }
+}
+
+impl Bus for Devices {
+    fn maps(&self, addr: u32, width: usize) -> bool {
+        let end = addr as u64 + width as u64;
+        (addr as u64) < self.base as u64 + 12 && end > self.base as u64
+    }
+
+    fn load(
+        &mut self,
+        addr: u32,
+        width: usize,
+        io: &mut dyn IoDevice,
+    ) -> Result<u32, MachineError> {
+        let value = match self.port(addr, width)? {
+            CONSOLE_PORT => {
+                let byte = io.read_input()?;
+                byte.map_or(u32::MAX, u32::from)
+            }
+            CYCLE_COUNTER => self.cycles,
+            _ => {
+                // xorshift32
+                let mut x = self.random;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.random = x;
+                x
+            }
+        };
+        Ok(truncate(value, width))
+    }
+
+    fn store(
+        &mut self,
+        addr: u32,
+        width: usize,
+        value: u32,
+        io: &mut dyn IoDevice,
+    ) -> Result<(), MachineError> {
+        let value = truncate(value, width);
+        match self.port(addr, width)? {
+            CONSOLE_PORT => io.write_output(&[value as u8])?,
+            CYCLE_COUNTER => self.cycles = value,
+            _ => self.random = if value == 0 { DEFAULT_SEED } else { value },
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        self.cycles = self.cycles.wrapping_add(1);
+    }
+}
+
+/// Keep the `width` low bytes of `value`.
+fn truncate(value: u32, width: usize) -> u32 {
+    if width >= 4 {
+        value
+    } else {
+        value & ((1 << (8 * width)) - 1)
+    }
+}
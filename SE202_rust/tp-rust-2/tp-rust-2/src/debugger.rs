@@ -0,0 +1,489 @@
+use crate::{Console, Instruction, Machine};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+commands:
+  step [n]              execute n instructions (default 1)
+  continue              run until a breakpoint, a watchpoint, exit or an error
+  back [n]              undo the last n executed instructions (default 1)
+  rcontinue             run backwards until a breakpoint or a watchpoint
+  break <loc>           set a breakpoint on an address or a label
+  delete <loc>          remove a breakpoint
+  watch <loc> [len]     stop when memory in [loc, loc+len) changes (default len 4)
+  unwatch <loc>         remove the watchpoint starting at loc
+  info                  list breakpoints and watchpoints
+  regs                  dump the registers
+  mem <loc> [len]       dump memory (default len 64)
+  set r<n> <value>      set a register
+  input <text>          queue a line of input for the program
+  disas [loc] [count]   disassemble (default: 5 instructions from IP)
+  help                  show this help
+  quit                  leave the debugger
+locations are decimal or 0x-prefixed hexadecimal addresses, or labels";
+
+/// Number of executed instructions which can be undone.
+const HISTORY_CAPACITY: usize = 100_000;
+
+/// State of the debugged program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Running,
+    Exited,
+    Faulted,
+}
+
+/// Why a `step` or `continue` command gave control back to the user.
+enum Stop {
+    Steps,
+    Breakpoint,
+    Watchpoint { start: u32, old: Vec<u8> },
+    Exited,
+    Faulted,
+    HistoryStart,
+}
+
+/// Command-driven debugger for a [Machine]. Commands can come from a
+/// terminal or from a script, see [Debugger::repl].
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<u32>,
+    watchpoints: BTreeMap<u32, u32>,
+    // Input not consumed yet by the program.
+    input: VecDeque<u8>,
+    status: Status,
+}
+
+impl Debugger {
+    /// Debug `machine`. `labels` become the symbols of the machine, they
+    /// are used to name addresses and can be used in place of addresses in
+    /// commands. The undo log of the machine is enabled so that execution
+    /// can go backwards.
+    pub fn new(mut machine: Machine, labels: BTreeMap<String, u32>) -> Self {
+        machine.enable_history(HISTORY_CAPACITY);
+        machine.set_symbols(labels);
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            input: VecDeque::new(),
+            status: Status::Running,
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Read commands from `input` until it is exhausted or a `quit` command
+    /// is found. Both debugger messages and program output go to `out`.
+    /// When `prompt` is set, a prompt is printed before reading each command.
+    pub fn repl<R: BufRead, W: Write>(
+        &mut self,
+        input: R,
+        out: &mut W,
+        prompt: bool,
+    ) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            if prompt {
+                write!(out, "(vmdbg) ")?;
+                out.flush()?;
+            }
+            let Some(line) = lines.next() else {
+                return Ok(());
+            };
+            if !self.command(&line?, out)? {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Execute a single command. Returns `false` if the debugger must quit.
+    pub fn command<W: Write>(&mut self, line: &str, out: &mut W) -> io::Result<bool> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let Some((&name, args)) = args.split_first() else {
+            return Ok(true);
+        };
+        let result = match name {
+            "step" | "s" => self.step(args, out),
+            "continue" | "c" => self.cont(out),
+            "back" => self.back(args, out),
+            "rcontinue" | "rc" => self.rcont(out),
+            "break" | "b" => self.set_breakpoint(args, out),
+            "delete" | "d" => self.delete_breakpoint(args, out),
+            "watch" | "w" => self.set_watchpoint(args, out),
+            "unwatch" => self.delete_watchpoint(args, out),
+            "info" | "i" => self.info(out),
+            "regs" | "r" => self.dump_regs(out),
+            "mem" | "x" => self.dump_memory(args, out),
+            "set" => self.set_register(args, out),
+            "input" => self.queue_input(args),
+            "disas" => self.disassemble(args, out),
+            "help" | "h" => writeln!(out, "{HELP}").map_err(Error::Io),
+            "quit" | "q" => return Ok(false),
+            _ => Err(Error::Usage(format!(
+                "unknown command `{name}`, try `help`"
+            ))),
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(Error::Usage(message)) => {
+                writeln!(out, "error: {message}")?;
+                Ok(true)
+            }
+            Err(Error::Io(e)) => Err(e),
+        }
+    }
+
+    fn step<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let count = match args {
+            [] => 1,
+            [n] => n
+                .parse()
+                .map_err(|_| Error::Usage(format!("invalid count `{n}`")))?,
+            _ => return Err(usage("step [n]")),
+        };
+        let stop = self.execute(out, Some(count))?;
+        self.report(stop, out)
+    }
+
+    fn cont<W: Write>(&mut self, out: &mut W) -> Result<(), Error> {
+        let stop = self.execute(out, None)?;
+        self.report(stop, out)
+    }
+
+    fn back<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let count = match args {
+            [] => 1,
+            [n] => n
+                .parse()
+                .map_err(|_| Error::Usage(format!("invalid count `{n}`")))?,
+            _ => return Err(usage("back [n]")),
+        };
+        let stop = self.reverse(Some(count));
+        self.report(stop, out)
+    }
+
+    fn rcont<W: Write>(&mut self, out: &mut W) -> Result<(), Error> {
+        let stop = self.reverse(None);
+        self.report(stop, out)
+    }
+
+    /// Undo up to `count` instructions, or until a breakpoint is reached or
+    /// a watched range changes if `count` is `None`.
+    fn reverse(&mut self, count: Option<u64>) -> Stop {
+        let mut undone = 0;
+        loop {
+            if count.is_some_and(|count| undone >= count) {
+                return Stop::Steps;
+            }
+            let watched: Vec<(u32, Vec<u8>)> = self
+                .watchpoints
+                .iter()
+                .map(|(&start, &len)| (start, self.memory_range(start, len).to_vec()))
+                .collect();
+            if !self.machine.step_back() {
+                return Stop::HistoryStart;
+            }
+            self.status = Status::Running;
+            undone += 1;
+            for (start, old) in watched {
+                if self.memory_range(start, old.len() as u32) != old {
+                    return Stop::Watchpoint { start, old };
+                }
+            }
+            if self.breakpoints.contains(&self.machine.regs()[0]) {
+                return Stop::Breakpoint;
+            }
+        }
+    }
+
+    /// Execute up to `count` instructions, or until something interesting
+    /// happens if `count` is `None`.
+    fn execute<W: Write>(&mut self, out: &mut W, count: Option<u64>) -> Result<Stop, Error> {
+        if self.status != Status::Running {
+            return Err(Error::Usage("the program is not running".to_string()));
+        }
+        let mut executed = 0;
+        loop {
+            if count.is_some_and(|count| executed >= count) {
+                return Ok(Stop::Steps);
+            }
+            let watched: Vec<(u32, Vec<u8>)> = self
+                .watchpoints
+                .iter()
+                .map(|(&start, &len)| (start, self.memory_range(start, len).to_vec()))
+                .collect();
+            match self
+                .machine
+                .step_on(&mut Console::new(&mut self.input, &mut *out))
+            {
+                Ok(false) => (),
+                Ok(true) => {
+                    self.status = Status::Exited;
+                    return Ok(Stop::Exited);
+                }
+                Err(e) => {
+                    self.status = Status::Faulted;
+                    writeln!(out, "machine error: {e}")?;
+                    return Ok(Stop::Faulted);
+                }
+            }
+            executed += 1;
+            for (start, old) in watched {
+                if self.memory_range(start, old.len() as u32) != old {
+                    return Ok(Stop::Watchpoint { start, old });
+                }
+            }
+            if self.breakpoints.contains(&self.machine.regs()[0]) {
+                return Ok(Stop::Breakpoint);
+            }
+        }
+    }
+
+    fn report<W: Write>(&self, stop: Stop, out: &mut W) -> Result<(), Error> {
+        match stop {
+            Stop::Steps => (),
+            Stop::Breakpoint => writeln!(out, "breakpoint hit")?,
+            Stop::Watchpoint { start, old } => {
+                let new = self.memory_range(start, old.len() as u32);
+                writeln!(out, "watchpoint {} changed", self.describe(start))?;
+                writeln!(out, "  old: {}", hex_bytes(&old))?;
+                writeln!(out, "  new: {}", hex_bytes(new))?;
+            }
+            Stop::Exited => {
+                writeln!(out, "program exited")?;
+                return Ok(());
+            }
+            Stop::Faulted => return Ok(()),
+            Stop::HistoryStart => writeln!(out, "reached the start of the recorded history")?,
+        }
+        let ip = self.machine.regs()[0];
+        write!(out, "stopped at {}: ", self.describe_code(ip))?;
+        match self.decode_at(ip) {
+            Some(instruction) => writeln!(out, "{instruction}")?,
+            None => writeln!(out, "<invalid instruction>")?,
+        }
+        Ok(())
+    }
+
+    fn set_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let [location] = args else {
+            return Err(usage("break <loc>"));
+        };
+        let address = self.location(location)?;
+        self.breakpoints.insert(address);
+        writeln!(out, "breakpoint at {}", self.describe(address))?;
+        Ok(())
+    }
+
+    fn delete_breakpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let [location] = args else {
+            return Err(usage("delete <loc>"));
+        };
+        let address = self.location(location)?;
+        if !self.breakpoints.remove(&address) {
+            return Err(Error::Usage(format!(
+                "no breakpoint at {}",
+                self.describe(address)
+            )));
+        }
+        writeln!(out, "deleted breakpoint at {}", self.describe(address))?;
+        Ok(())
+    }
+
+    fn set_watchpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let (start, len) = match args {
+            [location] => (self.location(location)?, 4),
+            [location, len] => (self.location(location)?, self.number(len)?),
+            _ => return Err(usage("watch <loc> [len]")),
+        };
+        let memory_size = self.machine.memory().len() as u64;
+        if len == 0 || start as u64 + len as u64 > memory_size {
+            return Err(Error::Usage("watched range is outside memory".to_string()));
+        }
+        self.watchpoints.insert(start, len);
+        writeln!(out, "watchpoint on {} ({len} bytes)", self.describe(start))?;
+        Ok(())
+    }
+
+    fn delete_watchpoint<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let [location] = args else {
+            return Err(usage("unwatch <loc>"));
+        };
+        let start = self.location(location)?;
+        if self.watchpoints.remove(&start).is_none() {
+            return Err(Error::Usage(format!(
+                "no watchpoint at {}",
+                self.describe(start)
+            )));
+        }
+        writeln!(out, "deleted watchpoint at {}", self.describe(start))?;
+        Ok(())
+    }
+
+    fn info<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        for &address in &self.breakpoints {
+            writeln!(out, "breakpoint at {}", self.describe(address))?;
+        }
+        for (&start, &len) in &self.watchpoints {
+            writeln!(out, "watchpoint on {} ({len} bytes)", self.describe(start))?;
+        }
+        Ok(())
+    }
+
+    fn dump_regs<W: Write>(&self, out: &mut W) -> Result<(), Error> {
+        for (i, &value) in self.machine.regs().iter().enumerate() {
+            let name = format!("r{i}");
+            writeln!(out, "{name:<4}0x{value:08x} {:>11}", value as i32)?;
+        }
+        Ok(())
+    }
+
+    fn dump_memory<W: Write>(&self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let (start, len) = match args {
+            [location] => (self.location(location)?, 64),
+            [location, len] => (self.location(location)?, self.number(len)?),
+            _ => return Err(usage("mem <loc> [len]")),
+        };
+        let bytes = self.memory_range(start, len);
+        for (i, chunk) in bytes.chunks(16).enumerate() {
+            writeln!(out, "{:04}: {}", start as usize + 16 * i, hex_bytes(chunk))?;
+        }
+        Ok(())
+    }
+
+    fn set_register<W: Write>(&mut self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let [reg, value] = args else {
+            return Err(usage("set r<n> <value>"));
+        };
+        let index = reg
+            .strip_prefix('r')
+            .and_then(|n| n.parse().ok())
+            .ok_or_else(|| Error::Usage(format!("invalid register `{reg}`")))?;
+        let value = self.location(value)?;
+        self.machine
+            .set_reg(index, value)
+            .map_err(|_| Error::Usage(format!("invalid register `{reg}`")))?;
+        writeln!(out, "{reg} = {value}")?;
+        Ok(())
+    }
+
+    fn queue_input(&mut self, args: &[&str]) -> Result<(), Error> {
+        self.input.extend(args.join(" ").bytes());
+        self.input.push_back(b'\n');
+        Ok(())
+    }
+
+    fn disassemble<W: Write>(&self, args: &[&str], out: &mut W) -> Result<(), Error> {
+        let (mut address, count) = match args {
+            [] => (self.machine.regs()[0], 5),
+            [location] => (self.location(location)?, 5),
+            [location, count] => (self.location(location)?, self.number(count)?),
+            _ => return Err(usage("disas [loc] [count]")),
+        };
+        for _ in 0..count {
+            if let Some(name) = self.label_at(address) {
+                writeln!(out, "{name}:")?;
+            }
+            let marker = if address == self.machine.regs()[0] {
+                "=>"
+            } else {
+                "  "
+            };
+            match self.decode_at(address) {
+                Some(instruction) => {
+                    writeln!(out, "{marker}{address:04}   {instruction}")?;
+                    address += instruction.size() as u32;
+                }
+                None => {
+                    writeln!(out, "{marker}{address:04}   <invalid instruction>")?;
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn decode_at(&self, address: u32) -> Option<Instruction> {
+        let memory = self.machine.memory().get(address as usize..)?;
+        Instruction::decode(memory)
+            .ok()
+            .map(|(instruction, _)| instruction)
+    }
+
+    /// Bytes of memory in `[start, start+len)`, clamped to the memory size.
+    fn memory_range(&self, start: u32, len: u32) -> &[u8] {
+        let memory = self.machine.memory();
+        let start = (start as usize).min(memory.len());
+        let end = (start + len as usize).min(memory.len());
+        &memory[start..end]
+    }
+
+    fn label_at(&self, address: u32) -> Option<&str> {
+        self.machine
+            .symbols()
+            .iter()
+            .find(|(_, &a)| a == address)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Format `address` along with its label, if any.
+    fn describe(&self, address: u32) -> String {
+        match self.label_at(address) {
+            Some(name) => format!("{address:04} <{name}>"),
+            None => format!("{address:04}"),
+        }
+    }
+
+    /// Format the code `address` along with the closest label before it.
+    fn describe_code(&self, address: u32) -> String {
+        match self.machine.symbolize(address) {
+            Some(symbol) => format!("{address:04} <{symbol}>"),
+            None => format!("{address:04}"),
+        }
+    }
+
+    /// Parse a label or a number.
+    fn location(&self, text: &str) -> Result<u32, Error> {
+        match self.machine.symbols().get(text) {
+            Some(&address) => Ok(address),
+            None => self.number(text),
+        }
+    }
+
+    fn number(&self, text: &str) -> Result<u32, Error> {
+        let parsed = match text.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => text
+                .parse::<u32>()
+                .ok()
+                .or_else(|| text.parse::<i32>().ok().map(|v| v as u32)),
+        };
+        parsed.ok_or_else(|| Error::Usage(format!("invalid location `{text}`")))
+    }
+}
+
+enum Error {
+    Usage(String),
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+fn usage(syntax: &str) -> Error {
+    Error::Usage(format!("usage: {syntax}"))
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
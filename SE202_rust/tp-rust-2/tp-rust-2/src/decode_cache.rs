@@ -0,0 +1,43 @@
+use crate::Instruction;
+use std::ops::Range;
+
+/// Size of the longest instruction.
+const MAX_INSTRUCTION_SIZE: usize = 4;
+
+/// Instructions already decoded, with their size, indexed by address.
+pub(crate) struct DecodeCache {
+    entries: Vec<Option<(Instruction, u8)>>,
+}
+
+impl DecodeCache {
+    pub fn new(memory_size: usize) -> Self {
+        DecodeCache {
+            entries: vec![None; memory_size],
+        }
+    }
+
+    pub fn get(&self, addr: u32) -> Option<(Instruction, usize)> {
+        let (instruction, size) = self.entries.get(addr as usize).copied().flatten()?;
+        Some((instruction, size as usize))
+    }
+
+    pub fn insert(&mut self, addr: u32, (instruction, size): (Instruction, usize)) {
+        if let Some(entry) = self.entries.get_mut(addr as usize) {
+            *entry = Some((instruction, size as u8));
+        }
+    }
+
+    /// Forget the instructions overlapping the bytes in `range`, which
+    /// have been modified.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        let start = range.start.saturating_sub(MAX_INSTRUCTION_SIZE - 1);
+        let end = range.end.min(self.entries.len());
+        if start < end {
+            self.entries[start..end].fill(None);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.fill(None);
+    }
+}
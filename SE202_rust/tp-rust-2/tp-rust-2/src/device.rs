@@ -0,0 +1,87 @@
+use std::io::{self, BufRead, Write};
+
+/// Input and output of a running program. Output instructions write to the
+/// device and input instructions read from it.
+///
+/// Every [Write] is a device without input, so that output-only programs
+/// can keep being run on a `Vec<u8>` or on standard output. Use [Console]
+/// to also provide input.
+pub trait IoDevice {
+    /// Write bytes produced by the program.
+    fn write_output(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    /// Return the next input byte without consuming it, or `None` at the
+    /// end of the input.
+    fn peek_input(&mut self) -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+
+    /// Consume and return the next input byte, or `None` at the end of the
+    /// input.
+    fn read_input(&mut self) -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+}
+
+// A single `write`, as output instructions always did.
+#[allow(clippy::unused_io_amount)]
+impl<W: Write> IoDevice for W {
+    fn write_output(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.write(bytes)?;
+        Ok(())
+    }
+}
+
+/// Device reading input from a [BufRead] and writing output to a [Write].
+///
+/// Pending output is flushed before reading, so that prompts show up
+/// before the program waits for an answer.
+///
+/// ```
+/// use interpreter::{Console, Machine, MachineConfig};
+///
+/// // 0: in_number r1
+/// // 2: out_number r1
+/// // 4: exit
+/// let config = MachineConfig {
+///     input: true,
+///     ..MachineConfig::default()
+/// };
+/// let mut machine = Machine::with_config(&[10, 1, 8, 1, 7], config);
+/// let mut console = Console::new(&b" -12\n"[..], Vec::new());
+/// machine.run_on(&mut console).unwrap();
+/// assert_eq!(b"-12", &console.into_output()[..]);
+/// ```
+pub struct Console<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Console { input, output }
+    }
+
+    pub fn into_output(self) -> W {
+        self.output
+    }
+}
+
+impl<R: BufRead, W: Write> IoDevice for Console<R, W> {
+    fn write_output(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.write_all(bytes)
+    }
+
+    fn peek_input(&mut self) -> io::Result<Option<u8>> {
+        self.output.flush()?;
+        Ok(self.input.fill_buf()?.first().copied())
+    }
+
+    fn read_input(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.peek_input()?;
+        if byte.is_some() {
+            self.input.consume(1);
+        }
+        Ok(byte)
+    }
+}
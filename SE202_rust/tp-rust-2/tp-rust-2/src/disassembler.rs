@@ -0,0 +1,189 @@
+use crate::{Instruction, NREGS};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{self, Write};
+
+/// One entry of a disassembled memory image: either a valid instruction or
+/// a run of bytes that cannot be decoded and are shown as data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    pub instruction: Option<Instruction>,
+}
+
+/// Decode `memory` from address 0 until its end. Bytes which do not form a
+/// valid instruction (unknown opcode, truncated instruction or register out
+/// of range) are grouped into data entries.
+pub fn disassemble(memory: &[u8]) -> Vec<DecodedInstruction> {
+    disassemble_with_regs(memory, NREGS)
+}
+
+/// Same as [disassemble], for a machine with `nregs` registers.
+pub fn disassemble_with_regs(memory: &[u8], nregs: usize) -> Vec<DecodedInstruction> {
+    let mut decoded: Vec<DecodedInstruction> = Vec::new();
+    let mut address = 0;
+    while address < memory.len() {
+        match Instruction::decode(&memory[address..]) {
+            Ok((instruction, size))
+                if instruction
+                    .registers()
+                    .iter()
+                    .all(|&r| (r as usize) < nregs) =>
+            {
+                decoded.push(DecodedInstruction {
+                    address: address as u32,
+                    bytes: memory[address..address + size].to_vec(),
+                    instruction: Some(instruction),
+                });
+                address += size;
+                continue;
+            }
+            _ => (),
+        }
+        match decoded.last_mut() {
+            Some(last) if last.instruction.is_none() => last.bytes.push(memory[address]),
+            _ => decoded.push(DecodedInstruction {
+                address: address as u32,
+                bytes: vec![memory[address]],
+                instruction: None,
+            }),
+        }
+        address += 1;
+    }
+    decoded
+}
+
+impl DecodedInstruction {
+    /// Write the instruction text, using `label` to name the addresses in
+    /// `loadimm` and `call` operands.
+    fn write_text(&self, f: &mut impl Write, label: impl Fn(u32) -> Option<String>) -> fmt::Result {
+        match self.instruction {
+            None => write_byte_string(f, &self.bytes),
+            Some(Instruction::LoadImm { reg, value }) => match label(value as u16 as u32) {
+                Some(name) => write!(f, "loadimm r{reg} <- #{name}"),
+                None => write!(f, "loadimm r{reg} <- #{value}"),
+            },
+            Some(Instruction::Call { target }) => match label(target as u32) {
+                Some(name) => write!(f, "call #{name}"),
+                None => write!(f, "call #{target}"),
+            },
+            Some(instruction) => write!(f, "{instruction}"),
+        }
+    }
+}
+
+impl fmt::Display for DecodedInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_text(f, |_| None)
+    }
+}
+
+/// Write `bytes` the way Python prints a bytes object, e.g. `b'Hi\n'`.
+fn write_byte_string(f: &mut impl Write, bytes: &[u8]) -> fmt::Result {
+    let quote = if bytes.contains(&b'\'') && !bytes.contains(&b'"') {
+        '"'
+    } else {
+        '\''
+    };
+    write!(f, "b{quote}")?;
+    for &b in bytes {
+        match b {
+            b'\\' => f.write_str("\\\\")?,
+            b'\n' => f.write_str("\\n")?,
+            b'\r' => f.write_str("\\r")?,
+            b'\t' => f.write_str("\\t")?,
+            _ if b as char == quote => write!(f, "\\{quote}")?,
+            0x20..=0x7e => f.write_char(b as char)?,
+            _ => write!(f, "\\x{b:02x}")?,
+        }
+    }
+    f.write_char(quote)
+}
+
+/// Addresses of the jump targets found in `instructions`, with a synthetic
+/// label for each. A jump is a `loadimm r0 <- #addr`, a `call #addr`, or a
+/// conditional `move r0 <- rX if ...` where `rX` was last set by a
+/// `loadimm` in the same straight-line sequence. Only targets which are the
+/// start of a decoded entry are kept.
+pub fn jump_targets(instructions: &[DecodedInstruction]) -> BTreeMap<u32, String> {
+    let (targets, _) = find_jumps(instructions);
+    targets
+}
+
+/// Return the jump targets and the addresses of the `loadimm` instructions
+/// which load them.
+fn find_jumps(instructions: &[DecodedInstruction]) -> (BTreeMap<u32, String>, BTreeSet<u32>) {
+    let mut jumps = Vec::new();
+    // Value and address of the last `loadimm` into each register.
+    let mut constants: HashMap<u8, (u32, u32)> = HashMap::new();
+    for insn in instructions {
+        let Some(instruction) = insn.instruction else {
+            constants.clear();
+            continue;
+        };
+        match instruction {
+            Instruction::LoadImm { reg: 0, value } => {
+                jumps.push((value as u16 as u32, insn.address));
+                constants.clear();
+            }
+            Instruction::LoadImm { reg, value } => {
+                constants.insert(reg, (value as u16 as u32, insn.address));
+            }
+            Instruction::Call { target } => {
+                jumps.push((target as u32, insn.address));
+                constants.clear();
+            }
+            Instruction::MoveIf { a: 0, b, .. } => {
+                if let Some(&jump) = constants.get(&b) {
+                    jumps.push(jump);
+                }
+            }
+            // Any other write to a register forgets its value.
+            _ => match instruction.destination() {
+                Some(0) => constants.clear(),
+                Some(reg) => {
+                    constants.remove(&reg);
+                }
+                None => (),
+            },
+        }
+    }
+
+    let starts: Vec<u32> = instructions.iter().map(|insn| insn.address).collect();
+    let mut targets = BTreeMap::new();
+    let mut sources = BTreeSet::new();
+    for (target, source) in jumps {
+        if starts.binary_search(&target).is_ok() {
+            targets.insert(target, format!("label_{target:04}"));
+            sources.insert(source);
+        }
+    }
+    (targets, sources)
+}
+
+/// Render `instructions` in the format of the `.dis` listings, with a
+/// synthetic label before every jump target. The result can be fed back
+/// to the [assembler](crate::assembler).
+pub fn listing(instructions: &[DecodedInstruction]) -> String {
+    let (labels, sources) = find_jumps(instructions);
+    let mut out = String::new();
+    for insn in instructions {
+        if let Some(name) = labels.get(&insn.address) {
+            let _ = writeln!(out, "{name}:");
+        }
+        if insn.instruction.is_some() {
+            let _ = write!(out, "  {:04}   ", insn.address);
+        } else {
+            out.push_str("  ???? ");
+        }
+        let _ = insn.write_text(&mut out, |address| {
+            if sources.contains(&insn.address) {
+                labels.get(&address).cloned()
+            } else {
+                None
+            }
+        });
+        out.push('\n');
+    }
+    out
+}
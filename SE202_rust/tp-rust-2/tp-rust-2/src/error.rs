@@ -0,0 +1,230 @@
+use std::{fmt, io};
+
+/// Instruction which was being executed when a fault happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FaultSite {
+    /// Address of the instruction.
+    pub ip: u32,
+    /// Raw bytes of the instruction. When it cannot be decoded, these are
+    /// the bytes found at the IP: the unknown opcode, or a truncated
+    /// instruction at the end of the memory.
+    pub bytes: Vec<u8>,
+}
+
+/// Kind of a memory access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Load,
+    Store,
+    /// Reading an instruction to execute it.
+    Fetch,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Access::Load => write!(f, "load from"),
+            Access::Store => write!(f, "store to"),
+            Access::Fetch => write!(f, "instruction fetch from"),
+        }
+    }
+}
+
+/// Error stopping the execution of a [Machine](crate::Machine).
+///
+/// Faults carry the [FaultSite] of the instruction which caused them. It
+/// is filled in by [step_on](crate::Machine::step_on), so errors created
+/// elsewhere, by a [Bus](crate::Bus) for example, leave it to `None`.
+#[derive(Debug)]
+pub enum MachineError {
+    /// The IP does not point to a complete instruction in memory.
+    IpOutOfBounds {
+        site: Option<FaultSite>,
+    },
+    /// The opcode is unknown.
+    InvalidInstruction {
+        opcode: u8,
+        site: Option<FaultSite>,
+    },
+    /// The instruction is not available in the configuration of the
+    /// machine, such as an extended instruction with the base instruction
+    /// set.
+    UnsupportedInstruction {
+        site: Option<FaultSite>,
+    },
+    /// A register operand does not exist. Also returned by
+    /// [set_reg](crate::Machine::set_reg).
+    InvalidRegister {
+        reg: usize,
+        site: Option<FaultSite>,
+    },
+    /// No handler is registered for syscall `number`.
+    UnknownSyscall {
+        number: u32,
+        site: Option<FaultSite>,
+    },
+    /// A control register operand does not exist.
+    InvalidControlRegister {
+        reg: u8,
+        site: Option<FaultSite>,
+    },
+    /// The `width` bytes starting at `addr` are not all in memory.
+    MemoryOutOfBounds {
+        addr: u32,
+        width: usize,
+        access: Access,
+        site: Option<FaultSite>,
+    },
+    /// The memory permissions do not allow a `kind` access at `addr`.
+    ProtectionFault {
+        addr: u32,
+        kind: Access,
+        site: Option<FaultSite>,
+    },
+    /// A device does not accept a `width`-byte access at `addr`.
+    InvalidDeviceAccess {
+        addr: u32,
+        width: usize,
+        site: Option<FaultSite>,
+    },
+    /// A push would go below the stack limit, the stack pointer being `sp`.
+    StackOverflow {
+        sp: u32,
+        site: Option<FaultSite>,
+    },
+    /// A pop with an empty stack, the stack pointer being `sp`.
+    StackUnderflow {
+        sp: u32,
+        site: Option<FaultSite>,
+    },
+    DivisionByZero {
+        site: Option<FaultSite>,
+    },
+    /// The input does not contain a valid number.
+    InvalidInput {
+        site: Option<FaultSite>,
+    },
+    IoError {
+        error: io::Error,
+        site: Option<FaultSite>,
+    },
+    StepLimitExceeded {
+        steps: u64,
+    },
+    DeadlineExceeded {
+        steps: u64,
+    },
+}
+
+impl MachineError {
+    /// Instruction which caused the fault, if known.
+    pub fn site(&self) -> Option<&FaultSite> {
+        match self {
+            MachineError::IpOutOfBounds { site }
+            | MachineError::InvalidInstruction { site, .. }
+            | MachineError::UnsupportedInstruction { site }
+            | MachineError::InvalidRegister { site, .. }
+            | MachineError::InvalidControlRegister { site, .. }
+            | MachineError::UnknownSyscall { site, .. }
+            | MachineError::MemoryOutOfBounds { site, .. }
+            | MachineError::ProtectionFault { site, .. }
+            | MachineError::InvalidDeviceAccess { site, .. }
+            | MachineError::StackOverflow { site, .. }
+            | MachineError::StackUnderflow { site, .. }
+            | MachineError::DivisionByZero { site }
+            | MachineError::InvalidInput { site }
+            | MachineError::IoError { site, .. } => site.as_ref(),
+            MachineError::StepLimitExceeded { .. } | MachineError::DeadlineExceeded { .. } => None,
+        }
+    }
+
+    /// Attribute the fault to `site`, unless it already has one.
+    pub(crate) fn at(mut self, fault_site: FaultSite) -> Self {
+        match &mut self {
+            MachineError::IpOutOfBounds { site }
+            | MachineError::InvalidInstruction { site, .. }
+            | MachineError::UnsupportedInstruction { site }
+            | MachineError::InvalidRegister { site, .. }
+            | MachineError::InvalidControlRegister { site, .. }
+            | MachineError::UnknownSyscall { site, .. }
+            | MachineError::MemoryOutOfBounds { site, .. }
+            | MachineError::ProtectionFault { site, .. }
+            | MachineError::InvalidDeviceAccess { site, .. }
+            | MachineError::StackOverflow { site, .. }
+            | MachineError::StackUnderflow { site, .. }
+            | MachineError::DivisionByZero { site }
+            | MachineError::InvalidInput { site }
+            | MachineError::IoError { site, .. } => {
+                site.get_or_insert(fault_site);
+            }
+            MachineError::StepLimitExceeded { .. } | MachineError::DeadlineExceeded { .. } => (),
+        }
+        self
+    }
+}
+
+impl From<io::Error> for MachineError {
+    fn from(error: io::Error) -> Self {
+        MachineError::IoError { error, site: None }
+    }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachineError::IpOutOfBounds { .. } => write!(f, "no complete instruction at the IP")?,
+            MachineError::InvalidInstruction { opcode, .. } => {
+                write!(f, "invalid opcode {opcode}")?
+            }
+            MachineError::UnsupportedInstruction { .. } => {
+                write!(f, "instruction not supported by the machine configuration")?
+            }
+            MachineError::InvalidRegister { reg, .. } => write!(f, "invalid register r{reg}")?,
+            MachineError::UnknownSyscall { number, .. } => write!(f, "unknown syscall {number}")?,
+            MachineError::InvalidControlRegister { reg, .. } => {
+                write!(f, "invalid control register c{reg}")?
+            }
+            MachineError::MemoryOutOfBounds {
+                addr,
+                width,
+                access,
+                ..
+            } => write!(f, "{width}-byte {access} address {addr} out of memory")?,
+            MachineError::ProtectionFault { addr, kind, .. } => {
+                write!(f, "protection fault: {kind} address {addr} not permitted")?
+            }
+            MachineError::InvalidDeviceAccess { addr, width, .. } => {
+                write!(f, "invalid {width}-byte device access at address {addr}")?
+            }
+            MachineError::StackOverflow { sp, .. } => write!(f, "stack overflow (sp = {sp})")?,
+            MachineError::StackUnderflow { sp, .. } => write!(f, "stack underflow (sp = {sp})")?,
+            MachineError::DivisionByZero { .. } => write!(f, "division by zero")?,
+            MachineError::InvalidInput { .. } => write!(f, "invalid number in the input")?,
+            MachineError::IoError { error, .. } => write!(f, "input/output error: {error}")?,
+            MachineError::StepLimitExceeded { steps } => {
+                write!(f, "step limit exceeded after {steps} steps")?
+            }
+            MachineError::DeadlineExceeded { steps } => {
+                write!(f, "deadline exceeded after {steps} steps")?
+            }
+        }
+        if let Some(site) = self.site() {
+            write!(f, " at {:04} [", site.ip)?;
+            for (i, b) in site.bytes.iter().enumerate() {
+                let separator = if i == 0 { "" } else { " " };
+                write!(f, "{separator}{b:02x}")?;
+            }
+            write!(f, "]")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for MachineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MachineError::IoError { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use crate::machine::MemWrite;
+use std::collections::VecDeque;
+
+/// What a step changed, with the previous values, so that it can be undone.
+pub(crate) struct UndoStep {
+    /// Registers modified by the step, with their previous value.
+    pub regs: Vec<(usize, u32)>,
+    /// Memory writes done by the step, in execution order.
+    pub writes: Vec<MemWrite>,
+}
+
+/// Undo log keeping the changes of the last `capacity` steps. Older steps
+/// are forgotten as new ones are recorded.
+pub(crate) struct History {
+    steps: VecDeque<UndoStep>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            steps: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.steps.len() > capacity {
+            self.steps.pop_front();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    pub fn push(&mut self, step: UndoStep) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+        }
+        self.steps.push_back(step);
+    }
+
+    pub fn pop(&mut self) -> Option<UndoStep> {
+        self.steps.pop_back()
+    }
+}
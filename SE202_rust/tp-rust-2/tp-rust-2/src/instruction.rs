@@ -0,0 +1,508 @@
+use crate::MachineError;
+use std::fmt;
+
+/// A decoded machine instruction. Register operands are kept as raw
+/// register numbers and are only checked when the instruction is executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// `move ra <- rb if rc != 0`
+    MoveIf { a: u8, b: u8, c: u8 },
+    /// `store [ra] <- rb`
+    Store { a: u8, b: u8 },
+    /// `load ra <- [rb]`
+    Load { a: u8, b: u8 },
+    /// `loadimm reg <- #value`
+    LoadImm { reg: u8, value: i16 },
+    /// `sub ra <- rb - rc`
+    Sub { a: u8, b: u8, c: u8 },
+    /// `out reg`
+    Out { reg: u8 },
+    /// `exit`
+    Exit,
+    /// `out_number reg`
+    OutNumber { reg: u8 },
+    /// `in reg`, when [input](crate::MachineConfig::input) is enabled
+    In { reg: u8 },
+    /// `in_number reg`, when [input](crate::MachineConfig::input) is
+    /// enabled
+    InNumber { reg: u8 },
+    /// `op ra <- rb OP rc`, from the extended instruction set
+    Alu { op: AluOp, a: u8, b: u8, c: u8 },
+    /// `push reg`
+    Push { reg: u8 },
+    /// `pop reg`
+    Pop { reg: u8 },
+    /// `call #target`
+    Call { target: u16 },
+    /// `ret`
+    Ret,
+    /// `store8 [ra] <- rb` or `store16 [ra] <- rb`, storing the low bits of
+    /// `rb`
+    StoreNarrow { width: Width, a: u8, b: u8 },
+    /// `load8 ra <- [rb]` or `load16 ra <- [rb]`, zero-extended, or
+    /// `load8s ra <- [rb]` or `load16s ra <- [rb]`, sign-extended
+    LoadNarrow {
+        width: Width,
+        signed: bool,
+        a: u8,
+        b: u8,
+    },
+    /// `getctl ra <- cN`, reading a control register, when
+    /// [traps](crate::MachineConfig::traps) are enabled
+    GetCtl { a: u8, c: u8 },
+    /// `setctl cN <- ra`, writing a control register, when
+    /// [traps](crate::MachineConfig::traps) are enabled
+    SetCtl { c: u8, a: u8 },
+    /// `iret`, returning from a trap handler, when
+    /// [traps](crate::MachineConfig::traps) are enabled
+    Iret,
+    /// `syscall`, running the host handler whose number is in r1, when
+    /// [syscalls](crate::MachineConfig::syscalls) are enabled
+    Syscall,
+}
+
+/// Size of the memory accesses of the narrow loads and stores, from the
+/// extended instruction set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    Byte,
+    Half,
+}
+
+/// Operations of the extended instruction set, written
+/// `mnemonic ra <- rb OP rc` and computing `ra` from `rb` and `rc`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    /// `add ra <- rb + rc`
+    Add,
+    /// `and ra <- rb & rc`
+    And,
+    /// `or ra <- rb | rc`
+    Or,
+    /// `xor ra <- rb ^ rc`
+    Xor,
+    /// `shl ra <- rb << rc`
+    Shl,
+    /// `shr ra <- rb >> rc`, logical shift
+    Shr,
+    /// `sar ra <- rb >> rc`, arithmetic shift
+    Sar,
+    /// `slt ra <- rb < rc`, signed comparison giving 1 or 0
+    Slt,
+    /// `sltu ra <- rb < rc`, unsigned comparison giving 1 or 0
+    Sltu,
+    /// `mul ra <- rb * rc`
+    Mul,
+    /// `div ra <- rb / rc`, signed division
+    Div,
+    /// `mod ra <- rb % rc`, remainder of the signed division
+    Mod,
+}
+
+pub const OP_MOVE_IF: u8 = 1;
+pub const OP_STORE: u8 = 2;
+pub const OP_LOAD: u8 = 3;
+pub const OP_LOAD_IMM: u8 = 4;
+pub const OP_SUB: u8 = 5;
+pub const OP_OUT: u8 = 6;
+pub const OP_EXIT: u8 = 7;
+pub const OP_OUT_NUMBER: u8 = 8;
+pub const OP_IN: u8 = 9;
+pub const OP_IN_NUMBER: u8 = 10;
+pub const OP_ADD: u8 = 11;
+pub const OP_AND: u8 = 12;
+pub const OP_OR: u8 = 13;
+pub const OP_XOR: u8 = 14;
+pub const OP_SHL: u8 = 15;
+pub const OP_SHR: u8 = 16;
+pub const OP_SAR: u8 = 17;
+pub const OP_SLT: u8 = 18;
+pub const OP_SLTU: u8 = 19;
+pub const OP_MUL: u8 = 20;
+pub const OP_DIV: u8 = 21;
+pub const OP_MOD: u8 = 22;
+pub const OP_PUSH: u8 = 23;
+pub const OP_POP: u8 = 24;
+pub const OP_CALL: u8 = 25;
+pub const OP_RET: u8 = 26;
+pub const OP_STORE8: u8 = 27;
+pub const OP_STORE16: u8 = 28;
+pub const OP_LOAD8: u8 = 29;
+pub const OP_LOAD8S: u8 = 30;
+pub const OP_LOAD16: u8 = 31;
+pub const OP_LOAD16S: u8 = 32;
+pub const OP_GETCTL: u8 = 33;
+pub const OP_SETCTL: u8 = 34;
+pub const OP_IRET: u8 = 35;
+pub const OP_SYSCALL: u8 = 36;
+
+/// Size in bytes of the instruction starting with `opcode`, or 0 if the
+/// opcode is not a valid one.
+pub fn instruction_size(opcode: u8) -> usize {
+    match opcode {
+        OP_MOVE_IF | OP_LOAD_IMM | OP_SUB | OP_ADD..=OP_MOD => 4,
+        OP_STORE | OP_LOAD | OP_CALL | OP_STORE8..=OP_LOAD16S | OP_GETCTL | OP_SETCTL => 3,
+        OP_OUT | OP_OUT_NUMBER | OP_IN | OP_IN_NUMBER | OP_PUSH | OP_POP => 2,
+        OP_EXIT | OP_RET | OP_IRET | OP_SYSCALL => 1,
+        _ => 0,
+    }
+}
+
+impl Instruction {
+    /// Decode the instruction at the beginning of `bytes`, and return it
+    /// along with its size in bytes.
+    pub fn decode(bytes: &[u8]) -> Result<(Instruction, usize), MachineError> {
+        let opcode = *bytes
+            .first()
+            .ok_or(MachineError::IpOutOfBounds { site: None })?;
+        let size = instruction_size(opcode);
+        if size == 0 {
+            return Err(MachineError::InvalidInstruction { opcode, site: None });
+        }
+        if bytes.len() < size {
+            return Err(MachineError::IpOutOfBounds { site: None });
+        }
+        let b = &bytes[..size];
+        let instruction = match opcode {
+            OP_MOVE_IF => Instruction::MoveIf {
+                a: b[1],
+                b: b[2],
+                c: b[3],
+            },
+            OP_STORE => Instruction::Store { a: b[1], b: b[2] },
+            OP_LOAD => Instruction::Load { a: b[1], b: b[2] },
+            OP_LOAD_IMM => Instruction::LoadImm {
+                reg: b[1],
+                value: i16::from_le_bytes([b[2], b[3]]),
+            },
+            OP_SUB => Instruction::Sub {
+                a: b[1],
+                b: b[2],
+                c: b[3],
+            },
+            OP_OUT => Instruction::Out { reg: b[1] },
+            OP_EXIT => Instruction::Exit,
+            OP_OUT_NUMBER => Instruction::OutNumber { reg: b[1] },
+            OP_IN => Instruction::In { reg: b[1] },
+            OP_IN_NUMBER => Instruction::InNumber { reg: b[1] },
+            OP_ADD..=OP_MOD => Instruction::Alu {
+                op: AluOp::from_opcode(opcode).unwrap(),
+                a: b[1],
+                b: b[2],
+                c: b[3],
+            },
+            OP_PUSH => Instruction::Push { reg: b[1] },
+            OP_POP => Instruction::Pop { reg: b[1] },
+            OP_CALL => Instruction::Call {
+                target: u16::from_le_bytes([b[1], b[2]]),
+            },
+            OP_RET => Instruction::Ret,
+            OP_STORE8 | OP_STORE16 => Instruction::StoreNarrow {
+                width: if opcode == OP_STORE8 {
+                    Width::Byte
+                } else {
+                    Width::Half
+                },
+                a: b[1],
+                b: b[2],
+            },
+            OP_LOAD8..=OP_LOAD16S => Instruction::LoadNarrow {
+                width: if opcode <= OP_LOAD8S {
+                    Width::Byte
+                } else {
+                    Width::Half
+                },
+                signed: opcode == OP_LOAD8S || opcode == OP_LOAD16S,
+                a: b[1],
+                b: b[2],
+            },
+            OP_GETCTL => Instruction::GetCtl { a: b[1], c: b[2] },
+            OP_SETCTL => Instruction::SetCtl { c: b[1], a: b[2] },
+            OP_IRET => Instruction::Iret,
+            OP_SYSCALL => Instruction::Syscall,
+            _ => unreachable!(),
+        };
+        Ok((instruction, size))
+    }
+
+    /// Encode the instruction into its binary representation.
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            Instruction::MoveIf { a, b, c } => vec![OP_MOVE_IF, a, b, c],
+            Instruction::Store { a, b } => vec![OP_STORE, a, b],
+            Instruction::Load { a, b } => vec![OP_LOAD, a, b],
+            Instruction::LoadImm { reg, value } => {
+                let [lo, hi] = value.to_le_bytes();
+                vec![OP_LOAD_IMM, reg, lo, hi]
+            }
+            Instruction::Sub { a, b, c } => vec![OP_SUB, a, b, c],
+            Instruction::Out { reg } => vec![OP_OUT, reg],
+            Instruction::Exit => vec![OP_EXIT],
+            Instruction::OutNumber { reg } => vec![OP_OUT_NUMBER, reg],
+            Instruction::In { reg } => vec![OP_IN, reg],
+            Instruction::InNumber { reg } => vec![OP_IN_NUMBER, reg],
+            Instruction::Alu { op, a, b, c } => vec![op.opcode(), a, b, c],
+            Instruction::Push { reg } => vec![OP_PUSH, reg],
+            Instruction::Pop { reg } => vec![OP_POP, reg],
+            Instruction::Call { target } => {
+                let [lo, hi] = target.to_le_bytes();
+                vec![OP_CALL, lo, hi]
+            }
+            Instruction::Ret => vec![OP_RET],
+            Instruction::StoreNarrow { a, b, .. } | Instruction::LoadNarrow { a, b, .. } => {
+                vec![self.opcode(), a, b]
+            }
+            Instruction::GetCtl { a, c } => vec![OP_GETCTL, a, c],
+            Instruction::SetCtl { c, a } => vec![OP_SETCTL, c, a],
+            Instruction::Iret => vec![OP_IRET],
+            Instruction::Syscall => vec![OP_SYSCALL],
+        }
+    }
+
+    /// Size in bytes of the encoded instruction.
+    pub fn size(&self) -> usize {
+        instruction_size(self.opcode())
+    }
+
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Instruction::MoveIf { .. } => OP_MOVE_IF,
+            Instruction::Store { .. } => OP_STORE,
+            Instruction::Load { .. } => OP_LOAD,
+            Instruction::LoadImm { .. } => OP_LOAD_IMM,
+            Instruction::Sub { .. } => OP_SUB,
+            Instruction::Out { .. } => OP_OUT,
+            Instruction::Exit => OP_EXIT,
+            Instruction::OutNumber { .. } => OP_OUT_NUMBER,
+            Instruction::In { .. } => OP_IN,
+            Instruction::InNumber { .. } => OP_IN_NUMBER,
+            Instruction::Alu { op, .. } => op.opcode(),
+            Instruction::Push { .. } => OP_PUSH,
+            Instruction::Pop { .. } => OP_POP,
+            Instruction::Call { .. } => OP_CALL,
+            Instruction::Ret => OP_RET,
+            Instruction::StoreNarrow { width, .. } => match width {
+                Width::Byte => OP_STORE8,
+                Width::Half => OP_STORE16,
+            },
+            Instruction::LoadNarrow { width, signed, .. } => match width {
+                Width::Byte => OP_LOAD8 + *signed as u8,
+                Width::Half => OP_LOAD16 + *signed as u8,
+            },
+            Instruction::GetCtl { .. } => OP_GETCTL,
+            Instruction::SetCtl { .. } => OP_SETCTL,
+            Instruction::Iret => OP_IRET,
+            Instruction::Syscall => OP_SYSCALL,
+        }
+    }
+
+    /// Registers referenced by the instruction.
+    pub fn registers(&self) -> Vec<u8> {
+        match *self {
+            Instruction::MoveIf { a, b, c }
+            | Instruction::Sub { a, b, c }
+            | Instruction::Alu { a, b, c, .. } => vec![a, b, c],
+            Instruction::Store { a, b }
+            | Instruction::Load { a, b }
+            | Instruction::StoreNarrow { a, b, .. }
+            | Instruction::LoadNarrow { a, b, .. } => vec![a, b],
+            Instruction::LoadImm { reg, .. }
+            | Instruction::Out { reg }
+            | Instruction::OutNumber { reg }
+            | Instruction::In { reg }
+            | Instruction::InNumber { reg }
+            | Instruction::Push { reg }
+            | Instruction::Pop { reg } => vec![reg],
+            Instruction::GetCtl { a, .. } | Instruction::SetCtl { a, .. } => vec![a],
+            Instruction::Exit
+            | Instruction::Call { .. }
+            | Instruction::Ret
+            | Instruction::Iret
+            | Instruction::Syscall => vec![],
+        }
+    }
+
+    /// Whether the instruction is part of the extended instruction set.
+    pub fn is_extended(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Alu { .. }
+                | Instruction::StoreNarrow { .. }
+                | Instruction::LoadNarrow { .. }
+        )
+    }
+
+    /// Whether the instruction uses the stack, and can only be executed
+    /// when the machine has one.
+    pub fn uses_stack(&self) -> bool {
+        matches!(
+            self,
+            Instruction::Push { .. }
+                | Instruction::Pop { .. }
+                | Instruction::Call { .. }
+                | Instruction::Ret
+        )
+    }
+
+    /// Whether the instruction reads input, and can only be executed when
+    /// the machine has input enabled.
+    pub fn reads_input(&self) -> bool {
+        matches!(self, Instruction::In { .. } | Instruction::InNumber { .. })
+    }
+
+    /// Whether the instruction is part of the exception model, and can only
+    /// be executed when the machine has traps enabled.
+    pub fn uses_traps(&self) -> bool {
+        matches!(
+            self,
+            Instruction::GetCtl { .. } | Instruction::SetCtl { .. } | Instruction::Iret
+        )
+    }
+
+    /// Register written by the instruction, if any. The stack instructions
+    /// also update the stack pointer, which is not reported.
+    pub fn destination(&self) -> Option<u8> {
+        match *self {
+            Instruction::MoveIf { a, .. }
+            | Instruction::Load { a, .. }
+            | Instruction::LoadNarrow { a, .. }
+            | Instruction::Sub { a, .. }
+            | Instruction::Alu { a, .. } => Some(a),
+            Instruction::LoadImm { reg, .. }
+            | Instruction::In { reg }
+            | Instruction::InNumber { reg }
+            | Instruction::Pop { reg } => Some(reg),
+            Instruction::GetCtl { a, .. } => Some(a),
+            Instruction::Call { .. } | Instruction::Ret | Instruction::Iret => Some(0),
+            _ => None,
+        }
+    }
+}
+
+/// Format the instruction with the syntax of the `.dis` listings.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::MoveIf { a, b, c } => write!(f, "move r{a} <- r{b} if r{c} != 0"),
+            Instruction::Store { a, b } => write!(f, "store [r{a}] <- r{b}"),
+            Instruction::Load { a, b } => write!(f, "load r{a} <- [r{b}]"),
+            Instruction::LoadImm { reg, value } => write!(f, "loadimm r{reg} <- #{value}"),
+            Instruction::Sub { a, b, c } => write!(f, "sub r{a} <- r{b} - r{c}"),
+            Instruction::Out { reg } => write!(f, "out r{reg}"),
+            Instruction::Exit => write!(f, "exit"),
+            Instruction::OutNumber { reg } => write!(f, "out_number r{reg}"),
+            Instruction::In { reg } => write!(f, "in r{reg}"),
+            Instruction::InNumber { reg } => write!(f, "in_number r{reg}"),
+            Instruction::Alu { op, a, b, c } => {
+                write!(f, "{} r{a} <- r{b} {} r{c}", op.mnemonic(), op.operator())
+            }
+            Instruction::Push { reg } => write!(f, "push r{reg}"),
+            Instruction::Pop { reg } => write!(f, "pop r{reg}"),
+            Instruction::Call { target } => write!(f, "call #{target}"),
+            Instruction::Ret => write!(f, "ret"),
+            Instruction::StoreNarrow { width, a, b } => {
+                write!(f, "store{} [r{a}] <- r{b}", width.bits())
+            }
+            Instruction::LoadNarrow {
+                width,
+                signed,
+                a,
+                b,
+            } => {
+                let suffix = if signed { "s" } else { "" };
+                write!(f, "load{}{suffix} r{a} <- [r{b}]", width.bits())
+            }
+            Instruction::GetCtl { a, c } => write!(f, "getctl r{a} <- c{c}"),
+            Instruction::SetCtl { c, a } => write!(f, "setctl c{c} <- r{a}"),
+            Instruction::Iret => write!(f, "iret"),
+            Instruction::Syscall => write!(f, "syscall"),
+        }
+    }
+}
+
+impl AluOp {
+    pub const ALL: [AluOp; 12] = [
+        AluOp::Add,
+        AluOp::And,
+        AluOp::Or,
+        AluOp::Xor,
+        AluOp::Shl,
+        AluOp::Shr,
+        AluOp::Sar,
+        AluOp::Slt,
+        AluOp::Sltu,
+        AluOp::Mul,
+        AluOp::Div,
+        AluOp::Mod,
+    ];
+
+    pub fn opcode(self) -> u8 {
+        OP_ADD + self as u8
+    }
+
+    pub fn from_opcode(opcode: u8) -> Option<AluOp> {
+        AluOp::ALL.into_iter().find(|op| op.opcode() == opcode)
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            AluOp::Add => "add",
+            AluOp::And => "and",
+            AluOp::Or => "or",
+            AluOp::Xor => "xor",
+            AluOp::Shl => "shl",
+            AluOp::Shr => "shr",
+            AluOp::Sar => "sar",
+            AluOp::Slt => "slt",
+            AluOp::Sltu => "sltu",
+            AluOp::Mul => "mul",
+            AluOp::Div => "div",
+            AluOp::Mod => "mod",
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<AluOp> {
+        AluOp::ALL.into_iter().find(|op| op.mnemonic() == mnemonic)
+    }
+
+    /// Operator between the source registers in the listing syntax.
+    pub fn operator(self) -> &'static str {
+        match self {
+            AluOp::Add => "+",
+            AluOp::And => "&",
+            AluOp::Or => "|",
+            AluOp::Xor => "^",
+            AluOp::Shl => "<<",
+            AluOp::Shr | AluOp::Sar => ">>",
+            AluOp::Slt | AluOp::Sltu => "<",
+            AluOp::Mul => "*",
+            AluOp::Div => "/",
+            AluOp::Mod => "%",
+        }
+    }
+}
+
+impl Width {
+    /// Number of bytes accessed.
+    pub fn bytes(self) -> usize {
+        match self {
+            Width::Byte => 1,
+            Width::Half => 2,
+        }
+    }
+
+    /// Number of bits accessed, as found in the mnemonics.
+    pub fn bits(self) -> u32 {
+        8 * self.bytes() as u32
+    }
+
+    /// Extend the `bits()` low bits of `value` to 32 bits.
+    pub fn extend(self, value: u32, signed: bool) -> u32 {
+        let shift = 32 - self.bits();
+        if signed {
+            (((value << shift) as i32) >> shift) as u32
+        } else {
+            (value << shift) >> shift
+        }
+    }
+}
@@ -1,3 +1,32 @@
+pub mod assembler;
+mod bus;
+pub mod debugger;
+mod decode_cache;
+mod device;
+pub mod disassembler;
+mod error;
+mod history;
+mod instruction;
+pub mod linker;
 mod machine;
+mod object;
+pub mod options;
+mod protection;
+pub mod profiler;
+pub mod reference;
+mod snapshot;
+mod syscall;
+mod threaded;
+pub mod tracer;
+mod trap;
 
+pub use bus::*;
+pub use device::*;
+pub use error::*;
+pub use instruction::*;
 pub use machine::*;
+pub use object::*;
+pub use protection::*;
+pub use snapshot::*;
+pub use syscall::*;
+pub use trap::*;
@@ -0,0 +1,363 @@
+//! Linker combining separately assembled [Object]s into one program.
+//!
+//! The code sections of every object are placed first, in the order of
+//! the objects, followed by their data sections. The immediates of the
+//! `loadimm` and `call` instructions referring to symbols are then updated:
+//! a symbol is looked up in the object making the reference, then among the
+//! `.global` symbols of every object.
+
+use crate::{
+    instruction_size, Object, Section, SectionKind, MAX_SYMBOL_NAME, OP_CALL, OP_LOAD_IMM,
+};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+/// Name of the global symbol where execution starts, when defined.
+pub const ENTRY_SYMBOL: &str = "start";
+
+/// Where a section of an input object was placed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// Index of the object in the inputs.
+    pub input: usize,
+    pub kind: SectionKind,
+    /// Address of the section in its object.
+    pub original: u32,
+    /// Address of the section in the linked program.
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Symbol of the linked program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkedSymbol {
+    /// Name in the linked program. Local symbols whose name is used by
+    /// another symbol are prefixed by the name of their object and `:`.
+    pub name: String,
+    pub address: u32,
+    pub input: usize,
+    pub global: bool,
+}
+
+/// Result of [link].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linked {
+    /// Names of the input objects.
+    pub inputs: Vec<String>,
+    /// Linked program, without relocations.
+    pub object: Object,
+    pub placements: Vec<Placement>,
+    /// Every symbol, sorted by address.
+    pub symbols: Vec<LinkedSymbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkError {
+    /// `symbol` is global in both objects `first` and `second`.
+    DuplicateSymbol {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    /// `symbol`, referenced by `input`, is defined nowhere.
+    UndefinedSymbol { symbol: String, input: String },
+    /// A symbol of `input` is not in one of its sections.
+    SymbolOutsideSections { symbol: String, input: String },
+    /// A relocation of `input` does not point to a `loadimm` or `call`
+    /// instruction in one of its sections.
+    InvalidRelocation { address: u32, input: String },
+    /// The address of `symbol`, referenced by `input`, does not fit in the
+    /// immediate of the instruction: above 0x7fff for a `loadimm`, which
+    /// sign-extends it, or above 0xffff for a `call`.
+    AddressOutOfRange {
+        symbol: String,
+        input: String,
+        address: u32,
+    },
+    /// The program needs `size` bytes, more than the `memory_size` bytes of
+    /// the machine.
+    DoesNotFit { size: u64, memory_size: usize },
+    /// The name of `symbol` of `input` in the linked program, prefixed with
+    /// the input name for a local symbol, is longer than [MAX_SYMBOL_NAME].
+    SymbolNameTooLong { symbol: String, input: String },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol {
+                symbol,
+                first,
+                second,
+            } => write!(
+                f,
+                "symbol `{symbol}` is defined by both {first} and {second}"
+            ),
+            LinkError::UndefinedSymbol { symbol, input } => {
+                write!(f, "{input}: symbol `{symbol}` is not defined")
+            }
+            LinkError::SymbolOutsideSections { symbol, input } => {
+                write!(f, "{input}: symbol `{symbol}` is outside of the sections")
+            }
+            LinkError::InvalidRelocation { address, input } => {
+                write!(f, "{input}: invalid relocation at address {address}")
+            }
+            LinkError::AddressOutOfRange {
+                symbol,
+                input,
+                address,
+            } => write!(
+                f,
+                "{input}: address {address} of `{symbol}` does not fit in an immediate"
+            ),
+            LinkError::DoesNotFit { size, memory_size } => write!(
+                f,
+                "the program needs {size} bytes but the memory only has {memory_size}"
+            ),
+            LinkError::SymbolNameTooLong { symbol, input } => write!(
+                f,
+                "{input}: the linked name of `{symbol}` is longer than {MAX_SYMBOL_NAME} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LinkError {}
+
+/// Link the named `inputs` into a program for a memory of `memory_size`
+/// bytes. Execution starts at the global symbol [ENTRY_SYMBOL] if any,
+/// otherwise at the entry point of the first object.
+pub fn link(inputs: &[(String, Object)], memory_size: usize) -> Result<Linked, LinkError> {
+    let name = |input: usize| inputs[input].0.clone();
+
+    // Place the sections.
+    let mut placements = Vec::new();
+    let mut address: u64 = 0;
+    for kind in [SectionKind::Code, SectionKind::Data] {
+        for (input, (_, object)) in inputs.iter().enumerate() {
+            for section in object.sections.iter().filter(|s| s.kind == kind) {
+                placements.push(Placement {
+                    input,
+                    kind,
+                    original: section.address,
+                    address: address as u32,
+                    size: section.bytes.len() as u32,
+                });
+                address += section.bytes.len() as u64;
+            }
+        }
+    }
+    if address > memory_size as u64 {
+        return Err(LinkError::DoesNotFit {
+            size: address,
+            memory_size,
+        });
+    }
+    let translate = |input: usize, addr: u32| relocate(&placements, input, addr);
+
+    // Collect the symbols.
+    let mut globals: BTreeMap<&str, (usize, u32)> = BTreeMap::new();
+    let mut symbols = Vec::new();
+    for (input, (_, object)) in inputs.iter().enumerate() {
+        for (symbol, &original) in &object.symbols {
+            let address =
+                translate(input, original).ok_or_else(|| LinkError::SymbolOutsideSections {
+                    symbol: symbol.clone(),
+                    input: name(input),
+                })?;
+            let global = object.globals.contains(symbol);
+            if global {
+                if let Some(&(first, _)) = globals.get(symbol.as_str()) {
+                    return Err(LinkError::DuplicateSymbol {
+                        symbol: symbol.clone(),
+                        first: name(first),
+                        second: name(input),
+                    });
+                }
+                globals.insert(symbol, (input, address));
+            }
+            symbols.push(LinkedSymbol {
+                name: symbol.clone(),
+                address,
+                input,
+                global,
+            });
+        }
+    }
+
+    // Copy the sections and update the references.
+    let mut memory = vec![0; address as usize];
+    for (input, (_, object)) in inputs.iter().enumerate() {
+        for section in &object.sections {
+            let start = translate(input, section.address).unwrap() as usize;
+            memory[start..start + section.bytes.len()].copy_from_slice(&section.bytes);
+        }
+        for relocation in &object.relocations {
+            let invalid = || LinkError::InvalidRelocation {
+                address: relocation.address,
+                input: name(input),
+            };
+            let section = containing(object, relocation.address).ok_or_else(invalid)?;
+            let offset = (relocation.address - section.address) as usize;
+            let opcode = section.bytes[offset];
+            if !(opcode == OP_LOAD_IMM || opcode == OP_CALL)
+                || offset + instruction_size(opcode) > section.bytes.len()
+            {
+                return Err(invalid());
+            }
+            let target = match object.symbols.get(&relocation.symbol) {
+                Some(&original) => translate(input, original).unwrap(),
+                None => {
+                    globals
+                        .get(relocation.symbol.as_str())
+                        .ok_or_else(|| LinkError::UndefinedSymbol {
+                            symbol: relocation.symbol.clone(),
+                            input: name(input),
+                        })?
+                        .1
+                }
+            };
+            let max = if opcode == OP_CALL { 0xffff } else { 0x7fff };
+            let immediate = u16::try_from(target)
+                .ok()
+                .filter(|&immediate| immediate <= max)
+                .ok_or_else(|| LinkError::AddressOutOfRange {
+                    symbol: relocation.symbol.clone(),
+                    input: name(input),
+                    address: target,
+                })?;
+            // The immediate ends the instruction.
+            let site = translate(input, relocation.address).unwrap() as usize;
+            let end = site + instruction_size(opcode);
+            memory[end - 2..end].copy_from_slice(&immediate.to_le_bytes());
+        }
+    }
+
+    let entry = match globals.get(ENTRY_SYMBOL) {
+        Some(&(_, address)) => address,
+        None => inputs
+            .first()
+            .and_then(|(_, object)| translate(0, object.entry))
+            .unwrap_or(0),
+    };
+    let code_size: u32 = placements
+        .iter()
+        .filter(|placement| placement.kind == SectionKind::Code)
+        .map(|placement| placement.size)
+        .sum();
+    let sections = [
+        (SectionKind::Code, 0..code_size as usize),
+        (SectionKind::Data, code_size as usize..memory.len()),
+    ]
+    .into_iter()
+    .filter(|(_, range)| !range.is_empty())
+    .map(|(kind, range)| Section {
+        kind,
+        address: range.start as u32,
+        bytes: memory[range].to_vec(),
+    })
+    .collect();
+
+    // Local symbols keep their name unless it is used by another symbol.
+    let mut uses: BTreeMap<&str, usize> = BTreeMap::new();
+    for symbol in &symbols {
+        *uses.entry(symbol.name.as_str()).or_default() += 1;
+    }
+    let renamed: Vec<String> = symbols
+        .iter()
+        .map(|symbol| match uses[symbol.name.as_str()] {
+            1 => symbol.name.clone(),
+            _ if symbol.global => symbol.name.clone(),
+            _ => format!("{}:{}", inputs[symbol.input].0, symbol.name),
+        })
+        .collect();
+    if let Some((symbol, _)) = symbols
+        .iter()
+        .zip(&renamed)
+        .find(|(_, renamed)| renamed.len() > MAX_SYMBOL_NAME)
+    {
+        return Err(LinkError::SymbolNameTooLong {
+            symbol: symbol.name.clone(),
+            input: name(symbol.input),
+        });
+    }
+    for (symbol, name) in symbols.iter_mut().zip(renamed) {
+        symbol.name = name;
+    }
+    symbols.sort_by(|a, b| (a.address, &a.name).cmp(&(b.address, &b.name)));
+
+    let object = Object {
+        entry,
+        sections,
+        symbols: symbols
+            .iter()
+            .map(|symbol| (symbol.name.clone(), symbol.address))
+            .collect(),
+        globals: globals.keys().map(|name| name.to_string()).collect(),
+        relocations: Vec::new(),
+    };
+    Ok(Linked {
+        inputs: inputs.iter().map(|(name, _)| name.clone()).collect(),
+        object,
+        placements,
+        symbols,
+    })
+}
+
+/// Section of `object` containing `addr`.
+fn containing(object: &Object, addr: u32) -> Option<&Section> {
+    object
+        .sections
+        .iter()
+        .find(|section| section.address <= addr && (addr as u64) < section.end())
+}
+
+/// Linked address of the address `addr` of the object `input`. An address
+/// just after a section, such as a label at the end of the program, follows
+/// that section.
+fn relocate(placements: &[Placement], input: usize, addr: u32) -> Option<u32> {
+    let placements = placements.iter().filter(|p| p.input == input);
+    let inside = |p: &&Placement| p.original <= addr && addr - p.original < p.size;
+    let at_end = |p: &&Placement| p.original <= addr && addr - p.original == p.size;
+    let placement = placements
+        .clone()
+        .find(inside)
+        .or_else(|| placements.clone().find(at_end))?;
+    Some(placement.address + (addr - placement.original))
+}
+
+impl Linked {
+    /// Map file describing where every section and symbol went.
+    pub fn map(&self) -> String {
+        let mut map = String::new();
+        let entry = &self.object.entry;
+        let _ = writeln!(map, "Entry point: {entry:04}");
+        let _ = writeln!(map, "\nSections:");
+        for placement in &self.placements {
+            let kind = match placement.kind {
+                SectionKind::Code => "code",
+                SectionKind::Data => "data",
+            };
+            let _ = writeln!(
+                map,
+                "  {:04}-{:04}  {kind}  {} (from {:04})",
+                placement.address,
+                placement.address + placement.size,
+                self.inputs[placement.input],
+                placement.original,
+            );
+        }
+        let _ = writeln!(map, "\nSymbols:");
+        let width = self.symbols.iter().map(|s| s.name.len()).max().unwrap_or(0);
+        for symbol in &self.symbols {
+            let scope = if symbol.global { "global" } else { "local " };
+            let _ = writeln!(
+                map,
+                "  {:04}  {scope}  {:width$}  {}",
+                symbol.address, symbol.name, self.inputs[symbol.input],
+            );
+        }
+        map
+    }
+}
@@ -1,28 +1,147 @@
-use std::io::{self, Write};
+use crate::decode_cache::DecodeCache;
+use crate::history::{History, UndoStep};
+use crate::protection::PermissionMap;
+use crate::syscall::{arg, c_string, Syscall, Syscalls};
+use crate::threaded::{translate, Block, BlockCache, Op, Step};
+use crate::tracer::Tracer;
+use crate::trap::Traps;
+use crate::{
+    instruction_size, Access, AluOp, Bus, Console, FaultSite, Instruction, IoDevice, MachineError,
+    Object, ObjectError, Protection, SectionKind, Snapshot, SnapshotError, SyscallHandler, TrapCause, Width, CTL_TRAP_IP, NCONTROL,
+};
+use std::collections::BTreeMap;
+use std::io;
+use std::time::Instant;
 
 const MEMORY_SIZE: usize = 4096;
-const NREGS: usize = 16;
+pub(crate) const NREGS: usize = 16;
 
-const IP: usize = 0;
+pub(crate) const IP: usize = 0;
 
-pub struct Machine {
-    memory: [u8; MEMORY_SIZE],
-    regs: [u32; NREGS],
+// Reading the clock is expensive compared to a step, so deadlines are only
+// checked every DEADLINE_CHECK_INTERVAL steps.
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+/// Instruction sets understood by a [Machine]. Each level includes the
+/// previous ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Isa {
+    /// The instructions of the specification.
+    #[default]
+    Base,
+    /// Adds the arithmetic and logic instructions of [AluOp], and the 8 and
+    /// 16-bit loads and stores.
+    Extended,
 }
 
+/// Sizes and instruction set of a [Machine]. The default configuration is
+/// the one described in the specification: 4096 bytes of memory, 16
+/// registers and the base instruction set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineConfig {
+    /// Size of the memory in bytes.
+    pub memory_size: usize,
+    /// Number of registers, between 1 and 256 since instructions encode
+    /// register numbers on one byte.
+    pub nregs: usize,
+    /// Instruction set. Instructions from a higher level are rejected as
+    /// [UnsupportedInstruction](MachineError::UnsupportedInstruction).
+    pub isa: Isa,
+    /// Stack of the `push`, `pop`, `call` and `ret` instructions. Without
+    /// it, those instructions are rejected as
+    /// [UnsupportedInstruction](MachineError::UnsupportedInstruction).
+    pub stack: Option<StackConfig>,
+    /// Whether the `in` and `in_number` instructions, which are not part of
+    /// the specification, are available. Without it, they are rejected as
+    /// [UnsupportedInstruction](MachineError::UnsupportedInstruction).
+    pub input: bool,
+    /// Whether the exception model is available: the `getctl`, `setctl` and
+    /// `iret` instructions, which are not part of the specification either.
+    /// Without it, they are rejected as
+    /// [UnsupportedInstruction](MachineError::UnsupportedInstruction).
+    pub traps: bool,
+    /// Whether the `syscall` instruction, which is not part of the
+    /// specification, is available. Without it, it is rejected as
+    /// [UnsupportedInstruction](MachineError::UnsupportedInstruction) and
+    /// neither built-in nor registered syscalls can run.
+    pub syscalls: bool,
+    /// Memory permissions. Without them, every access to the memory is
+    /// allowed.
+    pub protection: Option<Protection>,
+}
 
-#[derive(Debug)]
-pub enum MachineError {
-    RegisterOutOfBounds,
-    MemoryOutOfBoundsStepOn,
-    MemoryOutOfBoundsLoad,
-    MemoryOutOfBoundsStore,
-    WrongInstruction,
-    OutOfBounds,
-    InvalidRegister(usize),
-    InvalidInstruction(u8),
-    IoError(std::io::Error),
-    // add more errors as needed
+/// Stack used by the `push`, `pop`, `call` and `ret` instructions. The
+/// stack grows downwards and ends at the end of the memory; the stack
+/// pointer holds the address of the last pushed word. The program must
+/// initialize it, usually to the memory size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackConfig {
+    /// Register used as the stack pointer.
+    pub sp: u8,
+    /// Lowest address the stack may use. Pushing below it is a
+    /// [StackOverflow](MachineError::StackOverflow).
+    pub limit: u32,
+}
+
+/// The default stack uses r2 as the stack pointer, as the provided
+/// programs do, and may grow down to address 0.
+impl Default for StackConfig {
+    fn default() -> Self {
+        StackConfig { sp: 2, limit: 0 }
+    }
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        MachineConfig {
+            memory_size: MEMORY_SIZE,
+            nregs: NREGS,
+            isa: Isa::Base,
+            stack: None,
+            input: false,
+            traps: false,
+            syscalls: false,
+            protection: None,
+        }
+    }
+}
+
+pub struct Machine {
+    memory: Vec<u8>,
+    regs: Vec<u32>,
+    // Memory writes are only recorded when this is `Some`.
+    mem_writes: Option<Vec<MemWrite>>,
+    // Memory reads, as address and width, only recorded when this is `Some`.
+    mem_reads: Option<Vec<(u32, usize)>>,
+    // Undo log, only kept when enabled.
+    history: Option<History>,
+    // Decoded instructions, when the cache is enabled.
+    decode_cache: Option<DecodeCache>,
+    // Blocks translated by run_threaded, once it has been used.
+    blocks: Option<BlockCache>,
+    // Memory-mapped devices.
+    bus: Option<Box<dyn Bus>>,
+    isa: Isa,
+    stack: Option<StackConfig>,
+    input: bool,
+    // Whether the instructions of the exception model are available.
+    trap_instructions: bool,
+    // Whether the syscall instruction is available.
+    syscall_instruction: bool,
+    // Control registers, only acted upon by run_with_traps.
+    traps: Traps,
+    syscalls: Syscalls,
+    // Memory permissions, only checked when configured.
+    permissions: Option<PermissionMap>,
+    symbols: BTreeMap<String, u32>,
+}
+
+/// A memory write done by an instruction, with the previous content.
+#[derive(Clone)]
+pub(crate) struct MemWrite {
+    pub addr: u32,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
 }
 
 
@@ -33,24 +152,122 @@ impl Machine {
     /// # Panics
     /// This function panics when `memory` is larger than the machine memory.
     pub fn new(memory: &[u8]) -> Self {
-        if memory.len() > MEMORY_SIZE {
+        Self::with_config(memory, MachineConfig::default())
+    }
+
+    /// Create a new machine with the sizes given in `config`. The `memory`
+    /// parameter will be copied at the beginning of the machine memory.
+    ///
+    /// # Panics
+    /// This function panics when `memory` is larger than the machine memory,
+    /// when the number of registers is not between 1 and 256, or when the
+    /// stack pointer is the IP or not a register.
+    pub fn with_config(memory: &[u8], config: MachineConfig) -> Self {
+        if memory.len() > config.memory_size {
             panic!("memory slice is too large for the machine memory");
         }
-    
+        if !(1..=256).contains(&config.nregs) {
+            panic!("the number of registers must be between 1 and 256");
+        }
+        if config
+            .stack
+            .is_some_and(|stack| stack.sp as usize == IP || stack.sp as usize >= config.nregs)
+        {
+            panic!("the stack pointer must be a register other than the IP");
+        }
+
         let mut machine = Machine {
-            regs: [0; NREGS],
-            memory: [0; MEMORY_SIZE],
+            regs: vec![0; config.nregs],
+            memory: vec![0; config.memory_size],
+            mem_writes: None,
+            mem_reads: None,
+            history: None,
+            decode_cache: Some(DecodeCache::new(config.memory_size)),
+            blocks: None,
+            bus: None,
+            isa: config.isa,
+            stack: config.stack,
+            input: config.input,
+            trap_instructions: config.traps,
+            syscall_instruction: config.syscalls,
+            traps: Traps::default(),
+            syscalls: Syscalls::default(),
+            permissions: config.protection.as_ref().map(|protection| {
+                PermissionMap::new(protection, config.memory_size, std::iter::once(0..memory.len()))
+            }),
+            symbols: BTreeMap::new(),
         };
-    
+
         machine.memory[..memory.len()].copy_from_slice(memory);
-    
+
         machine
     }
     
 
+    /// Create a new machine from the content of an object file, loading
+    /// its sections, starting at its entry point and knowing its symbols.
+    /// Anything which does not start with [OBJECT_MAGIC](crate::OBJECT_MAGIC)
+    /// is a raw image, loaded as with [new](Machine::new).
+    pub fn from_object(bytes: &[u8]) -> Result<Self, ObjectError> {
+        Self::from_object_with_config(bytes, MachineConfig::default())
+    }
+
+    /// Same as [from_object](Machine::from_object), with the sizes given in
+    /// `config`. When the image is protected, only the code sections are.
+    ///
+    /// # Panics
+    /// This function panics on an invalid configuration, as
+    /// [with_config](Machine::with_config).
+    pub fn from_object_with_config(
+        bytes: &[u8],
+        config: MachineConfig,
+    ) -> Result<Self, ObjectError> {
+        let object = match Object::from_bytes(bytes) {
+            Ok(object) => object,
+            Err(ObjectError::BadMagic) => {
+                if bytes.len() > config.memory_size {
+                    return Err(ObjectError::DoesNotFit {
+                        address: 0,
+                        size: bytes.len(),
+                    });
+                }
+                return Ok(Self::with_config(bytes, config));
+            }
+            Err(e) => return Err(e),
+        };
+        Self::load_object(&object, config)
+    }
+
+    /// Create a new machine running `object`.
+    ///
+    /// # Panics
+    /// This function panics on an invalid configuration, as
+    /// [with_config](Machine::with_config).
+    pub fn load_object(object: &Object, config: MachineConfig) -> Result<Self, ObjectError> {
+        object.check_loadable(config.memory_size)?;
+        let code = object
+            .sections
+            .iter()
+            .filter(|section| section.kind == SectionKind::Code)
+            .map(|section| section.address as usize..section.end() as usize);
+        let permissions = config
+            .protection
+            .as_ref()
+            .map(|protection| PermissionMap::new(protection, config.memory_size, code));
+        let mut machine = Self::with_config(&[], config);
+        for section in &object.sections {
+            let start = section.address as usize;
+            machine.memory[start..start + section.bytes.len()].copy_from_slice(&section.bytes);
+        }
+        machine.permissions = permissions;
+        machine.regs[IP] = object.entry;
+        machine.symbols.clone_from(&object.symbols);
+        Ok(machine)
+    }
+
     /// Run until the program terminates or until an error happens.
-    /// If output instructions are run, they print on `fd`.
-    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<(), MachineError> {
+    /// Input and output instructions use `fd`.
+    pub fn run_on<T: IoDevice>(&mut self, fd: &mut T) -> Result<(), MachineError> {
         loop {
             let terminated = self.step_on(fd)?;
             if terminated {
@@ -60,10 +277,228 @@ impl Machine {
         Ok(())
     }
 
+    /// Run until the program terminates, until an error happens or until
+    /// `max_steps` instructions have been executed without terminating, in
+    /// which case [StepLimitExceeded](MachineError::StepLimitExceeded) is
+    /// returned. Input and output instructions use `fd`.
+    pub fn run_with_limit<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        max_steps: u64,
+    ) -> Result<(), MachineError> {
+        self.run_with_limits(fd, Some(max_steps), None)
+    }
+
+    /// Run until the program terminates, until an error happens or until
+    /// `deadline` is reached, in which case
+    /// [DeadlineExceeded](MachineError::DeadlineExceeded) is returned.
+    /// Input and output instructions use `fd`.
+    pub fn run_with_deadline<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        deadline: Instant,
+    ) -> Result<(), MachineError> {
+        self.run_with_limits(fd, None, Some(deadline))
+    }
+
+    /// Combination of [run_with_limit](Machine::run_with_limit) and
+    /// [run_with_deadline](Machine::run_with_deadline), each limit being
+    /// optional.
+    pub fn run_with_limits<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        max_steps: Option<u64>,
+        deadline: Option<Instant>,
+    ) -> Result<(), MachineError> {
+        let mut steps: u64 = 0;
+        loop {
+            if max_steps.is_some_and(|max| steps >= max) {
+                return Err(MachineError::StepLimitExceeded { steps });
+            }
+            if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(MachineError::DeadlineExceeded { steps });
+            }
+            if self.step_on(fd)? {
+                return Ok(());
+            }
+            steps += 1;
+        }
+    }
+
+    /// Same as [run_on](Machine::run_on), with the same results, output
+    /// and memory accesses, but faster: straight-line blocks of
+    /// instructions, ending with the first one which may write the IP, are
+    /// translated once into operations whose registers are already checked,
+    /// and executed without decoding them again.
+    ///
+    /// A block is translated again when its code is modified. A program
+    /// modifying its code too often is interpreted instead, as is every
+    /// program run with the undo log enabled.
+    pub fn run_threaded<T: IoDevice>(&mut self, fd: &mut T) -> Result<(), MachineError> {
+        self.run_threaded_with_limits(fd, None, None)
+    }
+
+    /// Same as [run_with_limits](Machine::run_with_limits), executing
+    /// instructions as [run_threaded](Machine::run_threaded) does.
+    pub fn run_threaded_with_limits<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        max_steps: Option<u64>,
+        deadline: Option<Instant>,
+    ) -> Result<(), MachineError> {
+        if self.history.is_some() {
+            return self.run_with_limits(fd, max_steps, deadline);
+        }
+        let mut steps: u64 = 0;
+        loop {
+            if max_steps.is_some_and(|max| steps >= max) {
+                return Err(MachineError::StepLimitExceeded { steps });
+            }
+            if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(MachineError::DeadlineExceeded { steps });
+            }
+            // Steps which may be executed before checking the limits again.
+            let mut budget = max_steps.map_or(u64::MAX, |max| max - steps);
+            if deadline.is_some() {
+                budget = budget.min(DEADLINE_CHECK_INTERVAL - steps % DEADLINE_CHECK_INTERVAL);
+            }
+            let terminated = match self.block_at(self.regs[IP]) {
+                Some(block) => self.run_block(&block, fd, budget, &mut steps)?,
+                None => {
+                    let terminated = self.decode_and_execute(fd)?;
+                    steps += 1;
+                    terminated
+                }
+            };
+            if terminated {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Run until the program terminates or until an error happens, taking
+    /// traps as described in [step_with_traps](Machine::step_with_traps).
+    /// Input and output instructions use `fd`.
+    pub fn run_with_traps<T: IoDevice>(&mut self, fd: &mut T) -> Result<(), MachineError> {
+        while !self.step_with_traps(fd)? {}
+        Ok(())
+    }
+
+    /// Similar to [step_on](Machine::step_on), with the exception model
+    /// enabled. When the trap vector control register is set and no
+    /// handler is running:
+    ///   - a fault with a [TrapCause] does not stop the machine: the
+    ///     address of the faulting instruction, the cause and a value
+    ///     describing the fault are stored in the control registers, and
+    ///     the IP jumps to the trap vector;
+    ///   - when a timer interval is set, a timer trap is taken after that
+    ///     many steps, with the address of the next instruction saved.
+    ///
+    /// The handler returns with `iret`. A fault inside the handler, or any
+    /// fault when no trap vector is set, is returned as an error.
+    ///
+    /// Control registers are neither part of snapshots nor of the undo
+    /// log, and entering a handler is not recorded as a step.
+    pub fn step_with_traps<T: IoDevice>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+        match self.step_on(fd) {
+            Ok(true) => Ok(true),
+            Ok(false) => {
+                if self.traps.enabled() && self.traps.tick() {
+                    self.regs[IP] = self.traps.enter(TrapCause::Timer, self.regs[IP], 0);
+                }
+                Ok(false)
+            }
+            Err(e) => match TrapCause::of(&e) {
+                Some(cause) if self.traps.enabled() => {
+                    let ip = e.site().map_or(self.regs[IP], |site| site.ip);
+                    self.regs[IP] = self.traps.enter(cause, ip, TrapCause::value(&e));
+                    Ok(false)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Run until the program terminates or until an error happens, and
+    /// report every executed instruction and memory access to `tracer`.
+    /// Input and output instructions use `fd`.
+    pub fn run_traced<T: IoDevice, R: Tracer>(
+        &mut self,
+        fd: &mut T,
+        tracer: &mut R,
+    ) -> Result<(), MachineError> {
+        while !self.step_traced(fd, tracer)? {}
+        Ok(())
+    }
+
+    /// Same as [run_with_limits](Machine::run_with_limits), reporting every
+    /// step to `tracer` as [run_traced](Machine::run_traced) does.
+    pub fn run_with_limits_traced<T: IoDevice, R: Tracer>(
+        &mut self,
+        fd: &mut T,
+        tracer: &mut R,
+        max_steps: Option<u64>,
+        deadline: Option<Instant>,
+    ) -> Result<(), MachineError> {
+        let mut steps: u64 = 0;
+        loop {
+            if max_steps.is_some_and(|max| steps >= max) {
+                return Err(MachineError::StepLimitExceeded { steps });
+            }
+            if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                return Err(MachineError::DeadlineExceeded { steps });
+            }
+            if self.step_traced(fd, tracer)? {
+                return Ok(());
+            }
+            steps += 1;
+        }
+    }
+
+    /// Similar to [step_on](Machine::step_on), and report the executed
+    /// instruction and its memory accesses to `tracer`. Nothing is reported
+    /// if the instruction fails.
+    pub fn step_traced<T: IoDevice, R: Tracer>(
+        &mut self,
+        fd: &mut T,
+        tracer: &mut R,
+    ) -> Result<bool, MachineError> {
+        let ip = self.regs[IP];
+        let insn = self
+            .memory
+            .get(ip as usize..)
+            .and_then(|bytes| Some(bytes[..Instruction::decode(bytes).ok()?.1].to_vec()))
+            .unwrap_or_default();
+        let before = self.regs.clone();
+
+        self.mem_writes = Some(Vec::new());
+        self.mem_reads = Some(Vec::new());
+        let result = self.step_on(fd);
+        let writes = self.mem_writes.take().unwrap_or_default();
+        let reads = self.mem_reads.take().unwrap_or_default();
+        let terminated = result?;
+
+        for &(addr, width) in &reads {
+            tracer.on_mem_read(addr, width);
+        }
+        for write in &writes {
+            tracer.on_mem_write(write.addr, &write.old, &write.new);
+        }
+        tracer.on_step(ip, &insn, &before, &self.regs);
+        Ok(terminated)
+    }
+
     /// Run until the program terminates or until an error happens.
-    /// If output instructions are run, they print on standard output.
+    /// Input instructions read from standard input and output instructions
+    /// print on standard output.
     pub fn run(&mut self) -> Result<(), MachineError> {
-        self.run_on(&mut io::stdout().lock())
+        self.run_on(&mut Console::new(io::stdin().lock(), io::stdout().lock()))
     }
 
     /// Execute the next instruction by doing the following steps:
@@ -71,51 +506,251 @@ impl Machine {
     ///   - increment the IP by the size of the instruction
     ///   - execute the decoded instruction
     ///
-    /// If output instructions are run, they print on `fd`.
+    /// Input and output instructions use `fd`.
     /// If an error happens at either of those steps, an error is
     /// returned.
     ///
     /// In case of success, `true` is returned if the program is
     /// terminated (upon encountering an exit instruction), or
     /// `false` if the execution must continue.
-    pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
-        let instruction_ad: u32 = self.regs[IP];
-        if instruction_ad >= MEMORY_SIZE as u32 {
-            return Err(MachineError::MemoryOutOfBoundsStepOn);
-        }
-        let opcode: u8 = self.memory[instruction_ad as usize];
-        let size: u32 = decode(opcode);
-        let next_instruction_ad: u32 = instruction_ad + size;
-        self.set_reg(0, next_instruction_ad);
-        let mut b1: u8 = 0;
-        let mut b2: u8 = 0;
-        let mut b3: u8 = 0;
-        if instruction_ad + 1 < MEMORY_SIZE as u32 {
-            b1 = self.memory[(instruction_ad + 1) as usize];
-        }
-        if instruction_ad + 2 < MEMORY_SIZE as u32 {
-            b2 = self.memory[(instruction_ad + 2) as usize];
-        }
-        if instruction_ad + 3 < MEMORY_SIZE as u32 {
-            b3 = self.memory[(instruction_ad + 3) as usize];
-        }
-        match opcode {
-            1 => return self.move_(b1,b2,b3),
-            2 => return self.store(b1, b2),
-            3 => return self.load(b1,b2),
-            4 => return self.load_imm(b1, b2, b3),
-            5 => return self.sub(b1, b2, b3),
-            6 => return self.out(fd,b1),
-            7 => return self.exit(),
-            8 => return self.out_number(fd, b1),
-            _ => return Err(MachineError::WrongInstruction),
+    pub fn step_on<T: IoDevice>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+        if self.history.is_none() {
+            return self.decode_and_execute(fd);
+        }
+
+        // Collect this step's writes separately, then hand them over to
+        // an enclosing recording (from step_traced) if there is one.
+        let regs_before = self.regs.clone();
+        let outer_writes = self.mem_writes.replace(Vec::new());
+        let result = self.decode_and_execute(fd);
+        let writes = std::mem::replace(&mut self.mem_writes, outer_writes).unwrap_or_default();
+        if let Some(outer_writes) = &mut self.mem_writes {
+            outer_writes.extend(writes.iter().cloned());
+        }
+
+        let regs: Vec<(usize, u32)> = regs_before
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, old)| self.regs[i] != old)
+            .collect();
+        // A failed step is only recorded if it changed something, such as
+        // the IP being advanced before the instruction faulted.
+        if result.is_ok() || !regs.is_empty() || !writes.is_empty() {
+            if let Some(history) = &mut self.history {
+                history.push(UndoStep { regs, writes });
+            }
+        }
+        result
+    }
+
+    /// Body of [step_on](Machine::step_on), without the undo log handling.
+    /// Faults are attributed to the instruction at the IP.
+    fn decode_and_execute<T: IoDevice>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+        let ip = self.regs[IP];
+        // Cached instructions have already been fetched successfully, and
+        // the permissions of an address never change.
+        let cached = self.decode_cache.as_ref().and_then(|cache| cache.get(ip));
+        let (instruction, size) = match cached {
+            Some(decoded) => decoded,
+            None => self.fetch(ip)?,
+        };
+        self.regs[IP] = ip + size as u32;
+        let result = self.execute(instruction, fd).map_err(|e| {
+            e.at(FaultSite {
+                ip,
+                bytes: instruction.encode(),
+            })
+        });
+        if let (Ok(_), Some(bus)) = (&result, &mut self.bus) {
+            bus.tick();
+        }
+        result
+    }
+
+    /// Check that the instruction at `ip` may be executed and decode it,
+    /// caching it if enabled.
+    fn fetch(&mut self, ip: u32) -> Result<(Instruction, usize), MachineError> {
+        let bytes = self.memory.get(ip as usize..).unwrap_or_default();
+        let decoded = Instruction::decode(bytes);
+        // Bytes of the instruction, or the ones found at the IP when it
+        // cannot be decoded.
+        let len = match decoded {
+            Ok((_, size)) => size,
+            Err(_) => bytes
+                .first()
+                .map_or(0, |&opcode| instruction_size(opcode).max(1))
+                .min(bytes.len()),
+        };
+        let site = || FaultSite {
+            ip,
+            bytes: bytes[..len].to_vec(),
+        };
+        // Memory which may not be executed is refused before looking at
+        // its content.
+        self.check_access(ip, len, Access::Fetch)
+            .map_err(|e| e.at(site()))?;
+        let decoded = decoded.map_err(|e| e.at(site()))?;
+        if let Some(cache) = &mut self.decode_cache {
+            cache.insert(ip, decoded);
+        }
+        Ok(decoded)
+    }
+
+    /// Translated block starting at `ip`, translating it if needed. There
+    /// is none when the instruction at `ip` cannot be executed, or when
+    /// the code has been modified too often.
+    fn block_at(&mut self, ip: u32) -> Option<Block> {
+        let blocks = self
+            .blocks
+            .get_or_insert_with(|| BlockCache::new(self.memory.len()));
+        if blocks.gave_up() {
+            return None;
+        }
+        if let Some(block) = blocks.get(ip) {
+            return Some(block);
+        }
+        let steps = translate(&self.memory, ip, self.regs.len(), self.isa, |ip, len| {
+            self.check_access(ip, len, Access::Fetch).is_ok()
+        });
+        if steps.is_empty() {
+            return None;
+        }
+        self.blocks.as_mut().map(|blocks| blocks.insert(ip, steps))
+    }
+
+    /// Execute the steps of `block`, at most `budget` of them, counting
+    /// them in `steps`. Stops early, with the IP of the next instruction,
+    /// when the code has been modified.
+    fn run_block<T: IoDevice>(
+        &mut self,
+        block: &[Step],
+        fd: &mut T,
+        budget: u64,
+        steps: &mut u64,
+    ) -> Result<bool, MachineError> {
+        let generation = self.blocks.as_ref().map_or(0, |blocks| blocks.generation);
+        for step in block.iter().take(budget.try_into().unwrap_or(usize::MAX)) {
+            self.regs[IP] = step.next;
+            let terminated = self.execute_op(step.op, fd).map_err(|e| {
+                e.at(FaultSite {
+                    ip: step.ip,
+                    bytes: step.instruction.encode(),
+                })
+            })?;
+            if let Some(bus) = &mut self.bus {
+                bus.tick();
+            }
+            *steps += 1;
+            if terminated {
+                return Ok(true);
+            }
+            if step.op.may_write()
+                && self
+                    .blocks
+                    .as_ref()
+                    .is_some_and(|blocks| blocks.generation != generation)
+            {
+                break;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Execute a translated operation, as [execute](Machine::execute) does
+    /// for the instruction it comes from.
+    fn execute_op<T: IoDevice>(&mut self, op: Op, fd: &mut T) -> Result<bool, MachineError> {
+        match op {
+            Op::MoveIf { a, b, c } => {
+                if self.regs[c] != 0 {
+                    self.regs[a] = self.regs[b];
+                }
+            }
+            Op::LoadImm { a, value } => self.regs[a] = value,
+            Op::Sub { a, b, c } => self.regs[a] = self.regs[b].wrapping_sub(self.regs[c]),
+            Op::Alu { op, a, b, c } => self.regs[a] = alu_value(op, self.regs[b], self.regs[c])?,
+            Op::Load { a, b } => self.regs[a] = self.read_value(fd, self.regs[b], 4)?,
+            Op::Store { a, b } => self.write_value(fd, self.regs[a], 4, self.regs[b])?,
+            Op::LoadNarrow {
+                width,
+                signed,
+                a,
+                b,
+            } => {
+                let value = self.read_value(fd, self.regs[b], width.bytes())?;
+                self.regs[a] = width.extend(value, signed);
+            }
+            Op::StoreNarrow { width, a, b } => {
+                self.write_value(fd, self.regs[a], width.bytes(), self.regs[b])?
+            }
+            Op::Other(instruction) => return self.execute(instruction, fd),
+        }
+        Ok(false)
+    }
+
+    /// Execute an already decoded instruction. The IP is not advanced.
+    ///
+    /// Input and output instructions use `fd`.
+    /// Returns `true` if the program is terminated, `false` otherwise.
+    /// Since the instruction does not come from memory, errors have no
+    /// [FaultSite].
+    pub fn execute<T: IoDevice>(
+        &mut self,
+        instruction: Instruction,
+        fd: &mut T,
+    ) -> Result<bool, MachineError> {
+        if instruction.is_extended() && self.isa < Isa::Extended {
+            return Err(MachineError::UnsupportedInstruction { site: None });
+        }
+        if instruction.uses_stack() && self.stack.is_none() {
+            return Err(MachineError::UnsupportedInstruction { site: None });
+        }
+        if instruction.reads_input() && !self.input {
+            return Err(MachineError::UnsupportedInstruction { site: None });
+        }
+        if instruction.uses_traps() && !self.trap_instructions {
+            return Err(MachineError::UnsupportedInstruction { site: None });
+        }
+        if instruction == Instruction::Syscall && !self.syscall_instruction {
+            return Err(MachineError::UnsupportedInstruction { site: None });
+        }
+        match instruction {
+            Instruction::MoveIf { a, b, c } => self.move_(a, b, c),
+            Instruction::Store { a, b } => self.store(fd, a, b),
+            Instruction::Load { a, b } => self.load(fd, a, b),
+            Instruction::LoadImm { reg, value } => {
+                let [lo, hi] = value.to_le_bytes();
+                self.load_imm(reg, lo, hi)
+            }
+            Instruction::Sub { a, b, c } => self.sub(a, b, c),
+            Instruction::Out { reg } => self.out(fd, reg),
+            Instruction::Exit => self.exit(),
+            Instruction::OutNumber { reg } => self.out_number(fd, reg),
+            Instruction::In { reg } => self.in_(fd, reg),
+            Instruction::InNumber { reg } => self.in_number(fd, reg),
+            Instruction::Alu { op, a, b, c } => self.alu(op, a, b, c),
+            Instruction::Push { reg } => self.push(fd, reg),
+            Instruction::Pop { reg } => self.pop(fd, reg),
+            Instruction::Call { target } => self.call(fd, target),
+            Instruction::Ret => self.ret(fd),
+            Instruction::StoreNarrow { width, a, b } => self.store_narrow(fd, width, a, b),
+            Instruction::LoadNarrow {
+                width,
+                signed,
+                a,
+                b,
+            } => self.load_narrow(fd, width, signed, a, b),
+            Instruction::GetCtl { a, c } => self.get_ctl(a, c),
+            Instruction::SetCtl { c, a } => self.set_ctl(c, a),
+            Instruction::Iret => self.iret(),
+            Instruction::Syscall => self.syscall(fd),
         }
     }
     
     /// Similar to [step_on](Machine::step_on).
-    /// If output instructions are run, they print on standard output.
+    /// Input instructions read from standard input and output instructions
+    /// print on standard output.
     pub fn step(&mut self) -> Result<bool, MachineError> {
-        self.step_on(&mut io::stdout().lock())
+        self.step_on(&mut Console::new(io::stdin().lock(), io::stdout().lock()))
     }
 
     /// Reference onto the machine current set of regs.
@@ -125,8 +760,8 @@ impl Machine {
 
     /// Sets a register to the given value.
     pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<(), MachineError> {
-        if reg >= NREGS {
-            return Err(MachineError::InvalidRegister(reg));
+        if reg >= self.regs.len() {
+            return Err(MachineError::InvalidRegister { reg, site: None });
         }
         self.regs[reg] = value;
         Ok(())
@@ -134,79 +769,384 @@ impl Machine {
 
 
     /// Reference onto the machine current memory.
+    #[allow(clippy::needless_return)]
     pub fn memory(&self) -> &[u8] {
         return &self.memory;
     }
 
-    pub fn move_(&mut self, b1: u8, b2: u8, b3: u8) -> Result<bool, MachineError> {
-        const NREGS_U8: u8 = NREGS as u8; // store the number of registers as an u8
-        let reg_idx = &[b1, b2, b3]; // store the register indices
-        if reg_idx.iter().any(|&i| i >= NREGS_U8) {
-            return Err(MachineError::OutOfBounds);
+    /// Run `handler` when the `syscall` instruction is executed with `n` in
+    /// r1, replacing the previous handler of `n` if any, built-in ones
+    /// included. The instruction is only available when
+    /// [syscalls](MachineConfig::syscalls) are enabled.
+    ///
+    /// The memory changes made by the handler are recorded in the undo log
+    /// and reported to tracers, but memory-mapped devices are bypassed.
+    /// When memory permissions are enforced, changes to memory which is not
+    /// writable are undone and reported as a
+    /// [ProtectionFault](MachineError::ProtectionFault).
+    pub fn register_syscall(&mut self, n: u32, handler: SyscallHandler) {
+        self.syscalls.handlers.insert(n, Syscall::Host(handler));
+    }
+
+    /// Symbols of the program, when loaded from an object.
+    pub fn symbols(&self) -> &BTreeMap<String, u32> {
+        &self.symbols
+    }
+
+    /// Replace the symbols of the program, such as with the labels of its
+    /// listing.
+    pub fn set_symbols(&mut self, symbols: BTreeMap<String, u32>) {
+        self.symbols = symbols;
+    }
+
+    /// Name of `addr`, as the closest symbol at or before it followed by
+    /// the offset from it if any, such as `loop` or `print+4`.
+    pub fn symbolize(&self, addr: u32) -> Option<String> {
+        let (name, &address) = self
+            .symbols
+            .iter()
+            .filter(|&(_, &address)| address <= addr)
+            .max_by_key(|&(_, &address)| address)?;
+        Some(match addr - address {
+            0 => name.clone(),
+            offset => format!("{name}+{offset}"),
+        })
+    }
+
+    /// Values of the control registers, indexed by the `CTL_` constants.
+    pub fn control_regs(&self) -> &[u32] {
+        &self.traps.control
+    }
+
+    /// Set the value of a control register.
+    pub fn set_control(&mut self, reg: u8, value: u32) -> Result<(), MachineError> {
+        self.check_control(reg)?;
+        self.traps.set(reg, value);
+        Ok(())
+    }
+
+    /// Whether a trap handler is running, from the moment a trap is taken
+    /// until `iret`.
+    pub fn in_trap_handler(&self) -> bool {
+        self.traps.in_handler
+    }
+
+    /// Capture the registers and memory of the machine.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            regs: self.regs.clone(),
+            memory: self.memory.clone(),
+        }
+    }
+
+    /// Replace the registers and memory of the machine by the ones in
+    /// `snapshot`. The machine takes the register count and memory size of
+    /// the snapshot, which must still have the stack pointer and hold the
+    /// protected image if they are configured. The undo log, if enabled, is
+    /// emptied.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if let Some(stack) = self.stack {
+            if snapshot.regs.len() <= stack.sp as usize {
+                return Err(SnapshotError::MissingStackPointer {
+                    nregs: snapshot.regs.len(),
+                    sp: stack.sp,
+                });
+            }
+        }
+        if let Some(permissions) = &self.permissions {
+            if snapshot.memory.len() < permissions.image_end() {
+                return Err(SnapshotError::ImageDoesNotFit {
+                    memory_size: snapshot.memory.len(),
+                    image_size: permissions.image_end(),
+                });
+            }
+        }
+        self.regs.clone_from(&snapshot.regs);
+        self.memory.clone_from(&snapshot.memory);
+        if let Some(permissions) = &mut self.permissions {
+            permissions.resize(self.memory.len());
+        }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        if self.decode_cache.is_some() {
+            self.decode_cache = Some(DecodeCache::new(self.memory.len()));
+        }
+        self.blocks = None;
+        Ok(())
+    }
+
+    /// Start recording the register and memory changes of every executed
+    /// step, so that they can be undone with [step_back](Machine::step_back).
+    /// Only the last `capacity` steps are kept. If the undo log is already
+    /// enabled, its capacity is changed and the oldest steps are dropped if
+    /// needed.
+    ///
+    /// Changes made from outside, with [set_reg](Machine::set_reg) for
+    /// example, are not recorded, and output cannot be taken back.
+    pub fn enable_history(&mut self, capacity: usize) {
+        match &mut self.history {
+            Some(history) => history.set_capacity(capacity),
+            None => self.history = Some(History::new(capacity)),
+        }
+    }
+
+    /// Stop recording steps and forget the recorded ones.
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Number of steps which can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Undo the last recorded step, restoring the registers and memory as
+    /// they were before it. Returns `false` if there is nothing to undo.
+    pub fn step_back(&mut self) -> bool {
+        let Some(step) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        for write in step.writes.iter().rev() {
+            let addr = write.addr as usize;
+            self.memory[addr..addr + write.old.len()].copy_from_slice(&write.old);
+            if let Some(cache) = &mut self.decode_cache {
+                cache.invalidate(addr..addr + write.old.len());
+            }
+            if let Some(blocks) = &mut self.blocks {
+                blocks.invalidate(addr..addr + write.old.len());
+            }
+        }
+        for (reg, old) in step.regs {
+            self.regs[reg] = old;
+        }
+        true
+    }
+
+    /// Undo steps until `predicate` holds for the machine, which is checked
+    /// after every undone step. Returns `false` if the recorded history got
+    /// exhausted before that, in which case the machine is left in the
+    /// oldest recorded state.
+    pub fn run_back_until<P: FnMut(&Machine) -> bool>(&mut self, mut predicate: P) -> bool {
+        while self.step_back() {
+            if predicate(self) {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Keep the instructions decoded by [step_on](Machine::step_on) to skip
+    /// decoding them again the next time they are executed, which is the
+    /// default. Cached instructions are forgotten when the memory they were
+    /// decoded from is written, so self-modifying code keeps working.
+    pub fn enable_decode_cache(&mut self) {
+        if self.decode_cache.is_none() {
+            self.decode_cache = Some(DecodeCache::new(self.memory.len()));
+        }
+    }
+
+    /// Decode every instruction from memory when executing it.
+    pub fn disable_decode_cache(&mut self) {
+        self.decode_cache = None;
+    }
+
+    /// Map `bus` in the address space of the machine, replacing the
+    /// previous one if any. Loads and stores to the addresses it maps reach
+    /// its devices instead of the memory.
+    pub fn attach_bus(&mut self, bus: Box<dyn Bus>) {
+        self.bus = Some(bus);
+    }
+
+    /// Remove the bus from the address space and return it.
+    pub fn detach_bus(&mut self) -> Option<Box<dyn Bus>> {
+        self.bus.take()
+    }
+
+    /// Bus mapping the `width` bytes at `addr`, if any.
+    fn bus_at(&mut self, addr: u32, width: usize) -> Option<&mut Box<dyn Bus>> {
+        self.bus.as_mut().filter(|bus| bus.maps(addr, width))
+    }
+
+    /// Check that `regs` are existing registers, reporting the first one
+    /// which is not.
+    fn check_regs(&self, regs: &[u8]) -> Result<(), MachineError> {
+        match regs.iter().find(|&&reg| reg as usize >= self.regs.len()) {
+            Some(&reg) => Err(MachineError::InvalidRegister {
+                reg: reg as usize,
+                site: None,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Check that the `width` bytes starting at `addr` are in memory.
+    fn check_access(&self, addr: u32, width: usize, access: Access) -> Result<(), MachineError> {
+        match &self.permissions {
+            Some(permissions) => permissions.check(addr, width, access),
+            None => Ok(()),
+        }
+    }
+
+    fn valid_range(&self, addr: u32, width: usize) -> bool {
+        (addr as usize)
+            .checked_add(width)
+            .is_some_and(|end| end <= self.memory.len())
+    }
+
+    /// Copy `data` into memory at `addr`, recording the write if needed.
+    /// The range must have been checked by the caller.
+    fn write_memory(&mut self, addr: u32, data: &[u8]) {
+        let range = addr as usize..addr as usize + data.len();
+        if let Some(writes) = &mut self.mem_writes {
+            writes.push(MemWrite {
+                addr,
+                old: self.memory[range.clone()].to_vec(),
+                new: data.to_vec(),
+            });
+        }
+        if let Some(cache) = &mut self.decode_cache {
+            cache.invalidate(range.clone());
+        }
+        if let Some(blocks) = &mut self.blocks {
+            blocks.invalidate(range.clone());
         }
+        self.memory[range].copy_from_slice(data);
+    }
+
+    pub fn move_(&mut self, b1: u8, b2: u8, b3: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1, b2, b3])?;
         let reg_b = self.regs[b2 as usize];
         let reg_c = self.regs[b3 as usize];
         if reg_c != 0 {
-            self.set_reg(b1 as usize, reg_b);
+            self.set_reg(b1 as usize, reg_b)?;
         }
         Ok(false)
     }
 
-    pub fn store(&mut self, dest_reg: u8, src_reg: u8) -> Result<bool, MachineError> {
-        const LAST_REG: u8 = (NREGS - 1) as u8;
-        const MEM_SIZE: u32 = (MEMORY_SIZE - 4) as u32;
-    
-        if dest_reg > LAST_REG || src_reg > LAST_REG {
-            return Err(MachineError::OutOfBounds);
-        }
+    pub fn store<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        dest_reg: u8,
+        src_reg: u8,
+    ) -> Result<bool, MachineError> {
+        self.check_regs(&[dest_reg, src_reg])?;
     
         let dest_addr = self.regs[dest_reg as usize];
-        if dest_addr > MEM_SIZE {
-            return Err(MachineError::MemoryOutOfBoundsStore);
-        }
-    
         let src_data = self.regs[src_reg as usize];
-    
-        self.memory[dest_addr as usize..(dest_addr + 4) as usize].copy_from_slice(&src_data.to_le_bytes());
+        self.write_value(fd, dest_addr, 4, src_data)?;
     
         Ok(false)
     }
     
 
     /// Loads a 32-bit value from memory and stores it into a register.
-    pub fn load(&mut self, b1: u8, b2: u8) -> Result<bool, MachineError> {
-        let reg_nb: u8 = (NREGS - 1) as u8;
-        let mem_size: usize = MEMORY_SIZE - 4;
+    pub fn load<T: IoDevice>(&mut self, fd: &mut T, b1: u8, b2: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1, b2])?;
+
+        let addr = self.regs[b2 as usize];
+        self.regs[b1 as usize] = self.read_value(fd, addr, 4)?;
     
-        if b1 > reg_nb || b2 > reg_nb {
-            return Err(MachineError::RegisterOutOfBounds);
+        Ok(false)
+    }
+
+    /// Stores the low 8 or 16 bits of a register into memory.
+    pub fn store_narrow<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        width: Width,
+        dest_reg: u8,
+        src_reg: u8,
+    ) -> Result<bool, MachineError> {
+        self.check_regs(&[dest_reg, src_reg])?;
+
+        let dest_addr = self.regs[dest_reg as usize];
+        let src_data = self.regs[src_reg as usize];
+        self.write_value(fd, dest_addr, width.bytes(), src_data)?;
+
+        Ok(false)
+    }
+
+    /// Loads an 8 or 16-bit value from memory into a register, extending
+    /// it with its sign bit if `signed` is set or with zeros otherwise.
+    pub fn load_narrow<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        width: Width,
+        signed: bool,
+        b1: u8,
+        b2: u8,
+    ) -> Result<bool, MachineError> {
+        self.check_regs(&[b1, b2])?;
+
+        let addr = self.regs[b2 as usize];
+        let value = self.read_value(fd, addr, width.bytes())?;
+        self.regs[b1 as usize] = width.extend(value, signed);
+
+        Ok(false)
+    }
+
+    /// Write the `width` low bytes of `value` at `addr`, either to the bus
+    /// or to memory.
+    fn write_value<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        addr: u32,
+        width: usize,
+        value: u32,
+    ) -> Result<(), MachineError> {
+        self.check_access(addr, width, Access::Store)?;
+        if let Some(bus) = self.bus_at(addr, width) {
+            return bus.store(addr, width, value, fd);
         }
-    
-        let regb_ad: usize = self.regs[b2 as usize] as usize;
-    
-        if regb_ad > mem_size {
-            return Err(MachineError::MemoryOutOfBoundsLoad);
+        if !self.valid_range(addr, width) {
+            return Err(MachineError::MemoryOutOfBounds {
+                addr,
+                width,
+                access: Access::Store,
+                site: None,
+            });
         }
-    
+        self.write_memory(addr, &value.to_le_bytes()[..width]);
+        Ok(())
+    }
+
+    /// Read `width` bytes at `addr`, either from the bus or from memory.
+    fn read_value<T: IoDevice>(
+        &mut self,
+        fd: &mut T,
+        addr: u32,
+        width: usize,
+    ) -> Result<u32, MachineError> {
+        self.check_access(addr, width, Access::Load)?;
+        if let Some(bus) = self.bus_at(addr, width) {
+            return bus.load(addr, width, fd);
+        }
+        if !self.valid_range(addr, width) {
+            return Err(MachineError::MemoryOutOfBounds {
+                addr,
+                width,
+                access: Access::Load,
+                site: None,
+            });
+        }
+        if let Some(reads) = &mut self.mem_reads {
+            reads.push((addr, width));
+        }
+        let addr = addr as usize;
         let mut value: u32 = 0;
-        for i in 0..4 {
-            value |= (self.memory[regb_ad + i] as u32) << (i * 8);
+        for i in 0..width {
+            value |= (self.memory[addr + i] as u32) << (i * 8);
         }
-    
-        self.regs[b1 as usize] = value;
-    
-        Ok(false)
+        Ok(value)
     }
     
 
 
+    #[allow(clippy::needless_return)]
     pub fn load_imm(&mut self, b1: u8, b2: u8, b3: u8) -> Result<bool, MachineError> {
 
-        let reg_nb: u8 = (NREGS - 1) as u8;
-
-        if b1 > reg_nb {
-            return Err(MachineError::OutOfBounds);
-        }
+        self.check_regs(&[b1])?;
 
         self.regs[b1 as usize]  = ((b3 as i16) << 8 | (b2 as i16)) as u32;
 
@@ -214,13 +1154,10 @@ impl Machine {
     }
 
 
+    #[allow(clippy::needless_return)]
     pub fn sub(&mut self, b1: u8, b2: u8, b3: u8) -> Result<bool, MachineError> {
 
-        let reg_nb: u8 = (NREGS - 1) as u8;
-    
-        if b1 > reg_nb || b2 > reg_nb || b3 > reg_nb {
-            return Err(MachineError::OutOfBounds);
-        }
+        self.check_regs(&[b1, b2, b3])?;
     
         let regc_data: i64 = self.regs[b3 as usize] as i64;
         let regb_data: i64 = self.regs[b2 as usize] as i64;
@@ -231,59 +1168,292 @@ impl Machine {
     }
     
 
-    pub fn out<T: Write>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
-        let reg_nb: u8 = (NREGS - 1) as u8;
-    
-        if b1 > reg_nb {
-            return Err(MachineError::OutOfBounds);
-        }
+    /// Computes `ra <- rb OP rc` for an instruction of the extended set.
+    /// Shifting by 32 or more gives 0, or -1 for an arithmetic shift of a
+    /// negative number.
+    pub fn alu(&mut self, op: AluOp, b1: u8, b2: u8, b3: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1, b2, b3])?;
+
+        let regb_data = self.regs[b2 as usize];
+        let regc_data = self.regs[b3 as usize];
+        self.regs[b1 as usize] = alu_value(op, regb_data, regc_data)?;
+
+        Ok(false)
+    }
+
+    pub fn out<T: IoDevice>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1])?;
     
         let rega_data: u8 = self.regs[b1 as usize] as u8;
         let c: char = rega_data as char;
         let mut buf: [u8; 4] = [0; 4];
         let str = c.encode_utf8(&mut buf);
         
-        match fd.write(str.as_bytes()) {
-            Ok(_) => Ok(false),
-            Err(err) => Err(MachineError::IoError(err.into())),
-        }
+        fd.write_output(str.as_bytes())?;
+
+        Ok(false)
     }
 
+    #[allow(clippy::needless_return)]
     pub fn exit(&mut self) -> Result<bool, MachineError> {
         return Ok(true);
     }
 
-    pub fn out_number<T: Write>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
-        let reg_nb: u8 = (NREGS - 1) as u8;
+    pub fn out_number<T: IoDevice>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1])?;
+
+        let rega_data: i32 = self.regs[b1 as usize] as i32;
+
+        fd.write_output(rega_data.to_string().as_bytes())?;
+
+        Ok(false)
+    }
+
+    /// Reads one byte of input into a register, or -1 at the end of the
+    /// input.
+    pub fn in_<T: IoDevice>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1])?;
+
+        let byte = fd.read_input()?;
+        self.regs[b1 as usize] = byte.map_or(u32::MAX, u32::from);
+
+        Ok(false)
+    }
+
+    /// Reads a decimal number, optionally preceded by whitespace and a
+    /// sign, into a register. The input stops right after the last digit.
+    /// Numbers must fit in 32 bits, either signed or unsigned.
+    pub fn in_number<T: IoDevice>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1])?;
 
-        if b1 > reg_nb {
-            return Err(MachineError::OutOfBounds);
+        let text = read_number_text(fd)?;
+        let value = text
+            .parse::<i64>()
+            .ok()
+            .filter(|value| (i32::MIN as i64..=u32::MAX as i64).contains(value))
+            .ok_or(MachineError::InvalidInput { site: None })?;
+        self.regs[b1 as usize] = value as u32;
+
+        Ok(false)
+    }
+
+    /// Pushes a register onto the stack.
+    pub fn push<T: IoDevice>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1])?;
+
+        let value = self.regs[b1 as usize];
+        self.push_word(fd, value)?;
+
+        Ok(false)
+    }
+
+    /// Pops the word on top of the stack into a register.
+    pub fn pop<T: IoDevice>(&mut self, fd: &mut T, b1: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1])?;
+
+        self.regs[b1 as usize] = self.pop_word(fd)?;
+
+        Ok(false)
+    }
+
+    /// Pushes the return address, which is the current IP, and jumps to
+    /// `target`.
+    pub fn call<T: IoDevice>(&mut self, fd: &mut T, target: u16) -> Result<bool, MachineError> {
+        self.push_word(fd, self.regs[IP])?;
+        self.regs[IP] = target as u32;
+
+        Ok(false)
+    }
+
+    /// Pops the return address pushed by `call` into the IP.
+    pub fn ret<T: IoDevice>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+        self.regs[IP] = self.pop_word(fd)?;
+
+        Ok(false)
+    }
+
+    /// Runs the handler of the syscall whose number is in r1.
+    pub fn syscall<T: IoDevice>(&mut self, fd: &mut T) -> Result<bool, MachineError> {
+        self.check_regs(&[1])?;
+
+        let number = self.regs[1];
+        let syscall = self
+            .syscalls
+            .handlers
+            .get_mut(&number)
+            .ok_or(MachineError::UnknownSyscall { number, site: None })?;
+        match syscall {
+            Syscall::Print => {
+                let addr = arg(&self.regs, 3)?;
+                let text = c_string(&self.memory, addr)?;
+                self.check_access(addr, text.len() + 1, Access::Load)?;
+                if let Some(reads) = &mut self.mem_reads {
+                    reads.push((addr, text.len() + 1));
+                }
+                fd.write_output(text)?;
+                self.regs[1] = text.len() as u32;
+            }
+            Syscall::Host(handler) => {
+                let before = (self.mem_writes.is_some() || self.permissions.is_some())
+                    .then(|| self.memory.clone());
+                let result = handler(&mut self.regs, &mut self.memory);
+                if let Some(cache) = &mut self.decode_cache {
+                    cache.clear();
+                }
+                if let Some(blocks) = &mut self.blocks {
+                    blocks.clear();
+                }
+                if let Some(before) = before {
+                    let changed = changes(&before, &self.memory);
+                    // Writes the program could not have made are undone.
+                    let denied = changed.iter().find_map(|write| {
+                        self.check_access(write.addr, write.new.len(), Access::Store).err()
+                    });
+                    if let Some(error) = denied {
+                        self.memory = before;
+                        return Err(error);
+                    }
+                    if let Some(writes) = &mut self.mem_writes {
+                        writes.extend(changed);
+                    }
+                }
+                result?;
+            }
         }
 
-        let rega_data: i32 = self.regs[b1 as usize] as i32;
+        Ok(false)
+    }
 
-        fd.write(rega_data.to_string().as_bytes())
-            .map_err(|e| MachineError::IoError(e))?;
+    /// Copies a control register into a register.
+    pub fn get_ctl(&mut self, b1: u8, b2: u8) -> Result<bool, MachineError> {
+        self.check_regs(&[b1])?;
+        self.check_control(b2)?;
+
+        self.regs[b1 as usize] = self.traps.control[b2 as usize];
 
         Ok(false)
     }
 
-        
-}
+    /// Copies a register into a control register.
+    pub fn set_ctl(&mut self, b1: u8, b2: u8) -> Result<bool, MachineError> {
+        self.check_control(b1)?;
+        self.check_regs(&[b2])?;
+
+        self.traps.set(b1, self.regs[b2 as usize]);
+
+        Ok(false)
+    }
+
+    /// Returns from a trap handler to the saved IP.
+    pub fn iret(&mut self) -> Result<bool, MachineError> {
+        self.regs[IP] = self.traps.control[CTL_TRAP_IP as usize];
+        self.traps.in_handler = false;
+
+        Ok(false)
+    }
+
+    fn check_control(&self, reg: u8) -> Result<(), MachineError> {
+        if reg as usize >= NCONTROL {
+            return Err(MachineError::InvalidControlRegister { reg, site: None });
+        }
+        Ok(())
+    }
+
+    /// Stack pointer register and lowest stack address. Only called for
+    /// stack instructions, which are rejected without a stack.
+    fn stack(&self) -> (usize, u32) {
+        let stack = self.stack.expect("no stack configured");
+        (stack.sp as usize, stack.limit)
+    }
 
-fn decode(opcode: u8) -> u32 {
+    /// Decrement the stack pointer and write `value` where it points. The
+    /// stack pointer is left unchanged if the write fails.
+    fn push_word<T: IoDevice>(&mut self, fd: &mut T, value: u32) -> Result<(), MachineError> {
+        let (sp, limit) = self.stack();
+        let addr = self.regs[sp]
+            .checked_sub(4)
+            .filter(|&addr| addr >= limit)
+            .ok_or(MachineError::StackOverflow {
+                sp: self.regs[sp],
+                site: None,
+            })?;
+        self.write_value(fd, addr, 4, value)?;
+        self.regs[sp] = addr;
+        Ok(())
+    }
+
+    /// Read the word the stack pointer points to and increment it.
+    fn pop_word<T: IoDevice>(&mut self, fd: &mut T) -> Result<u32, MachineError> {
+        let (sp, _) = self.stack();
+        let addr = self.regs[sp];
+        if !self.valid_range(addr, 4) {
+            return Err(MachineError::StackUnderflow { sp: addr, site: None });
+        }
+        let value = self.read_value(fd, addr, 4)?;
+        self.regs[sp] = addr + 4;
+        Ok(value)
+    }
 
-    let size: u32;
+        
+}
 
-    match opcode {
+/// Result of `b OP c`, as computed by [alu](Machine::alu).
+fn alu_value(op: AluOp, b: u32, c: u32) -> Result<u32, MachineError> {
+    Ok(match op {
+        AluOp::Add => b.wrapping_add(c),
+        AluOp::And => b & c,
+        AluOp::Or => b | c,
+        AluOp::Xor => b ^ c,
+        AluOp::Shl => b.checked_shl(c).unwrap_or(0),
+        AluOp::Shr => b.checked_shr(c).unwrap_or(0),
+        AluOp::Sar => ((b as i32) >> c.min(31)) as u32,
+        AluOp::Slt => ((b as i32) < (c as i32)) as u32,
+        AluOp::Sltu => (b < c) as u32,
+        AluOp::Mul => b.wrapping_mul(c),
+        AluOp::Div | AluOp::Mod if c == 0 => {
+            return Err(MachineError::DivisionByZero { site: None })
+        }
+        AluOp::Div => (b as i32).wrapping_div(c as i32) as u32,
+        AluOp::Mod => (b as i32).wrapping_rem(c as i32) as u32,
+    })
+}
 
-        1 | 4 | 5  => size = 4, 
-        2 | 3 => size = 3, 
-        6 | 8 => size = 2, 
-        7 => size = 1,
-        _ => size = 0,
-         
+/// Memory writes turning `before` into `after`, one per changed range.
+fn changes(before: &[u8], after: &[u8]) -> Vec<MemWrite> {
+    let mut writes = Vec::new();
+    let mut i = 0;
+    while i < before.len() {
+        if before[i] == after[i] {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < before.len() && before[i] != after[i] {
+            i += 1;
+        }
+        writes.push(MemWrite {
+            addr: start as u32,
+            old: before[start..i].to_vec(),
+            new: after[start..i].to_vec(),
+        });
     }
-    return size;
+    writes
 }
 
+/// Consume the text of a decimal number from the input of `fd`: leading
+/// whitespace, an optional sign and digits. Whitespace is not returned.
+fn read_number_text<T: IoDevice>(fd: &mut T) -> io::Result<String> {
+    while fd.peek_input()?.is_some_and(|b| b.is_ascii_whitespace()) {
+        fd.read_input()?;
+    }
+    let mut text = String::new();
+    if let Some(sign @ (b'-' | b'+')) = fd.peek_input()? {
+        fd.read_input()?;
+        text.push(sign as char);
+    }
+    while let Some(digit) = fd.peek_input()?.filter(u8::is_ascii_digit) {
+        fd.read_input()?;
+        text.push(digit as char);
+    }
+    Ok(text)
+}
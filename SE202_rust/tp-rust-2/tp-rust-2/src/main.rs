@@ -1,19 +1,208 @@
-use interpreter::{Machine, MachineError};
-use std::fs::File;
-use std::io::Read;
+use interpreter::options::{labels, MachineOptions, USAGE as MACHINE_USAGE};
+use interpreter::profiler::Profiler;
+use interpreter::{Console, Instruction, Machine, MachineError, Snapshot};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::process::exit;
+use std::time::{Duration, Instant};
 
-fn main() -> Result<(), MachineError> {
-    // Take a filename as argument on the command line
-    let filename = std::env::args().nth(1).unwrap();
+const USAGE: &str = "\
+usage: tp-rust-2 [OPTIONS] program.bin|program.obj
+       tp-rust-2 [OPTIONS] --load-snapshot FILE
+options:
+  --max-steps N                  stop after N steps
+  --timeout SECONDS              stop after SECONDS seconds
+  --save-snapshot-on-exit FILE   save the machine state when the run stops
+  --profile                      print the most executed instructions and the
+                                 most accessed addresses after the run
+  --profile-folded FILE          write the call stacks of the run to FILE, for
+                                 flamegraph tools";
 
-    // Read content to buffer
-    let mut fs = File::open(&filename).unwrap();
-    let mut buffer = Vec::new();
-    fs.read_to_end(&mut buffer).unwrap();
+/// Command line options.
+struct Options {
+    filename: Option<String>,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    load_snapshot: Option<String>,
+    save_snapshot: Option<String>,
+    machine: MachineOptions,
+    profile: bool,
+    profile_folded: Option<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut filename = None;
+    let mut max_steps = None;
+    let mut timeout = None;
+    let mut load_snapshot = None;
+    let mut save_snapshot = None;
+    let mut machine = MachineOptions::default();
+    let mut profile = false;
+    let mut profile_folded = None;
+    while let Some(arg) = args.next() {
+        if machine.parse(&arg, &mut args)? {
+            continue;
+        }
+        match arg.as_str() {
+            "--max-steps" => {
+                let value = args.next().ok_or("--max-steps needs a value")?;
+                let steps = value
+                    .parse()
+                    .map_err(|_| format!("invalid step count `{value}`"))?;
+                max_steps = Some(steps);
+            }
+            "--timeout" => {
+                let value = args.next().ok_or("--timeout needs a value")?;
+                let seconds = value
+                    .parse()
+                    .ok()
+                    .and_then(|s| Duration::try_from_secs_f64(s).ok())
+                    .ok_or_else(|| format!("invalid timeout `{value}`"))?;
+                timeout = Some(seconds);
+            }
+            "--load-snapshot" => {
+                load_snapshot = Some(args.next().ok_or("--load-snapshot needs a file")?);
+            }
+            "--save-snapshot-on-exit" => {
+                save_snapshot = Some(args.next().ok_or("--save-snapshot-on-exit needs a file")?);
+            }
+            "--profile" => profile = true,
+            "--profile-folded" => {
+                profile_folded = Some(args.next().ok_or("--profile-folded needs a file")?);
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option `{arg}`")),
+            _ if filename.is_none() => filename = Some(arg),
+            _ => return Err("only one program can be given".to_string()),
+        }
+    }
+    if filename.is_some() == load_snapshot.is_some() {
+        return Err("either a program or --load-snapshot must be given".to_string());
+    }
+    Ok(Options {
+        filename,
+        max_steps,
+        timeout,
+        load_snapshot,
+        save_snapshot,
+        machine,
+        profile,
+        profile_folded,
+    })
+}
+
+/// Describe why the machine stopped: the error, the faulting instruction
+/// and the registers.
+fn crash_report(machine: &Machine, error: &MachineError) -> String {
+    let mut report = format!("error: {error}\n");
+    if let Some(site) = error.site() {
+        if let Ok((instruction, _)) = Instruction::decode(&site.bytes) {
+            let symbol = machine
+                .symbolize(site.ip)
+                .map(|symbol| format!(" <{symbol}>"))
+                .unwrap_or_default();
+            report += &format!("instruction: {:04}{symbol}   {instruction}\n", site.ip);
+        }
+    }
+    report += "registers:\n";
+    for (i, row) in machine.regs().chunks(4).enumerate() {
+        for (j, value) in row.iter().enumerate() {
+            let reg = format!("r{}", 4 * i + j);
+            report += &format!("  {reg:>3} = 0x{value:08x}");
+        }
+        report += "\n";
+    }
+    report
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}\n{MACHINE_USAGE}");
+        exit(2);
+    });
+
+    let config = options.machine.config();
+    let mut labels = BTreeMap::new();
+    let mut machine = match (&options.filename, &options.load_snapshot) {
+        (Some(filename), _) => {
+            // Read content to buffer
+            let buffer = std::fs::read(filename).unwrap_or_else(|e| {
+                eprintln!("{filename}: {e}");
+                exit(1);
+            });
+
+            // Create a machine with this object or memory content
+            let machine = Machine::from_object_with_config(&buffer, config).unwrap_or_else(|e| {
+                eprintln!("{filename}: {e}");
+                exit(1);
+            });
+            if options.profile || options.profile_folded.is_some() {
+                labels = self::labels(Path::new(filename), &buffer, &machine);
+            }
+            machine
+        }
+        (None, Some(path)) => {
+            // Resume the machine from a previous snapshot
+            let bytes = std::fs::read(path).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                exit(1);
+            });
+            let snapshot = Snapshot::from_bytes(&bytes).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                exit(1);
+            });
+            let mut machine = Machine::with_config(&[], config);
+            machine.restore(&snapshot).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                exit(1);
+            });
+            machine
+        }
+        (None, None) => unreachable!(),
+    };
+    options.machine.attach(&mut machine);
+
+    // Run the machine until the end, or until a limit is reached
+    let deadline = options.timeout.map(|timeout| Instant::now() + timeout);
+    let mut console = Console::new(io::stdin().lock(), io::stdout().lock());
+    let mut profiler =
+        (options.profile || options.profile_folded.is_some()).then(|| Profiler::new(&labels));
+    let result = match &mut profiler {
+        Some(profiler) => {
+            machine.run_with_limits_traced(&mut console, profiler, options.max_steps, deadline)
+        }
+        None => machine.run_with_limits(&mut console, options.max_steps, deadline),
+    };
+    drop(console);
 
-    // Create a machine with this memory content
-    let mut machine = Machine::new(&buffer);
+    // The profile covers the run until it stopped, whatever the reason.
+    if let Some(profiler) = &profiler {
+        if options.profile {
+            eprint!("{}", profiler.report());
+        }
+        if let Some(path) = &options.profile_folded {
+            std::fs::write(path, profiler.folded()).unwrap_or_else(|e| {
+                eprintln!("{path}: {e}");
+                exit(1);
+            });
+        }
+    }
 
-    // Run the machine until the end
-    machine.run()
+    // The snapshot is saved however the run ended, so that a program
+    // stopped by --max-steps can be resumed later.
+    let mut saved = true;
+    if let Some(path) = &options.save_snapshot {
+        if let Err(e) = std::fs::write(path, machine.snapshot().to_bytes()) {
+            eprintln!("{path}: {e}");
+            saved = false;
+        }
+    }
+    if let Err(e) = result {
+        eprint!("{}", crash_report(&machine, &e));
+        exit(1);
+    }
+    if !saved {
+        exit(1);
+    }
 }
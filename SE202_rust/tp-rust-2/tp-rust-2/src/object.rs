@@ -0,0 +1,379 @@
+use crate::assembler::Program;
+use crate::snapshot::crc32;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Magic header at the beginning of every object file.
+pub const OBJECT_MAGIC: &[u8; 8] = b"TPVMOBJF";
+
+/// Version of the object file format written by [Object::to_bytes].
+pub const OBJECT_VERSION: u16 = 2;
+
+/// Size of the header: magic, version, number of sections, entry point,
+/// number of symbols and number of relocations.
+const HEADER_SIZE: usize = 8 + 2 + 2 + 4 + 4 + 4;
+
+/// Flag of the symbols visible from other objects.
+const GLOBAL: u8 = 1;
+
+/// Maximum length in bytes of a symbol name in an object file.
+pub const MAX_SYMBOL_NAME: usize = 255;
+
+/// Content of a [Section].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionKind {
+    /// Instructions, executable and read-only when the image is protected.
+    Code = 0,
+    /// Data, never protected.
+    Data = 1,
+}
+
+/// Bytes to load at `address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub address: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Section {
+    /// Address following the section.
+    pub fn end(&self) -> u64 {
+        self.address as u64 + self.bytes.len() as u64
+    }
+}
+
+/// Reference to `symbol` by the `loadimm` or `call` instruction at
+/// `address`, whose immediate is the address of the symbol once linked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub address: u32,
+    pub symbol: String,
+}
+
+/// Program with its sections, entry point and symbols, as loaded by
+/// [Machine::from_object](crate::Machine::from_object).
+///
+/// The file format is, with every integer stored in little-endian order:
+///
+/// | size         | content                                   |
+/// |--------------|-------------------------------------------|
+/// | 8            | magic `TPVMOBJF`                          |
+/// | 2            | format version (2)                        |
+/// | 2            | number of sections `s`                    |
+/// | 4            | entry point                               |
+/// | 4            | number of symbols `n`                     |
+/// | 4            | number of relocations `r`                 |
+/// | `s` times    | kind (1 byte, 0 for code and 1 for data), |
+/// |              | load address (4), size `m` (4), `m` bytes |
+/// | `n` times    | address (4), flags (1, 1 for global),     |
+/// |              | name length `l` (1), name                 |
+/// | `r` times    | instruction address (4), symbol name      |
+/// |              | length `l` (1), symbol name               |
+/// | 4            | CRC-32 of everything before               |
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Object {
+    /// Initial value of the IP.
+    pub entry: u32,
+    pub sections: Vec<Section>,
+    /// Address of every symbol.
+    pub symbols: BTreeMap<String, u32>,
+    /// Symbols visible from other objects when linking.
+    pub globals: BTreeSet<String>,
+    /// Symbol references to update when linking. A fully linked object has
+    /// none.
+    pub relocations: Vec<Relocation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingData,
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    InvalidSectionKind(u8),
+    InvalidSymbolName,
+    /// More sections than the file format can hold.
+    TooManySections(usize),
+    /// A symbol name is longer than [MAX_SYMBOL_NAME].
+    SymbolNameTooLong(String),
+    /// The object refers to a symbol it does not define, and must be
+    /// linked before being loaded.
+    UndefinedSymbol(String),
+    /// A relocation does not point to a `loadimm` or `call` instruction in
+    /// a section.
+    InvalidRelocation {
+        address: u32,
+    },
+    /// Two sections are loaded at `address`.
+    OverlappingSections {
+        address: u32,
+    },
+    /// The `size` bytes to load at `address` go past the end of the memory.
+    DoesNotFit {
+        address: u32,
+        size: usize,
+    },
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectError::BadMagic => write!(f, "not an object file"),
+            ObjectError::UnsupportedVersion(v) => write!(f, "unsupported object version {v}"),
+            ObjectError::Truncated => write!(f, "truncated object file"),
+            ObjectError::TrailingData => write!(f, "unexpected data after the object file"),
+            ObjectError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "object checksum mismatch (expected {expected:08x}, found {found:08x})"
+            ),
+            ObjectError::InvalidSectionKind(kind) => write!(f, "invalid section kind {kind}"),
+            ObjectError::InvalidSymbolName => write!(f, "invalid symbol name"),
+            ObjectError::TooManySections(n) => write!(f, "too many sections ({n})"),
+            ObjectError::SymbolNameTooLong(name) => write!(
+                f,
+                "symbol name `{name}` is longer than {MAX_SYMBOL_NAME} bytes"
+            ),
+            ObjectError::UndefinedSymbol(name) => {
+                write!(
+                    f,
+                    "symbol `{name}` is not defined, the object must be linked"
+                )
+            }
+            ObjectError::InvalidRelocation { address } => {
+                write!(f, "invalid relocation at address {address}")
+            }
+            ObjectError::OverlappingSections { address } => {
+                write!(f, "sections overlap at address {address}")
+            }
+            ObjectError::DoesNotFit { address, size } => write!(
+                f,
+                "{size} bytes at address {address} do not fit in the machine memory"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ObjectError {}
+
+impl Object {
+    /// Object of an assembled program: its `.code` and `.data` parts become
+    /// sections loaded at address 0 onwards, its labels become symbols, its
+    /// label references relocations, and execution starts at address 0.
+    pub fn from_program(program: &Program) -> Self {
+        let len = program.code.len() as u32;
+        let mut boundaries: Vec<u32> = program
+            .data
+            .iter()
+            .flat_map(|data| [data.start, data.end])
+            .chain([0, len])
+            .filter(|&address| address <= len)
+            .collect();
+        boundaries.sort();
+        boundaries.dedup();
+        let sections = boundaries
+            .windows(2)
+            .map(|pair| {
+                let is_data = program.data.iter().any(|data| data.contains(&pair[0]));
+                Section {
+                    kind: if is_data {
+                        SectionKind::Data
+                    } else {
+                        SectionKind::Code
+                    },
+                    address: pair[0],
+                    bytes: program.code[pair[0] as usize..pair[1] as usize].to_vec(),
+                }
+            })
+            .collect();
+        Object {
+            entry: 0,
+            sections,
+            symbols: program.labels.clone(),
+            globals: program.globals.clone(),
+            relocations: program.relocations.clone(),
+        }
+    }
+
+    /// Serialize the object in the current file format, which holds up to
+    /// 65535 sections and symbol names of up to [MAX_SYMBOL_NAME] bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ObjectError> {
+        let mut bytes = Vec::new();
+        bytes.extend(OBJECT_MAGIC);
+        bytes.extend(OBJECT_VERSION.to_le_bytes());
+        let nsections = u16::try_from(self.sections.len())
+            .map_err(|_| ObjectError::TooManySections(self.sections.len()))?;
+        bytes.extend(nsections.to_le_bytes());
+        bytes.extend(self.entry.to_le_bytes());
+        bytes.extend((self.symbols.len() as u32).to_le_bytes());
+        bytes.extend((self.relocations.len() as u32).to_le_bytes());
+        for section in &self.sections {
+            bytes.push(section.kind as u8);
+            bytes.extend(section.address.to_le_bytes());
+            bytes.extend((section.bytes.len() as u32).to_le_bytes());
+            bytes.extend(&section.bytes);
+        }
+        let name = |bytes: &mut Vec<u8>, name: &str| {
+            if name.len() > MAX_SYMBOL_NAME {
+                return Err(ObjectError::SymbolNameTooLong(name.to_string()));
+            }
+            bytes.push(name.len() as u8);
+            bytes.extend(name.as_bytes());
+            Ok(())
+        };
+        for (symbol, address) in &self.symbols {
+            bytes.extend(address.to_le_bytes());
+            bytes.push(if self.globals.contains(symbol) {
+                GLOBAL
+            } else {
+                0
+            });
+            name(&mut bytes, symbol)?;
+        }
+        for relocation in &self.relocations {
+            bytes.extend(relocation.address.to_le_bytes());
+            name(&mut bytes, &relocation.symbol)?;
+        }
+        let checksum = crc32(&bytes);
+        bytes.extend(checksum.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Parse an object written by [to_bytes](Object::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Object, ObjectError> {
+        if !bytes.starts_with(OBJECT_MAGIC) {
+            return Err(ObjectError::BadMagic);
+        }
+        if bytes.len() < HEADER_SIZE + 4 {
+            return Err(ObjectError::Truncated);
+        }
+        let (content, checksum) = bytes.split_at(bytes.len() - 4);
+        let mut reader = Reader {
+            bytes: content,
+            pos: OBJECT_MAGIC.len(),
+        };
+        let version = reader.u16()?;
+        if version != OBJECT_VERSION {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+        let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+        let found = crc32(content);
+        if expected != found {
+            return Err(ObjectError::ChecksumMismatch { expected, found });
+        }
+        let nsections = reader.u16()?;
+        let entry = reader.u32()?;
+        let nsymbols = reader.u32()?;
+        let nrelocations = reader.u32()?;
+        let mut sections = Vec::new();
+        for _ in 0..nsections {
+            let kind = match reader.take(1)?[0] {
+                0 => SectionKind::Code,
+                1 => SectionKind::Data,
+                kind => return Err(ObjectError::InvalidSectionKind(kind)),
+            };
+            let address = reader.u32()?;
+            let size = reader.u32()? as usize;
+            let bytes = reader.take(size)?.to_vec();
+            sections.push(Section {
+                kind,
+                address,
+                bytes,
+            });
+        }
+        let mut symbols = BTreeMap::new();
+        let mut globals = BTreeSet::new();
+        for _ in 0..nsymbols {
+            let address = reader.u32()?;
+            let flags = reader.take(1)?[0];
+            let name = reader.name()?;
+            if flags & GLOBAL != 0 {
+                globals.insert(name.clone());
+            }
+            symbols.insert(name, address);
+        }
+        let mut relocations = Vec::new();
+        for _ in 0..nrelocations {
+            let address = reader.u32()?;
+            let symbol = reader.name()?;
+            relocations.push(Relocation { address, symbol });
+        }
+        if reader.pos != content.len() {
+            return Err(ObjectError::TrailingData);
+        }
+        Ok(Object {
+            entry,
+            sections,
+            symbols,
+            globals,
+            relocations,
+        })
+    }
+
+    /// Check that the sections fit in a memory of `memory_size` bytes
+    /// without overlapping, and that every referenced symbol is defined.
+    pub fn check_loadable(&self, memory_size: usize) -> Result<(), ObjectError> {
+        if let Some(relocation) = self
+            .relocations
+            .iter()
+            .find(|relocation| !self.symbols.contains_key(&relocation.symbol))
+        {
+            return Err(ObjectError::UndefinedSymbol(relocation.symbol.clone()));
+        }
+        let mut sections: Vec<&Section> = self.sections.iter().collect();
+        sections.sort_by_key(|section| section.address);
+        for section in &sections {
+            if section.end() > memory_size as u64 {
+                return Err(ObjectError::DoesNotFit {
+                    address: section.address,
+                    size: section.bytes.len(),
+                });
+            }
+        }
+        for pair in sections.windows(2) {
+            if pair[0].end() > pair[1].address as u64 {
+                return Err(ObjectError::OverlappingSections {
+                    address: pair[1].address,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Little-endian reader failing with [ObjectError::Truncated].
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ObjectError> {
+        let bytes = self
+            .bytes
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(ObjectError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Name preceded by its length.
+    fn name(&mut self) -> Result<String, ObjectError> {
+        let len = self.take(1)?[0] as usize;
+        std::str::from_utf8(self.take(len)?)
+            .map(str::to_string)
+            .map_err(|_| ObjectError::InvalidSymbolName)
+    }
+}
@@ -0,0 +1,113 @@
+//! Command line options configuring the machine, and the recovery of the
+//! program labels, shared by the programs running it.
+
+use crate::assembler::assemble_program;
+use crate::{
+    read_file_syscall, Devices, Isa, Machine, MachineConfig, Protection, StackConfig, OBJECT_MAGIC,
+    SYS_READ_FILE,
+};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Usage of the options understood by [MachineOptions::parse].
+pub const USAGE: &str = "\
+  --devices                      map the console port, cycle counter and random
+                                 source at 0xff0
+  --isa base|extended            instruction set (default base)
+  --stack REG                    enable push, pop, call and ret with REG (such
+                                 as r2) as the stack pointer
+  --input                        enable in and in_number
+  --syscalls                     enable syscall, with the print and time
+                                 syscalls
+  --sandbox DIR                  enable syscall, letting the read file syscall
+                                 access the files in DIR
+  --protect-image                make the program read-only and executable";
+
+/// How to configure a machine and what to attach to it.
+#[derive(Debug, Clone, Default)]
+pub struct MachineOptions {
+    pub devices: bool,
+    pub isa: Isa,
+    pub stack: Option<StackConfig>,
+    pub input: bool,
+    pub syscalls: bool,
+    pub sandbox: Option<String>,
+    pub protect_image: bool,
+}
+
+impl MachineOptions {
+    /// Parse `arg` if it is one of the machine options, taking its value
+    /// from `args`. Return whether it was one.
+    pub fn parse(
+        &mut self,
+        arg: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> Result<bool, String> {
+        match arg {
+            "--devices" => self.devices = true,
+            "--isa" => {
+                self.isa = match args.next().as_deref() {
+                    Some("base") => Isa::Base,
+                    Some("extended") => Isa::Extended,
+                    _ => return Err("--isa needs `base` or `extended`".to_string()),
+                };
+            }
+            "--stack" => {
+                let value = args.next().ok_or("--stack needs a register")?;
+                let sp = value
+                    .strip_prefix('r')
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n: &u8| n != 0 && (n as usize) < MachineConfig::default().nregs)
+                    .ok_or_else(|| format!("invalid stack pointer `{value}`"))?;
+                self.stack = Some(StackConfig {
+                    sp,
+                    ..StackConfig::default()
+                });
+            }
+            "--input" => self.input = true,
+            "--syscalls" => self.syscalls = true,
+            "--sandbox" => self.sandbox = Some(args.next().ok_or("--sandbox needs a directory")?),
+            "--protect-image" => self.protect_image = true,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Configuration of the machine to create.
+    pub fn config(&self) -> MachineConfig {
+        MachineConfig {
+            isa: self.isa,
+            stack: self.stack,
+            input: self.input,
+            syscalls: self.syscalls || self.sandbox.is_some(),
+            protection: self.protect_image.then(Protection::default),
+            ..MachineConfig::default()
+        }
+    }
+
+    /// Attach the devices and register the syscalls of a created machine.
+    pub fn attach(&self, machine: &mut Machine) {
+        if self.devices {
+            machine.attach_bus(Box::new(Devices::default()));
+        }
+        if let Some(dir) = &self.sandbox {
+            machine.register_syscall(SYS_READ_FILE, read_file_syscall(dir));
+        }
+    }
+}
+
+/// Labels of the program `code`, read from `path` and loaded into
+/// `machine`: the symbols of an object file, or for a binary image the
+/// labels of the `.dis` listing next to it when it assembles to the same
+/// bytes.
+pub fn labels(path: &Path, code: &[u8], machine: &Machine) -> BTreeMap<String, u32> {
+    if code.starts_with(OBJECT_MAGIC) {
+        return machine.symbols().clone();
+    }
+    std::fs::read_to_string(path.with_extension("dis"))
+        .ok()
+        .and_then(|source| assemble_program(&source).ok())
+        .filter(|program| program.code == code)
+        .map(|program| program.labels)
+        .unwrap_or_default()
+}
@@ -0,0 +1,251 @@
+//! Execution profiler, counting how many times each instruction is
+//! executed and how many times each address is read and written.
+//!
+//! Executed instructions are also attributed to call stacks, for a
+//! flamegraph. Calls and returns are inferred from the convention of the
+//! `.dis` listings: a call stores the address of the instruction following
+//! `loadimm r0 <- #function` right before executing it, and the function
+//! returns by jumping to that address. The `call` and `ret` instructions
+//! are recognized too.
+
+use crate::tracer::Tracer;
+use crate::Instruction;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+
+/// [Tracer] counting executions and memory accesses, see the
+/// [module documentation](self).
+pub struct Profiler {
+    // Names of the labelled addresses, the first in alphabetical order for
+    // an address having several.
+    names: BTreeMap<u32, String>,
+    steps: u64,
+    executions: BTreeMap<u32, u64>,
+    // Bytes of the instruction first executed at every address.
+    instructions: BTreeMap<u32, Vec<u8>>,
+    reads: BTreeMap<u32, u64>,
+    writes: BTreeMap<u32, u64>,
+    // Functions of the call stack, starting with the entry point, the
+    // addresses the called ones return to, and the number of instructions
+    // executed in every stack.
+    functions: Vec<u32>,
+    returns: Vec<u32>,
+    stacks: HashMap<Vec<u32>, u64>,
+    // Words written by the current instruction.
+    written: Vec<u32>,
+    // Words written by the previous instruction.
+    previous_written: Vec<u32>,
+}
+
+impl Profiler {
+    /// Profiler naming addresses after `labels`, which may be empty.
+    pub fn new(labels: &BTreeMap<String, u32>) -> Self {
+        let mut names = BTreeMap::new();
+        for (name, &addr) in labels {
+            names.entry(addr).or_insert_with(|| name.clone());
+        }
+        Profiler {
+            names,
+            steps: 0,
+            executions: BTreeMap::new(),
+            instructions: BTreeMap::new(),
+            reads: BTreeMap::new(),
+            writes: BTreeMap::new(),
+            functions: Vec::new(),
+            returns: Vec::new(),
+            stacks: HashMap::new(),
+            written: Vec::new(),
+            previous_written: Vec::new(),
+        }
+    }
+
+    /// Number of executed instructions.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Number of executions of the instruction at every address.
+    pub fn executions(&self) -> &BTreeMap<u32, u64> {
+        &self.executions
+    }
+
+    /// Number of executed instructions following every label, the ones
+    /// before the first label being counted under `None`. Sorted by
+    /// decreasing count.
+    pub fn symbol_counts(&self) -> Vec<(Option<String>, u64)> {
+        let mut counts: BTreeMap<Option<&str>, u64> = BTreeMap::new();
+        for (&addr, &count) in &self.executions {
+            let symbol = self.names.range(..=addr).next_back();
+            *counts
+                .entry(symbol.map(|(_, name)| name.as_str()))
+                .or_default() += count;
+        }
+        let mut counts: Vec<(Option<String>, u64)> = counts
+            .into_iter()
+            .map(|(name, count)| (name.map(str::to_string), count))
+            .collect();
+        counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        counts
+    }
+
+    /// Number of reads starting at every address.
+    pub fn reads(&self) -> &BTreeMap<u32, u64> {
+        &self.reads
+    }
+
+    /// Number of writes starting at every address.
+    pub fn writes(&self) -> &BTreeMap<u32, u64> {
+        &self.writes
+    }
+
+    /// Number of executed instructions for every call stack, as the names
+    /// of its functions starting with the outermost one.
+    pub fn stacks(&self) -> BTreeMap<Vec<String>, u64> {
+        self.stacks
+            .iter()
+            .map(|(stack, &count)| (stack.iter().map(|&f| self.name(f)).collect(), count))
+            .collect()
+    }
+
+    /// Report listing the executed instructions, the labels and the
+    /// accessed addresses, the most used first.
+    pub fn report(&self) -> String {
+        let mut report = format!("{} instructions executed\n", self.steps);
+        let percent = |count: u64| 100.0 * count as f64 / self.steps.max(1) as f64;
+
+        report.push_str("\ninstructions:\n");
+        let mut executions: Vec<(u32, u64)> =
+            self.executions.iter().map(|(&a, &c)| (a, c)).collect();
+        executions.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+        for (addr, count) in executions {
+            let instruction = match Instruction::decode(&self.instructions[&addr]) {
+                Ok((instruction, _)) => instruction.to_string(),
+                Err(_) => "<invalid>".to_string(),
+            };
+            let symbol = self
+                .symbolize(addr)
+                .map(|symbol| format!("<{symbol}>"))
+                .unwrap_or_default();
+            let line = format!(
+                "  {count:>10} {:>6.2}%  {addr:04}   {instruction:<28} {symbol}",
+                percent(count)
+            );
+            let _ = writeln!(report, "{}", line.trim_end());
+        }
+
+        if !self.names.is_empty() {
+            report.push_str("\nlabels:\n");
+            for (name, count) in self.symbol_counts() {
+                let name = name.unwrap_or_else(|| "(before the first label)".to_string());
+                let _ = writeln!(report, "  {count:>10} {:>6.2}%  {name}", percent(count));
+            }
+        }
+
+        report.push_str("\nmemory:\n");
+        report.push_str("        reads     writes  address\n");
+        let mut addresses: Vec<u32> = self
+            .reads
+            .keys()
+            .chain(self.writes.keys())
+            .copied()
+            .collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        let accesses = |addr: &u32| {
+            let reads = self.reads.get(addr).copied().unwrap_or(0);
+            let writes = self.writes.get(addr).copied().unwrap_or(0);
+            (reads, writes)
+        };
+        addresses.sort_by_key(|addr| {
+            let (reads, writes) = accesses(addr);
+            std::cmp::Reverse(reads + writes)
+        });
+        for addr in addresses {
+            let (reads, writes) = accesses(&addr);
+            // Memory after the last label, such as the stack, is not part
+            // of it.
+            let symbol = Some(addr)
+                .filter(|&addr| self.names.range(addr..).next().is_some())
+                .and_then(|addr| self.symbolize(addr))
+                .map(|symbol| format!(" <{symbol}>"))
+                .unwrap_or_default();
+            let _ = writeln!(report, "  {reads:>10} {writes:>10}  {addr:04}{symbol}");
+        }
+        report
+    }
+
+    /// Call stacks in the folded format of flamegraph tools, one line per
+    /// stack with its functions separated by `;` and the number of
+    /// instructions executed in it.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (stack, count) in self.stacks() {
+            let _ = writeln!(folded, "{} {count}", stack.join(";"));
+        }
+        folded
+    }
+
+    /// Name of a function, its label or its address.
+    fn name(&self, addr: u32) -> String {
+        match self.names.get(&addr) {
+            Some(name) => name.clone(),
+            None => format!("{addr:04}"),
+        }
+    }
+
+    /// Label preceding `addr`, with the offset from it.
+    fn symbolize(&self, addr: u32) -> Option<String> {
+        let (&address, name) = self.names.range(..=addr).next_back()?;
+        Some(match addr - address {
+            0 => name.clone(),
+            offset => format!("{name}+{offset}"),
+        })
+    }
+}
+
+impl Tracer for Profiler {
+    fn on_step(&mut self, ip: u32, insn: &[u8], _before: &[u32], after: &[u32]) {
+        self.steps += 1;
+        *self.executions.entry(ip).or_default() += 1;
+        self.instructions.entry(ip).or_insert_with(|| insn.to_vec());
+
+        if self.functions.is_empty() {
+            self.functions.push(ip);
+        }
+        match self.stacks.get_mut(&self.functions) {
+            Some(count) => *count += 1,
+            None => {
+                self.stacks.insert(self.functions.clone(), 1);
+            }
+        }
+
+        let next = ip + insn.len() as u32;
+        let target = after[0];
+        let call = match Instruction::decode(insn) {
+            Ok((Instruction::Call { .. }, _)) => true,
+            Ok((Instruction::LoadImm { reg: 0, .. }, _)) => self.previous_written.contains(&next),
+            _ => false,
+        };
+        if call {
+            self.functions.push(target);
+            self.returns.push(next);
+        } else if target != next {
+            if let Some(depth) = self.returns.iter().rposition(|&ret| ret == target) {
+                self.returns.truncate(depth);
+                self.functions.truncate(depth + 1);
+            }
+        }
+        self.previous_written = std::mem::take(&mut self.written);
+    }
+
+    fn on_mem_read(&mut self, addr: u32, _width: usize) {
+        *self.reads.entry(addr).or_default() += 1;
+    }
+
+    fn on_mem_write(&mut self, addr: u32, _old: &[u8], new: &[u8]) {
+        *self.writes.entry(addr).or_default() += 1;
+        if let Ok(word) = <[u8; 4]>::try_from(new) {
+            self.written.push(u32::from_le_bytes(word));
+        }
+    }
+}
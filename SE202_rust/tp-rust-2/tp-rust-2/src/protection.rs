@@ -0,0 +1,137 @@
+use crate::{Access, MachineError};
+use std::ops::Range;
+
+/// Accesses allowed to a part of the memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions::new(false, false, false);
+    pub const READ_ONLY: Permissions = Permissions::new(true, false, false);
+    pub const READ_WRITE: Permissions = Permissions::new(true, true, false);
+    pub const READ_EXECUTE: Permissions = Permissions::new(true, false, true);
+    pub const ALL: Permissions = Permissions::new(true, true, true);
+
+    pub const fn new(read: bool, write: bool, execute: bool) -> Self {
+        Permissions {
+            read,
+            write,
+            execute,
+        }
+    }
+
+    fn bits(self) -> u8 {
+        self.read as u8 | (self.write as u8) << 1 | (self.execute as u8) << 2
+    }
+}
+
+/// Addresses `range` of the memory, with their permissions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub range: Range<u32>,
+    pub permissions: Permissions,
+}
+
+/// Memory permissions of a [Machine](crate::Machine). Accesses which are
+/// not allowed stop the machine with a
+/// [ProtectionFault](MachineError::ProtectionFault).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protection {
+    /// Permissions of the addresses outside of every region.
+    pub default: Permissions,
+    /// Regions with their own permissions. When regions overlap, the last
+    /// one wins.
+    pub regions: Vec<Region>,
+    /// Make the initial memory image, or the code sections of an
+    /// [Object](crate::Object), read-only and executable, before the regions
+    /// are applied.
+    pub protect_image: bool,
+}
+
+/// Everything is allowed except writing to the initial image.
+impl Default for Protection {
+    fn default() -> Self {
+        Protection {
+            default: Permissions::ALL,
+            regions: Vec::new(),
+            protect_image: true,
+        }
+    }
+}
+
+/// Permissions of every address of a machine.
+#[derive(Debug, Clone)]
+pub(crate) struct PermissionMap {
+    bits: Vec<u8>,
+    default: u8,
+    // End of the protected image, 0 when it is not protected.
+    image_end: usize,
+}
+
+impl PermissionMap {
+    /// Permissions given by `protection`, `image` being the parts of the
+    /// memory protected by [protect_image](Protection::protect_image).
+    pub fn new(
+        protection: &Protection,
+        memory_size: usize,
+        image: impl IntoIterator<Item = Range<usize>>,
+    ) -> Self {
+        let default = protection.default.bits();
+        let mut bits = vec![default; memory_size];
+        let mut image_end = 0;
+        if protection.protect_image {
+            for range in image {
+                image_end = image_end.max(range.end);
+                bits[range].fill(Permissions::READ_EXECUTE.bits());
+            }
+        }
+        for region in &protection.regions {
+            let start = (region.range.start as usize).min(memory_size);
+            let end = (region.range.end as usize).clamp(start, memory_size);
+            bits[start..end].fill(region.permissions.bits());
+        }
+        PermissionMap {
+            bits,
+            default,
+            image_end,
+        }
+    }
+
+    /// Memory size needed to hold the protected image.
+    pub fn image_end(&self) -> usize {
+        self.image_end
+    }
+
+    /// Follow a change of the memory size, giving the default permissions
+    /// to new addresses.
+    pub fn resize(&mut self, memory_size: usize) {
+        self.bits.resize(memory_size, self.default);
+    }
+
+    /// Check that the `width` bytes starting at `addr` allow `access`.
+    /// Addresses out of memory are left to the bounds checks.
+    pub fn check(&self, addr: u32, width: usize, access: Access) -> Result<(), MachineError> {
+        let needed = match access {
+            Access::Load => Permissions::READ_ONLY,
+            Access::Store => Permissions::new(false, true, false),
+            Access::Fetch => Permissions::new(false, false, true),
+        }
+        .bits();
+        for i in 0..width as u32 {
+            let addr = addr.wrapping_add(i);
+            let bits = self.bits.get(addr as usize).copied().unwrap_or(needed);
+            if bits & needed == 0 {
+                return Err(MachineError::ProtectionFault {
+                    addr,
+                    kind: access,
+                    site: None,
+                });
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,596 @@
+//! Reference executor written directly from the specification, and a
+//! harness running it in lockstep with a [Machine] to find where they
+//! disagree.
+//!
+//! The reference executor favours obviousness over speed and shares no
+//! code with the [Machine]: it works on the raw instruction bytes, keeps no
+//! state besides the registers and the memory, and only tells error kinds
+//! apart. It covers the semantics of the instruction sets and of the stack.
+//! Control registers, syscalls, memory permissions and devices are not
+//! modelled.
+
+use crate::{IoDevice, Isa, Machine, MachineConfig, MachineError};
+use std::fmt;
+use std::io;
+
+const IP: usize = 0;
+
+/// Kind of error stopping an executor, the details being left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    IpOutOfBounds,
+    InvalidInstruction,
+    UnsupportedInstruction,
+    InvalidRegister,
+    MemoryOutOfBounds,
+    DivisionByZero,
+    StackOverflow,
+    StackUnderflow,
+    InvalidInput,
+    Io,
+    /// The instruction is outside of what the reference models: it reads
+    /// or writes control registers, or makes a syscall.
+    NotModelled,
+}
+
+impl Fault {
+    /// Kind of a [Machine] error, `None` for one the reference never
+    /// reports.
+    pub fn of(error: &MachineError) -> Option<Fault> {
+        Some(match error {
+            MachineError::IpOutOfBounds { .. } => Fault::IpOutOfBounds,
+            MachineError::InvalidInstruction { .. } => Fault::InvalidInstruction,
+            MachineError::UnsupportedInstruction { .. } => Fault::UnsupportedInstruction,
+            MachineError::InvalidRegister { .. } => Fault::InvalidRegister,
+            MachineError::MemoryOutOfBounds { .. } => Fault::MemoryOutOfBounds,
+            MachineError::DivisionByZero { .. } => Fault::DivisionByZero,
+            MachineError::StackOverflow { .. } => Fault::StackOverflow,
+            MachineError::StackUnderflow { .. } => Fault::StackUnderflow,
+            MachineError::InvalidInput { .. } => Fault::InvalidInput,
+            MachineError::IoError { .. } => Fault::Io,
+            _ => return None,
+        })
+    }
+}
+
+impl From<io::Error> for Fault {
+    fn from(_: io::Error) -> Self {
+        Fault::Io
+    }
+}
+
+/// Executor of the specification, see the [module documentation](self).
+pub struct Reference {
+    memory: Vec<u8>,
+    regs: Vec<u32>,
+    config: MachineConfig,
+}
+
+impl Reference {
+    /// Create an executor in the state of
+    /// [Machine::with_config(memory, config)](Machine::with_config).
+    ///
+    /// # Panics
+    /// This function panics when `memory` is larger than the machine
+    /// memory, when the configuration is invalid, as
+    /// [with_config](Machine::with_config), or when it has memory
+    /// permissions.
+    pub fn new(memory: &[u8], config: MachineConfig) -> Self {
+        assert!(
+            memory.len() <= config.memory_size,
+            "memory slice is too large"
+        );
+        assert!((1..=256).contains(&config.nregs), "invalid register count");
+        assert!(
+            !config
+                .stack
+                .is_some_and(|stack| stack.sp == 0 || stack.sp as usize >= config.nregs),
+            "invalid stack pointer"
+        );
+        assert!(
+            config.protection.is_none(),
+            "memory permissions are not modelled"
+        );
+        let mut full = vec![0; config.memory_size];
+        full[..memory.len()].copy_from_slice(memory);
+        Reference {
+            memory: full,
+            regs: vec![0; config.nregs],
+            config,
+        }
+    }
+
+    pub fn regs(&self) -> &[u32] {
+        &self.regs
+    }
+
+    pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<(), Fault> {
+        let slot = self.regs.get_mut(reg).ok_or(Fault::InvalidRegister)?;
+        *slot = value;
+        Ok(())
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Execute one instruction: `true` is returned upon `exit`.
+    pub fn step<T: IoDevice>(&mut self, fd: &mut T) -> Result<bool, Fault> {
+        let ip = self.regs[IP] as usize;
+        let opcode = *self.memory.get(ip).ok_or(Fault::IpOutOfBounds)?;
+        let size = size(opcode);
+        if size == 0 {
+            return Err(Fault::InvalidInstruction);
+        }
+        let b = self
+            .memory
+            .get(ip..ip + size)
+            .ok_or(Fault::IpOutOfBounds)?
+            .to_vec();
+        self.regs[IP] += size as u32;
+        self.execute(&b, fd)
+    }
+
+    fn execute<T: IoDevice>(&mut self, b: &[u8], fd: &mut T) -> Result<bool, Fault> {
+        let extended = matches!(b[0], 11..=22 | 27..=32);
+        let stack = matches!(b[0], 23..=26);
+        let input = matches!(b[0], 9 | 10);
+        let traps = matches!(b[0], 33..=35);
+        if (extended && self.config.isa < Isa::Extended)
+            || (stack && self.config.stack.is_none())
+            || (input && !self.config.input)
+            || (traps && !self.config.traps)
+            || (b[0] == 36 && !self.config.syscalls)
+        {
+            return Err(Fault::UnsupportedInstruction);
+        }
+        if matches!(b[0], 33..=36) {
+            return Err(Fault::NotModelled);
+        }
+        match b[0] {
+            // move ra <- rb if rc != 0
+            1 => {
+                let (a, x, c) = (self.reg(b[1])?, self.reg(b[2])?, self.reg(b[3])?);
+                if self.regs[c] != 0 {
+                    self.regs[a] = self.regs[x];
+                }
+            }
+            // store [ra] <- rb
+            2 => {
+                let (a, x) = (self.reg(b[1])?, self.reg(b[2])?);
+                self.write(self.regs[a], 4, self.regs[x])?;
+            }
+            // load ra <- [rb]
+            3 => {
+                let (a, x) = (self.reg(b[1])?, self.reg(b[2])?);
+                self.regs[a] = self.read(self.regs[x], 4)?;
+            }
+            // loadimm ra <- #i16, sign-extended
+            4 => {
+                let a = self.reg(b[1])?;
+                self.regs[a] = i16::from_le_bytes([b[2], b[3]]) as i32 as u32;
+            }
+            // sub ra <- rb - rc
+            5 => {
+                let (a, x, c) = (self.reg(b[1])?, self.reg(b[2])?, self.reg(b[3])?);
+                self.regs[a] = self.regs[x].wrapping_sub(self.regs[c]);
+            }
+            // out ra, the low byte being a Unicode code point
+            6 => {
+                let a = self.reg(b[1])?;
+                let c = char::from(self.regs[a] as u8);
+                fd.write_output(c.to_string().as_bytes())?;
+            }
+            // exit
+            7 => return Ok(true),
+            // out_number ra, signed
+            8 => {
+                let a = self.reg(b[1])?;
+                fd.write_output((self.regs[a] as i32).to_string().as_bytes())?;
+            }
+            // in ra, -1 at the end of the input
+            9 => {
+                let a = self.reg(b[1])?;
+                self.regs[a] = fd.read_input()?.map_or(u32::MAX, u32::from);
+            }
+            // in_number ra
+            10 => {
+                let a = self.reg(b[1])?;
+                self.regs[a] = read_number(fd)?;
+            }
+            // add, and, or, xor, shl, shr, sar, slt, sltu, mul, div, mod
+            11..=22 => {
+                let (a, x, c) = (self.reg(b[1])?, self.reg(b[2])?, self.reg(b[3])?);
+                let (x, c) = (self.regs[x], self.regs[c]);
+                self.regs[a] = match b[0] {
+                    11 => x.wrapping_add(c),
+                    12 => x & c,
+                    13 => x | c,
+                    14 => x ^ c,
+                    15 if c >= 32 => 0,
+                    15 => x << c,
+                    16 if c >= 32 => 0,
+                    16 => x >> c,
+                    17 if c >= 32 => ((x as i32) >> 31) as u32,
+                    17 => ((x as i32) >> c) as u32,
+                    18 => ((x as i32) < (c as i32)) as u32,
+                    19 => (x < c) as u32,
+                    20 => x.wrapping_mul(c),
+                    _ if c == 0 => return Err(Fault::DivisionByZero),
+                    21 => (x as i32).wrapping_div(c as i32) as u32,
+                    _ => (x as i32).wrapping_rem(c as i32) as u32,
+                };
+            }
+            // push ra
+            23 => {
+                let a = self.reg(b[1])?;
+                self.push(self.regs[a])?;
+            }
+            // pop ra
+            24 => {
+                let a = self.reg(b[1])?;
+                self.regs[a] = self.pop()?;
+            }
+            // call #u16
+            25 => {
+                self.push(self.regs[IP])?;
+                self.regs[IP] = u16::from_le_bytes([b[1], b[2]]) as u32;
+            }
+            // ret
+            26 => self.regs[IP] = self.pop()?,
+            // store8 [ra] <- rb, store16 [ra] <- rb
+            27 | 28 => {
+                let (a, x) = (self.reg(b[1])?, self.reg(b[2])?);
+                let width = if b[0] == 27 { 1 } else { 2 };
+                self.write(self.regs[a], width, self.regs[x])?;
+            }
+            // load8, load8s, load16, load16s ra <- [rb]
+            _ => {
+                let (a, x) = (self.reg(b[1])?, self.reg(b[2])?);
+                let value = if b[0] <= 30 {
+                    self.read(self.regs[x], 1)?
+                } else {
+                    self.read(self.regs[x], 2)?
+                };
+                self.regs[a] = match b[0] {
+                    30 => value as u8 as i8 as i32 as u32,
+                    32 => value as u16 as i16 as i32 as u32,
+                    _ => value,
+                };
+            }
+        }
+        Ok(false)
+    }
+
+    /// Index of register `reg`, which must exist.
+    fn reg(&self, reg: u8) -> Result<usize, Fault> {
+        let reg = reg as usize;
+        if reg < self.regs.len() {
+            Ok(reg)
+        } else {
+            Err(Fault::InvalidRegister)
+        }
+    }
+
+    fn in_memory(&self, addr: u32, width: usize) -> bool {
+        addr as usize + width <= self.memory.len()
+    }
+
+    fn read(&self, addr: u32, width: usize) -> Result<u32, Fault> {
+        if !self.in_memory(addr, width) {
+            return Err(Fault::MemoryOutOfBounds);
+        }
+        let mut bytes = [0; 4];
+        bytes[..width].copy_from_slice(&self.memory[addr as usize..addr as usize + width]);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn write(&mut self, addr: u32, width: usize, value: u32) -> Result<(), Fault> {
+        if !self.in_memory(addr, width) {
+            return Err(Fault::MemoryOutOfBounds);
+        }
+        self.memory[addr as usize..addr as usize + width]
+            .copy_from_slice(&value.to_le_bytes()[..width]);
+        Ok(())
+    }
+
+    fn push(&mut self, value: u32) -> Result<(), Fault> {
+        let stack = self.config.stack.unwrap();
+        let sp = self.regs[stack.sp as usize];
+        if (sp as u64) < stack.limit as u64 + 4 {
+            return Err(Fault::StackOverflow);
+        }
+        self.write(sp - 4, 4, value)?;
+        self.regs[stack.sp as usize] = sp - 4;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<u32, Fault> {
+        let stack = self.config.stack.unwrap();
+        let sp = self.regs[stack.sp as usize];
+        if !self.in_memory(sp, 4) {
+            return Err(Fault::StackUnderflow);
+        }
+        let value = self.read(sp, 4)?;
+        self.regs[stack.sp as usize] = sp + 4;
+        Ok(value)
+    }
+}
+
+/// Size of the instruction starting with `opcode`, 0 for an unknown one.
+fn size(opcode: u8) -> usize {
+    match opcode {
+        7 | 26 | 35 | 36 => 1,
+        6 | 8 | 9 | 10 | 23 | 24 => 2,
+        2 | 3 | 25 | 27..=34 => 3,
+        1 | 4 | 5 | 11..=22 => 4,
+        _ => 0,
+    }
+}
+
+/// Read a decimal number: whitespace, an optional sign and digits, stopping
+/// right after the last digit.
+fn read_number<T: IoDevice>(fd: &mut T) -> Result<u32, Fault> {
+    while fd.peek_input()?.is_some_and(|b| b.is_ascii_whitespace()) {
+        fd.read_input()?;
+    }
+    let mut text = String::new();
+    while let Some(b) = fd.peek_input()? {
+        let sign = text.is_empty() && (b == b'-' || b == b'+');
+        if !sign && !b.is_ascii_digit() {
+            break;
+        }
+        text.push(b as char);
+        fd.read_input()?;
+    }
+    match text.parse::<i64>() {
+        Ok(n) if n >= i32::MIN as i64 && n <= u32::MAX as i64 => Ok(n as u32),
+        _ => Err(Fault::InvalidInput),
+    }
+}
+
+/// First step where a [Machine] and a [Reference] disagree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Number of steps executed identically before this one.
+    pub step: u64,
+    /// IP before the step.
+    pub ip: u32,
+    pub differences: Vec<Difference>,
+}
+
+/// Something a step left different in a [Machine] and a [Reference].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// Result of the step: `continue`, `exit` or the kind of error.
+    Outcome { machine: String, reference: String },
+    Register {
+        reg: usize,
+        machine: u32,
+        reference: u32,
+    },
+    Memory {
+        addr: u32,
+        machine: u8,
+        reference: u8,
+    },
+    /// Output written during the step.
+    Output {
+        machine: Vec<u8>,
+        reference: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Outcome { machine, reference } => {
+                write!(f, "outcome: machine `{machine}`, reference `{reference}`")
+            }
+            Difference::Register {
+                reg,
+                machine,
+                reference,
+            } => write!(f, "r{reg}: machine {machine}, reference {reference}"),
+            Difference::Memory {
+                addr,
+                machine,
+                reference,
+            } => write!(
+                f,
+                "memory {addr:04}: machine {machine:02x}, reference {reference:02x}"
+            ),
+            Difference::Output { machine, reference } => write!(
+                f,
+                "output: machine \"{}\", reference \"{}\"",
+                machine.escape_ascii(),
+                reference.escape_ascii()
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "divergence at step {} (IP {:04})", self.step, self.ip)?;
+        for difference in &self.differences {
+            write!(f, "\n  {difference}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// How the executors stopped after agreeing on every step.
+#[derive(Debug)]
+pub enum End {
+    Exit,
+    /// Both failed with the same kind of error, this one being the one of
+    /// the machine.
+    Fault(MachineError),
+    /// The step limit was reached.
+    StepLimit,
+    /// The next instruction is not [modelled](Fault::NotModelled) by the
+    /// reference.
+    NotModelled,
+}
+
+/// Result of a [lockstep] run without divergence.
+#[derive(Debug)]
+pub struct Agreement {
+    /// Number of executed steps, including a failed last one.
+    pub steps: u64,
+    pub output: Vec<u8>,
+    pub end: End,
+}
+
+/// Input and output of one executor.
+struct Channel<'a> {
+    input: &'a [u8],
+    output: Vec<u8>,
+}
+
+impl IoDevice for Channel<'_> {
+    fn write_output(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.output.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn peek_input(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.input.first().copied())
+    }
+
+    fn read_input(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.peek_input()?;
+        if byte.is_some() {
+            self.input = &self.input[1..];
+        }
+        Ok(byte)
+    }
+}
+
+/// Run `machine` and `reference` one step at a time on the same `input`,
+/// for at most `max_steps` steps, and compare the kind of error if any,
+/// the registers, the memory and the output after every step. Both must
+/// have been created with the same program and configuration.
+///
+/// ```
+/// use interpreter::reference::{lockstep, End, Reference};
+/// use interpreter::{Machine, MachineConfig};
+///
+/// // 0: loadimm r1 <- #-1
+/// // 4: out_number r1
+/// // 6: exit
+/// let program = [4, 1, 255, 255, 8, 1, 7];
+/// let mut machine = Machine::new(&program);
+/// let mut reference = Reference::new(&program, MachineConfig::default());
+/// let agreement = lockstep(&mut machine, &mut reference, b"", 100).unwrap();
+/// assert!(matches!(agreement.end, End::Exit));
+/// assert_eq!(b"-1", &agreement.output[..]);
+/// ```
+pub fn lockstep(
+    machine: &mut Machine,
+    reference: &mut Reference,
+    input: &[u8],
+    max_steps: u64,
+) -> Result<Agreement, Divergence> {
+    let mut machine_io = Channel {
+        input,
+        output: Vec::new(),
+    };
+    let mut reference_io = Channel {
+        input,
+        output: Vec::new(),
+    };
+    for step in 0..max_steps {
+        let ip = reference.regs()[IP];
+        let output_len = (machine_io.output.len(), reference_io.output.len());
+        let reference_result = reference.step(&mut reference_io);
+        if let Err(Fault::NotModelled) = reference_result {
+            return Ok(Agreement {
+                steps: step,
+                output: machine_io.output,
+                end: End::NotModelled,
+            });
+        }
+        let machine_result = machine.step_on(&mut machine_io);
+
+        let mut differences = Vec::new();
+        let machine_outcome = match &machine_result {
+            Ok(exit) => Ok(*exit),
+            Err(e) => Err(Fault::of(e).ok_or_else(|| e.to_string())),
+        };
+        if machine_outcome != reference_result.map_err(Ok) {
+            differences.push(Difference::Outcome {
+                machine: describe(&machine_outcome),
+                reference: describe(&reference_result.map_err(Ok)),
+            });
+        }
+        let pairs = machine.regs().iter().zip(reference.regs()).enumerate();
+        for (reg, (&machine, &reference)) in pairs {
+            if machine != reference {
+                differences.push(Difference::Register {
+                    reg,
+                    machine,
+                    reference,
+                });
+            }
+        }
+        // Most steps leave the memory alone: compare it as a whole first.
+        if machine.memory() != reference.memory() {
+            let pairs = machine.memory().iter().zip(reference.memory()).enumerate();
+            for (addr, (&machine, &reference)) in pairs {
+                if machine != reference {
+                    differences.push(Difference::Memory {
+                        addr: addr as u32,
+                        machine,
+                        reference,
+                    });
+                }
+            }
+        }
+        let machine_output = &machine_io.output[output_len.0..];
+        let reference_output = &reference_io.output[output_len.1..];
+        if machine_output != reference_output {
+            differences.push(Difference::Output {
+                machine: machine_output.to_vec(),
+                reference: reference_output.to_vec(),
+            });
+        }
+        if !differences.is_empty() {
+            return Err(Divergence {
+                step,
+                ip,
+                differences,
+            });
+        }
+
+        let end = match machine_result {
+            Ok(false) => continue,
+            Ok(true) => End::Exit,
+            Err(e) => End::Fault(e),
+        };
+        return Ok(Agreement {
+            steps: step + 1,
+            output: machine_io.output,
+            end,
+        });
+    }
+    Ok(Agreement {
+        steps: max_steps,
+        output: machine_io.output,
+        end: End::StepLimit,
+    })
+}
+
+/// Description of the result of a step: `continue`, `exit`, the kind of
+/// error, or the description of an error the reference does not know.
+fn describe(outcome: &Result<bool, Result<Fault, String>>) -> String {
+    match outcome {
+        Ok(false) => "continue".to_string(),
+        Ok(true) => "exit".to_string(),
+        Err(Ok(fault)) => format!("{fault:?}"),
+        Err(Err(error)) => error.clone(),
+    }
+}
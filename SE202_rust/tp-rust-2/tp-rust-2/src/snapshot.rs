@@ -0,0 +1,170 @@
+use std::fmt;
+
+/// Magic header at the beginning of every snapshot file.
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"TPVMSNAP";
+
+/// Version of the snapshot file format written by [Snapshot::to_bytes].
+pub const SNAPSHOT_VERSION: u16 = 1;
+
+/// Size of the header: magic, version, reserved field, number of registers
+/// and memory size.
+const HEADER_SIZE: usize = 8 + 2 + 2 + 4 + 4;
+
+/// Full state of a [Machine](crate::Machine): its registers and memory.
+///
+/// The file format is, with every integer stored in little-endian order:
+///
+/// | size         | content                                   |
+/// |--------------|-------------------------------------------|
+/// | 8            | magic `TPVMSNAP`                          |
+/// | 2            | format version (1)                        |
+/// | 2            | reserved, 0                               |
+/// | 4            | number of registers `n`                   |
+/// | 4            | memory size `m`                           |
+/// | 4 × `n`      | registers                                 |
+/// | `m`          | memory                                    |
+/// | 4            | CRC-32 of everything before               |
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub(crate) regs: Vec<u32>,
+    pub(crate) memory: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    TrailingData,
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    InvalidRegisterCount(u32),
+    /// The snapshot has no register for the configured stack pointer.
+    MissingStackPointer {
+        nregs: usize,
+        sp: u8,
+    },
+    /// The snapshot memory is smaller than the protected image.
+    ImageDoesNotFit {
+        memory_size: usize,
+        image_size: usize,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a snapshot file"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {v}")
+            }
+            SnapshotError::Truncated => write!(f, "truncated snapshot"),
+            SnapshotError::TrailingData => write!(f, "unexpected data after the snapshot"),
+            SnapshotError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "snapshot checksum mismatch (expected {expected:08x}, found {found:08x})"
+            ),
+            SnapshotError::InvalidRegisterCount(n) => {
+                write!(f, "invalid number of registers {n}")
+            }
+            SnapshotError::MissingStackPointer { nregs, sp } => write!(
+                f,
+                "snapshot has {nregs} registers, no stack pointer r{sp}"
+            ),
+            SnapshotError::ImageDoesNotFit {
+                memory_size,
+                image_size,
+            } => write!(
+                f,
+                "snapshot memory of {memory_size} bytes cannot hold the protected image of {image_size} bytes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl Snapshot {
+    pub fn regs(&self) -> &[u32] {
+        &self.regs
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Serialize the snapshot in the current file format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(HEADER_SIZE + 4 * self.regs.len() + self.memory.len() + 4);
+        bytes.extend(SNAPSHOT_MAGIC);
+        bytes.extend(SNAPSHOT_VERSION.to_le_bytes());
+        bytes.extend(0u16.to_le_bytes());
+        bytes.extend((self.regs.len() as u32).to_le_bytes());
+        bytes.extend((self.memory.len() as u32).to_le_bytes());
+        for reg in &self.regs {
+            bytes.extend(reg.to_le_bytes());
+        }
+        bytes.extend(&self.memory);
+        let checksum = crc32(&bytes);
+        bytes.extend(checksum.to_le_bytes());
+        bytes
+    }
+
+    /// Parse a snapshot written by [to_bytes](Snapshot::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        if bytes.len() < SNAPSHOT_MAGIC.len() || &bytes[..8] != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(SnapshotError::Truncated);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let version = u16_at(8);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let nregs = u32_at(12);
+        if !(1..=256).contains(&nregs) {
+            return Err(SnapshotError::InvalidRegisterCount(nregs));
+        }
+        let memory_size = u32_at(16) as usize;
+        let size = HEADER_SIZE + 4 * nregs as usize + memory_size + 4;
+        if bytes.len() < size {
+            return Err(SnapshotError::Truncated);
+        }
+        if bytes.len() > size {
+            return Err(SnapshotError::TrailingData);
+        }
+        let expected = u32_at(size - 4);
+        let found = crc32(&bytes[..size - 4]);
+        if expected != found {
+            return Err(SnapshotError::ChecksumMismatch { expected, found });
+        }
+        let regs = (0..nregs as usize)
+            .map(|i| u32_at(HEADER_SIZE + 4 * i))
+            .collect();
+        let memory_start = HEADER_SIZE + 4 * nregs as usize;
+        let memory = bytes[memory_start..memory_start + memory_size].to_vec();
+        Ok(Snapshot { regs, memory })
+    }
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,143 @@
+use crate::{Access, MachineError};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Print the NUL-terminated string at the address in r3. The bytes are
+/// written as they are to the output of the machine, and their count is
+/// returned in r1.
+pub const SYS_PRINT: u32 = 1;
+/// Return the time elapsed since the Unix epoch: seconds in r1 and
+/// milliseconds in r3.
+pub const SYS_TIME: u32 = 2;
+/// Read the file whose relative path is the NUL-terminated string at the
+/// address in r3, writing at most r5 bytes to the address in r4. The
+/// number of bytes read is returned in r1, or -1 if the file cannot be
+/// read. Only available once registered with [read_file_syscall].
+pub const SYS_READ_FILE: u32 = 3;
+
+/// Registers of a machine, as seen by a syscall handler.
+pub type Regs = [u32];
+
+/// Host function run by the `syscall` instruction. It receives the
+/// registers, the IP already pointing to the next instruction, and the
+/// whole memory. Returning an error stops the machine like a fault of the
+/// instruction.
+pub type SyscallHandler = Box<dyn FnMut(&mut Regs, &mut [u8]) -> Result<(), MachineError>>;
+
+pub(crate) enum Syscall {
+    /// [SYS_PRINT], which needs the output of the machine.
+    Print,
+    Host(SyscallHandler),
+}
+
+/// Syscalls of a machine, indexed by number.
+pub(crate) struct Syscalls {
+    pub handlers: HashMap<u32, Syscall>,
+}
+
+impl Default for Syscalls {
+    fn default() -> Self {
+        let mut handlers = HashMap::new();
+        handlers.insert(SYS_PRINT, Syscall::Print);
+        handlers.insert(SYS_TIME, Syscall::Host(Box::new(time)));
+        Syscalls { handlers }
+    }
+}
+
+/// Value of register `reg`, which must exist.
+pub(crate) fn arg(regs: &Regs, reg: usize) -> Result<u32, MachineError> {
+    regs.get(reg)
+        .copied()
+        .ok_or(MachineError::InvalidRegister { reg, site: None })
+}
+
+/// Set register `reg`, which must exist.
+pub(crate) fn set_result(regs: &mut Regs, reg: usize, value: u32) -> Result<(), MachineError> {
+    let slot = regs
+        .get_mut(reg)
+        .ok_or(MachineError::InvalidRegister { reg, site: None })?;
+    *slot = value;
+    Ok(())
+}
+
+/// The NUL-terminated string starting at `addr`, without its terminator.
+pub(crate) fn c_string(memory: &[u8], addr: u32) -> Result<&[u8], MachineError> {
+    let out_of_bounds = |width| MachineError::MemoryOutOfBounds {
+        addr,
+        width,
+        access: Access::Load,
+        site: None,
+    };
+    let bytes = memory.get(addr as usize..).ok_or(out_of_bounds(1))?;
+    let len = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(out_of_bounds(bytes.len() + 1))?;
+    Ok(&bytes[..len])
+}
+
+fn time(regs: &mut Regs, _: &mut [u8]) -> Result<(), MachineError> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    set_result(regs, 1, elapsed.as_secs() as u32)?;
+    set_result(regs, 3, elapsed.subsec_millis())
+}
+
+/// Handler of [SYS_READ_FILE] giving access to the files under `dir`.
+/// Absolute paths, paths containing `..` and symbolic links leading out of
+/// `dir` are refused.
+///
+/// ```
+/// use interpreter::{read_file_syscall, Machine, MachineConfig, SYS_READ_FILE};
+///
+/// let config = MachineConfig {
+///     syscalls: true,
+///     ..MachineConfig::default()
+/// };
+/// let mut machine = Machine::with_config(&[], config);
+/// machine.register_syscall(SYS_READ_FILE, read_file_syscall("data"));
+/// ```
+pub fn read_file_syscall(dir: impl Into<PathBuf>) -> SyscallHandler {
+    let dir = dir.into();
+    Box::new(move |regs, memory| {
+        let (path, addr, capacity) = (arg(regs, 3)?, arg(regs, 4)?, arg(regs, 5)?);
+        let end = addr as usize + capacity as usize;
+        if end > memory.len() {
+            return Err(MachineError::MemoryOutOfBounds {
+                addr,
+                width: capacity as usize,
+                access: Access::Store,
+                site: None,
+            });
+        }
+        let path = c_string(memory, path)?;
+        let read = std::str::from_utf8(path)
+            .ok()
+            .and_then(|path| sandboxed(&dir, Path::new(path)))
+            .and_then(|path| std::fs::read(path).ok());
+        let result = match read {
+            Some(content) => {
+                let len = content.len().min(capacity as usize);
+                memory[addr as usize..addr as usize + len].copy_from_slice(&content[..len]);
+                len as u32
+            }
+            None => u32::MAX,
+        };
+        set_result(regs, 1, result)
+    })
+}
+
+/// `path` inside `dir`, if it does not escape it.
+fn sandboxed(dir: &Path, path: &Path) -> Option<PathBuf> {
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        return None;
+    }
+    let dir = dir.canonicalize().ok()?;
+    let full = dir.join(path).canonicalize().ok()?;
+    full.starts_with(&dir).then_some(full)
+}
@@ -0,0 +1,223 @@
+use crate::machine::IP;
+use crate::{AluOp, Instruction, Isa, Width};
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Number of times translated code may be modified before giving up on
+/// translating, for programs which keep rewriting themselves.
+const MAX_FLUSHES: u32 = 64;
+
+/// Operation of a translated block. The operations executed most often
+/// have their registers checked at translation time, the others are
+/// executed as any decoded instruction.
+#[derive(Clone, Copy)]
+pub(crate) enum Op {
+    MoveIf {
+        a: usize,
+        b: usize,
+        c: usize,
+    },
+    LoadImm {
+        a: usize,
+        value: u32,
+    },
+    Sub {
+        a: usize,
+        b: usize,
+        c: usize,
+    },
+    Alu {
+        op: AluOp,
+        a: usize,
+        b: usize,
+        c: usize,
+    },
+    Load {
+        a: usize,
+        b: usize,
+    },
+    Store {
+        a: usize,
+        b: usize,
+    },
+    LoadNarrow {
+        width: Width,
+        signed: bool,
+        a: usize,
+        b: usize,
+    },
+    StoreNarrow {
+        width: Width,
+        a: usize,
+        b: usize,
+    },
+    Other(Instruction),
+}
+
+impl Op {
+    fn new(instruction: Instruction, nregs: usize, isa: Isa) -> Op {
+        let regs = instruction.registers();
+        if regs.iter().any(|&reg| reg as usize >= nregs)
+            || (instruction.is_extended() && isa < Isa::Extended)
+        {
+            // Left to `execute`, which reports the fault.
+            return Op::Other(instruction);
+        }
+        let r = |reg: u8| reg as usize;
+        match instruction {
+            Instruction::MoveIf { a, b, c } => Op::MoveIf {
+                a: r(a),
+                b: r(b),
+                c: r(c),
+            },
+            Instruction::LoadImm { reg, value } => Op::LoadImm {
+                a: r(reg),
+                value: value as u32,
+            },
+            Instruction::Sub { a, b, c } => Op::Sub {
+                a: r(a),
+                b: r(b),
+                c: r(c),
+            },
+            Instruction::Alu { op, a, b, c } => Op::Alu {
+                op,
+                a: r(a),
+                b: r(b),
+                c: r(c),
+            },
+            Instruction::Load { a, b } => Op::Load { a: r(a), b: r(b) },
+            Instruction::Store { a, b } => Op::Store { a: r(a), b: r(b) },
+            Instruction::LoadNarrow {
+                width,
+                signed,
+                a,
+                b,
+            } => Op::LoadNarrow {
+                width,
+                signed,
+                a: r(a),
+                b: r(b),
+            },
+            Instruction::StoreNarrow { width, a, b } => Op::StoreNarrow {
+                width,
+                a: r(a),
+                b: r(b),
+            },
+            _ => Op::Other(instruction),
+        }
+    }
+
+    /// Whether the operation may write memory, and thus translated code.
+    pub fn may_write(&self) -> bool {
+        matches!(
+            self,
+            Op::Store { .. } | Op::StoreNarrow { .. } | Op::Other(_)
+        )
+    }
+}
+
+/// One instruction of a block: its operation, where it is and where the
+/// next one starts.
+pub(crate) struct Step {
+    pub op: Op,
+    pub ip: u32,
+    pub next: u32,
+    pub instruction: Instruction,
+}
+
+/// Straight-line sequence of instructions, ending with the first one
+/// which may write the IP or stop the machine.
+pub(crate) type Block = Rc<[Step]>;
+
+/// Translate the block starting at `start`. The block stops before the
+/// first instruction which cannot be decoded or for which `fetchable`
+/// does not hold, so it is empty when the one at `start` cannot run.
+pub(crate) fn translate<F: Fn(u32, usize) -> bool>(
+    memory: &[u8],
+    start: u32,
+    nregs: usize,
+    isa: Isa,
+    fetchable: F,
+) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let mut ip = start;
+    while let Some(bytes) = memory.get(ip as usize..) {
+        let Ok((instruction, size)) = Instruction::decode(bytes) else {
+            break;
+        };
+        if !fetchable(ip, size) {
+            break;
+        }
+        let next = ip + size as u32;
+        steps.push(Step {
+            op: Op::new(instruction, nregs, isa),
+            ip,
+            next,
+            instruction,
+        });
+        // A host syscall may write any register.
+        if instruction.destination() == Some(IP as u8)
+            || matches!(instruction, Instruction::Exit | Instruction::Syscall)
+        {
+            break;
+        }
+        ip = next;
+    }
+    steps
+}
+
+/// Translated blocks, indexed by their first address.
+pub(crate) struct BlockCache {
+    blocks: Vec<Option<Block>>,
+    // Bytes belonging to a translated block.
+    translated: Vec<bool>,
+    /// Incremented every time the blocks are forgotten.
+    pub generation: u64,
+    flushes: u32,
+}
+
+impl BlockCache {
+    pub fn new(memory_size: usize) -> Self {
+        BlockCache {
+            blocks: vec![None; memory_size],
+            translated: vec![false; memory_size],
+            generation: 0,
+            flushes: 0,
+        }
+    }
+
+    /// Whether code has been modified too often to keep translating it.
+    pub fn gave_up(&self) -> bool {
+        self.flushes >= MAX_FLUSHES
+    }
+
+    pub fn get(&self, addr: u32) -> Option<Block> {
+        self.blocks.get(addr as usize)?.clone()
+    }
+
+    pub fn insert(&mut self, addr: u32, steps: Vec<Step>) -> Block {
+        let block: Block = steps.into();
+        if let (Some(first), Some(last)) = (block.first(), block.last()) {
+            self.translated[first.ip as usize..last.next as usize].fill(true);
+        }
+        self.blocks[addr as usize] = Some(block.clone());
+        block
+    }
+
+    /// Forget every block if the bytes in `range`, which have been
+    /// modified, were translated.
+    pub fn invalidate(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.translated.len());
+        let start = range.start.min(end);
+        if self.translated[start..end].contains(&true) {
+            self.flushes += 1;
+            self.clear();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.fill(None);
+        self.translated.fill(false);
+        self.generation += 1;
+    }
+}
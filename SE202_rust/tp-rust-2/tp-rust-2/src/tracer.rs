@@ -0,0 +1,186 @@
+use crate::Instruction;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+/// Observer of the execution of a [Machine](crate::Machine), see
+/// [Machine::run_traced](crate::Machine::run_traced).
+pub trait Tracer {
+    /// Called after an instruction has been executed successfully. `insn`
+    /// holds the instruction bytes, `before` and `after` the registers
+    /// before and after its execution.
+    fn on_step(&mut self, ip: u32, insn: &[u8], before: &[u32], after: &[u32]);
+
+    /// Called for every memory write done by an instruction, before
+    /// [on_step](Tracer::on_step) is called for this instruction.
+    fn on_mem_write(&mut self, _addr: u32, _old: &[u8], _new: &[u8]) {}
+
+    /// Called for every memory read of `width` bytes done by an
+    /// instruction, before its writes are reported. Reads from memory-mapped
+    /// devices are not reported.
+    fn on_mem_read(&mut self, _addr: u32, _width: usize) {}
+}
+
+/// A memory write waiting for the end of its instruction.
+struct PendingWrite {
+    addr: u32,
+    old: Vec<u8>,
+    new: Vec<u8>,
+}
+
+/// Tracer writing one JSON object per executed instruction, such as
+///
+/// ```text
+/// {"step":3,"ip":12,"insn":"store [r2] <- r10","bytes":[2,2,10],"regs":{"r0":15},"writes":[{"addr":4092,"old":[0,0,0,0],"new":[5,0,0,0]}]}
+/// ```
+///
+/// `regs` only contains the registers whose value changed.
+pub struct JsonTracer<W: Write> {
+    out: W,
+    step: u64,
+    writes: Vec<PendingWrite>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(out: W) -> Self {
+        JsonTracer {
+            out,
+            step: 0,
+            writes: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Return the underlying writer, or the first error encountered while
+    /// writing the trace.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.out),
+        }
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn on_step(&mut self, ip: u32, insn: &[u8], before: &[u32], after: &[u32]) {
+        let mut line = format!(
+            "{{\"step\":{},\"ip\":{ip},\"insn\":\"{}\",\"bytes\":{},\"regs\":{{",
+            self.step,
+            instruction_text(insn),
+            json_bytes(insn)
+        );
+        let changed = changed_registers(before, after);
+        for (i, (reg, value)) in changed.enumerate() {
+            let comma = if i > 0 { "," } else { "" };
+            let _ = write!(line, "{comma}\"r{reg}\":{value}");
+        }
+        line.push_str("},\"writes\":[");
+        for (i, write) in self.writes.drain(..).enumerate() {
+            let comma = if i > 0 { "," } else { "" };
+            let _ = write!(
+                line,
+                "{comma}{{\"addr\":{},\"old\":{},\"new\":{}}}",
+                write.addr,
+                json_bytes(&write.old),
+                json_bytes(&write.new)
+            );
+        }
+        line.push_str("]}");
+        self.step += 1;
+        if self.error.is_none() {
+            self.error = writeln!(self.out, "{line}").err();
+        }
+    }
+
+    fn on_mem_write(&mut self, addr: u32, old: &[u8], new: &[u8]) {
+        self.writes.push(PendingWrite {
+            addr,
+            old: old.to_vec(),
+            new: new.to_vec(),
+        });
+    }
+}
+
+/// Tracer writing one line per executed instruction, in the format of the
+/// `.dis` listings followed by the changes it made, such as
+///
+/// ```text
+///   0012   store [r2] <- r10            r0=15 [4092]=05 00 00 00
+/// ```
+pub struct TextTracer<W: Write> {
+    out: W,
+    writes: Vec<PendingWrite>,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(out: W) -> Self {
+        TextTracer {
+            out,
+            writes: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Return the underlying writer, or the first error encountered while
+    /// writing the trace.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.out),
+        }
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn on_step(&mut self, ip: u32, insn: &[u8], before: &[u32], after: &[u32]) {
+        let mut changes = Vec::new();
+        for (reg, value) in changed_registers(before, after) {
+            changes.push(format!("r{reg}={}", value as i32));
+        }
+        for write in self.writes.drain(..) {
+            let bytes: Vec<String> = write.new.iter().map(|b| format!("{b:02x}")).collect();
+            changes.push(format!("[{}]={}", write.addr, bytes.join(" ")));
+        }
+        let line = format!(
+            "  {ip:04}   {:<28} {}",
+            instruction_text(insn),
+            changes.join(" ")
+        );
+        if self.error.is_none() {
+            self.error = writeln!(self.out, "{}", line.trim_end()).err();
+        }
+    }
+
+    fn on_mem_write(&mut self, addr: u32, old: &[u8], new: &[u8]) {
+        self.writes.push(PendingWrite {
+            addr,
+            old: old.to_vec(),
+            new: new.to_vec(),
+        });
+    }
+}
+
+fn instruction_text(insn: &[u8]) -> String {
+    match Instruction::decode(insn) {
+        Ok((instruction, _)) => instruction.to_string(),
+        Err(_) => "<invalid>".to_string(),
+    }
+}
+
+fn changed_registers<'a>(
+    before: &'a [u32],
+    after: &'a [u32],
+) -> impl Iterator<Item = (usize, u32)> + 'a {
+    before
+        .iter()
+        .zip(after)
+        .enumerate()
+        .filter(|(_, (b, a))| b != a)
+        .map(|(reg, (_, &a))| (reg, a))
+}
+
+fn json_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| b.to_string()).collect();
+    format!("[{}]", bytes.join(","))
+}
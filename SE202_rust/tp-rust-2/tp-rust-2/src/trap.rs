@@ -0,0 +1,130 @@
+use crate::MachineError;
+
+/// Control register holding the address of the trap handler. Traps are
+/// disabled while it is 0.
+pub const CTL_TRAP_VECTOR: u8 = 0;
+/// Control register receiving the IP to resume at when a trap is taken:
+/// the address of the faulting instruction, or of the next instruction for
+/// a timer interrupt. `iret` jumps there.
+pub const CTL_TRAP_IP: u8 = 1;
+/// Control register receiving the [TrapCause] code of the last trap.
+pub const CTL_TRAP_CAUSE: u8 = 2;
+/// Control register receiving a value describing the last trap: the
+/// faulting address for memory faults, the instruction opcode for invalid
+/// instructions, the syscall number for unknown syscalls, 0 otherwise.
+pub const CTL_TRAP_VALUE: u8 = 3;
+/// Control register holding the number of steps between two timer
+/// interrupts, or 0 to disable the timer. Writing it restarts the timer.
+pub const CTL_TIMER_INTERVAL: u8 = 4;
+/// Number of control registers.
+pub const NCONTROL: usize = 5;
+
+/// Reason of a trap, stored in [CTL_TRAP_CAUSE].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapCause {
+    /// Unknown opcode or syscall, instruction not supported or invalid
+    /// register.
+    InvalidInstruction = 1,
+    /// Access outside of the memory, refused by a device or by the memory
+    /// permissions, including fetching an instruction.
+    MemoryFault = 2,
+    /// The timer interval elapsed.
+    Timer = 3,
+    DivisionByZero = 4,
+    /// Stack overflow or underflow.
+    StackFault = 5,
+}
+
+impl TrapCause {
+    /// Cause of the trap taken for `error`, or `None` if the error cannot
+    /// be handled by the program, such as an input/output error.
+    pub fn of(error: &MachineError) -> Option<TrapCause> {
+        match error {
+            MachineError::InvalidInstruction { .. }
+            | MachineError::UnsupportedInstruction { .. }
+            | MachineError::InvalidRegister { .. }
+            | MachineError::InvalidControlRegister { .. }
+            | MachineError::UnknownSyscall { .. } => Some(TrapCause::InvalidInstruction),
+            MachineError::IpOutOfBounds { .. }
+            | MachineError::MemoryOutOfBounds { .. }
+            | MachineError::ProtectionFault { .. }
+            | MachineError::InvalidDeviceAccess { .. } => Some(TrapCause::MemoryFault),
+            MachineError::DivisionByZero { .. } => Some(TrapCause::DivisionByZero),
+            MachineError::StackOverflow { .. } | MachineError::StackUnderflow { .. } => {
+                Some(TrapCause::StackFault)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    /// Value stored in [CTL_TRAP_VALUE] for `error`.
+    pub(crate) fn value(error: &MachineError) -> u32 {
+        match *error {
+            MachineError::InvalidInstruction { opcode, .. } => opcode as u32,
+            MachineError::UnknownSyscall { number, .. } => number,
+            MachineError::UnsupportedInstruction { ref site } => site
+                .as_ref()
+                .and_then(|site| site.bytes.first())
+                .map_or(0, |&opcode| opcode as u32),
+            MachineError::IpOutOfBounds { ref site } => site.as_ref().map_or(0, |site| site.ip),
+            MachineError::MemoryOutOfBounds { addr, .. }
+            | MachineError::ProtectionFault { addr, .. }
+            | MachineError::InvalidDeviceAccess { addr, .. } => addr,
+            _ => 0,
+        }
+    }
+}
+
+/// Control registers and trap handling state of a machine.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Traps {
+    pub control: [u32; NCONTROL],
+    /// Set from the moment a trap is taken until `iret`. Faults happening
+    /// meanwhile are not trapped, and the timer is paused.
+    pub in_handler: bool,
+    /// Steps executed since the timer was last restarted.
+    pub ticks: u32,
+}
+
+impl Traps {
+    /// Whether a trap can be taken now.
+    pub fn enabled(&self) -> bool {
+        self.control[CTL_TRAP_VECTOR as usize] != 0 && !self.in_handler
+    }
+
+    /// Enter the handler: record the trap and return the IP to jump to.
+    pub fn enter(&mut self, cause: TrapCause, ip: u32, value: u32) -> u32 {
+        self.control[CTL_TRAP_IP as usize] = ip;
+        self.control[CTL_TRAP_CAUSE as usize] = cause.code();
+        self.control[CTL_TRAP_VALUE as usize] = value;
+        self.in_handler = true;
+        self.control[CTL_TRAP_VECTOR as usize]
+    }
+
+    /// Count a step and tell whether the timer interrupt is due.
+    pub fn tick(&mut self) -> bool {
+        let interval = self.control[CTL_TIMER_INTERVAL as usize];
+        if interval == 0 || self.in_handler {
+            return false;
+        }
+        self.ticks += 1;
+        if self.ticks < interval {
+            return false;
+        }
+        self.ticks = 0;
+        true
+    }
+
+    /// Write a control register, restarting the timer when its interval
+    /// changes.
+    pub fn set(&mut self, reg: u8, value: u32) {
+        self.control[reg as usize] = value;
+        if reg == CTL_TIMER_INTERVAL {
+            self.ticks = 0;
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use interpreter::assembler::{assemble, assemble_object, assemble_program, AssemblerErrorKind};
+use interpreter::Machine;
+
+#[test]
+fn assemble_factorial_example() {
+    let code = assemble(include_str!("../examples/factorial.dis")).unwrap();
+    assert_eq!(&include_bytes!("../examples/factorial.bin")[..], &code[..]);
+}
+
+#[test]
+fn assemble_shipped_listings() {
+    let listings: &[(&str, &[u8])] = &[
+        (
+            include_str!("../examples/99bottles.dis"),
+            include_bytes!("../examples/99bottles.bin"),
+        ),
+        (
+            include_str!("../examples/count.dis"),
+            include_bytes!("../examples/count.bin"),
+        ),
+        (
+            include_str!("../examples/fibonacci.dis"),
+            include_bytes!("../examples/fibonacci.bin"),
+        ),
+        (
+            include_str!("../examples/guess.dis"),
+            include_bytes!("../examples/guess.bin"),
+        ),
+        (
+            include_str!("../examples/hello_world.dis"),
+            include_bytes!("../examples/hello_world.bin"),
+        ),
+        (include_str!("afact.dis"), include_bytes!("afact.bin")),
+        (include_str!("fact.dis"), include_bytes!("fact.bin")),
+        (include_str!("fibo.dis"), include_bytes!("fibo.bin")),
+        (include_str!("function.dis"), include_bytes!("function.bin")),
+        (include_str!("multiply.dis"), include_bytes!("multiply.bin")),
+        (include_str!("push_pop.dis"), include_bytes!("push_pop.bin")),
+        (include_str!("rfact.dis"), include_bytes!("rfact.bin")),
+        (
+            include_str!("rfact_stack.dis"),
+            include_bytes!("rfact_stack.bin"),
+        ),
+        (include_str!("rfact_tr.dis"), include_bytes!("rfact_tr.bin")),
+    ];
+    for (source, binary) in listings {
+        assert_eq!(*binary, &assemble(source).unwrap()[..]);
+    }
+}
+
+#[test]
+fn assemble_every_instruction() {
+    let source = "
+        move r1 <- r2 if r3 != 0
+        store [r2] <- r10
+        load r11 <- [r3]
+        loadimm r2 <- #4096
+        loadimm r3 <- #-4
+        sub r2 <- r2 - r3
+        out r3
+        exit
+        out_number r7
+    ";
+    #[rustfmt::skip]
+    let expected = [
+        1, 1, 2, 3,
+        2, 2, 10,
+        3, 11, 3,
+        4, 2, 0x00, 0x10,
+        4, 3, 0xfc, 0xff,
+        5, 2, 2, 3,
+        6, 3,
+        7,
+        8, 7,
+    ];
+    assert_eq!(&expected[..], &assemble(source).unwrap()[..]);
+}
+
+#[test]
+fn assemble_labels_and_data() {
+    // 0: loadimm r0 <- #end
+    // 4: b'hi'
+    // 6: end: exit
+    let source = "
+        loadimm r0 <- #end ; skip the data
+        b'hi'
+    end:
+        exit
+    ";
+    let program = assemble_program(source).unwrap();
+    assert_eq!(&[4, 0, 6, 0, b'h', b'i', 7], &program.code[..]);
+    assert_eq!(Some(&6), program.labels.get("end"));
+
+    let mut machine = Machine::new(&program.code);
+    machine.run().unwrap();
+    assert_eq!(7, machine.regs()[0]);
+}
+
+#[test]
+fn assemble_escaped_byte_strings() {
+    let source = r#"
+        b"I'm\n\x41\\"
+        [1, 2, 255]
+    "#;
+    let code = assemble(source).unwrap();
+    assert_eq!(b"I'm\nA\\\x01\x02\xff", &code[..]);
+}
+
+#[test]
+fn report_line_and_column() {
+    let err = assemble("exit\n  jump r1").unwrap_err();
+    assert_eq!((2, 3), (err.line, err.column));
+    assert_eq!(
+        AssemblerErrorKind::UnknownMnemonic("jump".to_string()),
+        err.kind
+    );
+
+    let err = assemble("sub r1 <- r2 + r3").unwrap_err();
+    assert_eq!((1, 14), (err.line, err.column));
+    assert_eq!(AssemblerErrorKind::Expected("-"), err.kind);
+
+    let err = assemble("out rx").unwrap_err();
+    assert_eq!((1, 5), (err.line, err.column));
+    assert_eq!(
+        AssemblerErrorKind::InvalidRegister("rx".to_string()),
+        err.kind
+    );
+
+    let err = assemble("exit\n\n  loadimm r0 <- #nowhere").unwrap_err();
+    assert_eq!((3, 3), (err.line, err.column));
+    assert_eq!(
+        AssemblerErrorKind::UndefinedLabel("nowhere".to_string()),
+        err.kind
+    );
+
+    let err = assemble("loadimm r1 <- #70000").unwrap_err();
+    assert_eq!(AssemblerErrorKind::ImmediateOutOfRange(70000), err.kind);
+    let source = format!("b'{}'\na: loadimm r1 <- #a", "x".repeat(0x8000));
+    let err = assemble(&source).unwrap_err();
+    assert_eq!(AssemblerErrorKind::ImmediateOutOfRange(0x8000), err.kind);
+    let source = format!("b'{}'\na: call #a", "x".repeat(0x8000));
+    assert!(assemble(&source).is_ok());
+
+    let err = assemble("a:\nexit\na:").unwrap_err();
+    assert_eq!((3, 1), (err.line, err.column));
+    assert_eq!(
+        AssemblerErrorKind::DuplicateLabel("a".to_string()),
+        err.kind
+    );
+
+    let err = assemble("  0000   exit\n  0002   exit").unwrap_err();
+    assert_eq!((2, 3), (err.line, err.column));
+    assert_eq!(
+        AssemblerErrorKind::AddressMismatch {
+            expected: 1,
+            found: 2
+        },
+        err.kind
+    );
+
+    let err = assemble("exit r1").unwrap_err();
+    assert_eq!((1, 6), (err.line, err.column));
+    assert_eq!(AssemblerErrorKind::TrailingCharacters, err.kind);
+}
+
+#[test]
+fn label_length() {
+    let longest = "l".repeat(255);
+    assert!(assemble(&format!("{longest}: loadimm r0 <- #{longest}")).is_ok());
+
+    let too_long = "l".repeat(256);
+    let kind = AssemblerErrorKind::LabelTooLong(too_long.clone());
+    let err = assemble(&format!("exit\n  {too_long}: exit")).unwrap_err();
+    assert_eq!((2, 3), (err.line, err.column));
+    assert_eq!(kind, err.kind);
+
+    let err = assemble_object(&format!("loadimm r1 <- #{too_long}")).unwrap_err();
+    assert_eq!((1, 16), (err.line, err.column));
+    assert_eq!(kind, err.kind);
+
+    let err = assemble_object(&format!("exit\n.global a, {too_long}")).unwrap_err();
+    assert_eq!((2, 12), (err.line, err.column));
+    assert_eq!(kind, err.kind);
+}
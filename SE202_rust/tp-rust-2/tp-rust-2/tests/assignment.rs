@@ -11,6 +11,7 @@ fn create_machine(code: &[u8]) -> (Machine, Vec<u8>) {
 }
 
 #[test]
+#[allow(clippy::manual_repeat_n)]
 fn test_assignment() {
     // Test that the examples given in the assignment text
     // behave as expected.
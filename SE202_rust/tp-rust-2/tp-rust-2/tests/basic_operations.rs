@@ -141,6 +141,7 @@ fn test_load_out_of_bounds() {
 }
 
 #[test]
+#[allow(clippy::zero_prefixed_literal)]
 fn test_store() {
     // 0: store [r0] <- r1
     // 3:
@@ -285,6 +286,7 @@ fn end_of_memory() {
 }
 
 #[test]
+#[allow(clippy::needless_range_loop)]
 fn no_wraparound_past_end_of_memory() {
     // memory_size-4: move r1 <- r1 if r1
     // 0:             exit
@@ -0,0 +1,266 @@
+use interpreter::assembler::assemble;
+use interpreter::{Bus, Console, Devices, IoDevice, Machine, MachineError, DEVICE_BASE};
+use std::cell::RefCell;
+use std::process::Command;
+use std::rc::Rc;
+
+fn machine_with_devices(source: &str) -> Machine {
+    let mut machine = Machine::new(&assemble(source).unwrap());
+    machine.attach_bus(Box::new(Devices::default()));
+    machine
+}
+
+#[test]
+fn console_port_output() {
+    let mut machine = machine_with_devices(
+        "
+        loadimm r1 <- #4080
+        loadimm r2 <- #72
+        store [r1] <- r2
+        loadimm r2 <- #0xe9
+        store [r1] <- r2
+        out r2
+        exit
+        ",
+    );
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    // The port writes raw bytes, `out` writes characters.
+    assert_eq!(&b"H\xe9\xc3\xa9"[..], &out[..]);
+    // The memory below the device is left untouched.
+    assert_eq!(&[0; 4], &machine.memory()[4080..4084]);
+}
+
+#[test]
+fn console_port_input() {
+    let mut machine = machine_with_devices(
+        "
+        loadimm r1 <- #4080
+        load r2 <- [r1]
+        load r3 <- [r1]
+        load r4 <- [r1]
+        exit
+        ",
+    );
+    machine
+        .run_on(&mut Console::new(&b"ok"[..], Vec::new()))
+        .unwrap();
+    assert_eq!(&[b'o' as u32, b'k' as u32, u32::MAX], &machine.regs()[2..5]);
+}
+
+#[test]
+fn cycle_counter() {
+    let mut machine = machine_with_devices(
+        "
+        loadimm r1 <- #4084
+        load r2 <- [r1]
+        loadimm r3 <- #1000
+        store [r1] <- r3
+        load r4 <- [r1]
+        load r5 <- [r1]
+        exit
+        ",
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    // The counter is read during the step, and the step setting it counts.
+    assert_eq!(
+        &[1, 1001, 1002],
+        &[machine.regs()[2], machine.regs()[4], machine.regs()[5]]
+    );
+}
+
+#[test]
+fn random_source() {
+    let source = "
+        loadimm r1 <- #4088
+        load r2 <- [r1]
+        load r3 <- [r1]
+        store [r1] <- r10
+        load r4 <- [r1]
+        exit
+        ";
+    let run = |seed: u32| {
+        let mut machine = machine_with_devices(source);
+        machine.set_reg(10, seed).unwrap();
+        machine.run_on(&mut Vec::new()).unwrap();
+        machine.regs()[2..5].to_vec()
+    };
+    let first = run(1);
+    assert_ne!(first[0], first[1]);
+    // Deterministic unless seeded.
+    assert_eq!(first, run(1));
+    assert_eq!(first[..2], run(2)[..2]);
+    assert_ne!(first[2], run(2)[2]);
+    // xorshift32 from seed 1
+    assert_eq!(270369, first[2]);
+}
+
+#[test]
+fn plain_memory_without_bus() {
+    // 0: store [r1] <- r2
+    // 3: load r3 <- [r1]
+    // 6: exit
+    let mut machine = Machine::new(&[2, 1, 2, 3, 3, 1, 7]);
+    machine.set_reg(1, DEVICE_BASE).unwrap();
+    machine.set_reg(2, 0x41).unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert!(out.is_empty());
+    assert_eq!(0x41, machine.regs()[3]);
+    assert_eq!(0x41, machine.memory()[DEVICE_BASE as usize]);
+
+    // Detaching the bus brings the memory back.
+    let mut machine = Machine::new(&[2, 1, 2, 7]);
+    machine.attach_bus(Box::new(Devices::default()));
+    assert!(machine.detach_bus().is_some());
+    machine.set_reg(1, DEVICE_BASE).unwrap();
+    machine.run_on(&mut out).unwrap();
+    assert!(out.is_empty());
+}
+
+#[test]
+fn invalid_device_accesses() {
+    for addr in [4078, 4081, 4089, 4090] {
+        // 0: store [r1] <- r2
+        let mut machine = Machine::new(&[2, 1, 2]);
+        machine.attach_bus(Box::new(Devices::default()));
+        machine.set_reg(1, addr).unwrap();
+        assert!(
+            matches!(
+                machine.step_on(&mut Vec::new()),
+                Err(MachineError::InvalidDeviceAccess { addr: a, .. }) if a == addr
+            ),
+            "address {addr}"
+        );
+    }
+
+    // Right after the devices is plain memory again.
+    // 0: store [r1] <- r2
+    // 3: load r3 <- [r1]
+    let mut machine = Machine::new(&[2, 1, 2, 3, 3, 1]);
+    machine.attach_bus(Box::new(Devices::default()));
+    machine.set_reg(1, 4092).unwrap();
+    machine.set_reg(2, 17).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(17, machine.regs()[3]);
+}
+
+/// 8×8 LED matrix, one byte per row, mapped at 0x800.
+struct LedMatrix {
+    rows: Rc<RefCell<[u8; 8]>>,
+}
+
+impl Bus for LedMatrix {
+    fn maps(&self, addr: u32, width: usize) -> bool {
+        (0x800..0x808).contains(&addr) || (0x800..0x808).contains(&(addr + width as u32 - 1))
+    }
+
+    fn load(
+        &mut self,
+        addr: u32,
+        width: usize,
+        _io: &mut dyn IoDevice,
+    ) -> Result<u32, MachineError> {
+        let rows = self.rows.borrow();
+        let start = (addr - 0x800) as usize;
+        let bytes = rows
+            .get(start..start + width)
+            .ok_or(MachineError::InvalidDeviceAccess {
+                addr,
+                width,
+                site: None,
+            })?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &b| (value << 8) | b as u32))
+    }
+
+    fn store(
+        &mut self,
+        addr: u32,
+        width: usize,
+        value: u32,
+        _io: &mut dyn IoDevice,
+    ) -> Result<(), MachineError> {
+        let mut rows = self.rows.borrow_mut();
+        let start = (addr - 0x800) as usize;
+        let bytes =
+            rows.get_mut(start..start + width)
+                .ok_or(MachineError::InvalidDeviceAccess {
+                    addr,
+                    width,
+                    site: None,
+                })?;
+        bytes.copy_from_slice(&value.to_le_bytes()[..width]);
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_bus() {
+    let rows = Rc::new(RefCell::new([0; 8]));
+    let mut machine = Machine::new(
+        &assemble(
+            "
+            loadimm r1 <- #0x800
+            loadimm r2 <- #0x1818
+            loadimm r3 <- #0x3c
+            sub r2 <- r2 - r3
+            store [r1] <- r2
+            loadimm r1 <- #0x804
+            loadimm r2 <- #0x7e3c
+            store [r1] <- r2
+            load r4 <- [r1]
+            exit
+            ",
+        )
+        .unwrap(),
+    );
+    machine.attach_bus(Box::new(LedMatrix { rows: rows.clone() }));
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!([0xdc, 0x17, 0, 0, 0x3c, 0x7e, 0, 0], *rows.borrow());
+    assert_eq!(0x7e3c, machine.regs()[4]);
+
+    // Partially overlapping access.
+    let mut machine = Machine::new(&[2, 1, 2]);
+    machine.attach_bus(Box::new(LedMatrix { rows }));
+    machine.set_reg(1, 0x806).unwrap();
+    assert!(matches!(
+        machine.step_on(&mut Vec::new()),
+        Err(MachineError::InvalidDeviceAccess { addr: 0x806, .. })
+    ));
+}
+
+#[test]
+fn command_line_devices() {
+    let path = std::env::temp_dir().join(format!("devices-{}.bin", std::process::id()));
+    let code = assemble(
+        "
+        loadimm r1 <- #4080
+        loadimm r2 <- #33
+        store [r1] <- r2
+        exit
+        ",
+    )
+    .unwrap();
+    std::fs::write(&path, code).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--devices"]);
+    assert!(output.status.success());
+    assert_eq!(&b"!"[..], &output.stdout[..]);
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
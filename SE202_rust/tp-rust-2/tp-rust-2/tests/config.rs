@@ -0,0 +1,144 @@
+use interpreter::{Isa, Machine, MachineConfig};
+
+const MEMORY_64K: MachineConfig = MachineConfig {
+    memory_size: 65536,
+    nregs: 16,
+    isa: Isa::Base,
+    stack: None,
+    input: false,
+    traps: false,
+    syscalls: false,
+    protection: None,
+};
+
+#[test]
+fn default_config() {
+    assert_eq!(
+        MachineConfig {
+            memory_size: 4096,
+            nregs: 16,
+            isa: Isa::Base,
+            stack: None,
+            input: false,
+            traps: false,
+            syscalls: false,
+            protection: None,
+        },
+        MachineConfig::default()
+    );
+    let machine = Machine::with_config(&[1, 2, 3], MachineConfig::default());
+    assert_eq!(4096, machine.memory().len());
+    assert_eq!(16, machine.regs().len());
+    assert_eq!(&[1, 2, 3], &machine.memory()[..3]);
+}
+
+#[test]
+fn large_memory() {
+    let mut machine = Machine::with_config(&[0; 5000], MEMORY_64K);
+    assert_eq!(65536, machine.memory().len());
+    assert!(machine.regs().iter().all(|v| *v == 0));
+    machine.set_reg(0, 4999).unwrap();
+    assert!(machine.step().is_err());
+}
+
+#[test]
+#[should_panic]
+fn create_with_too_large_a_memory_64k() {
+    Machine::with_config(&[0; 65537], MEMORY_64K);
+}
+
+#[test]
+fn store_and_load_in_64k() {
+    // 0: store [r1] <- r2
+    // 3: load r3 <- [r1]
+    // 6: exit
+    let mut machine = Machine::with_config(&[2, 1, 2, 3, 3, 1, 7], MEMORY_64K);
+    machine.set_reg(1, 65532).unwrap();
+    machine.set_reg(2, 0xdeadbeef).unwrap();
+    machine.run().unwrap();
+    assert_eq!(0xdeadbeef, machine.regs()[3]);
+    assert_eq!(&[0xef, 0xbe, 0xad, 0xde], &machine.memory()[65532..]);
+
+    // Accesses past the configured size still fail.
+    for addr in [65533, 65536, 0xFFFF_FFFF] {
+        let mut machine = Machine::with_config(&[2, 1, 2], MEMORY_64K);
+        machine.set_reg(1, addr).unwrap();
+        assert!(machine.step().is_err());
+
+        let mut machine = Machine::with_config(&[3, 2, 1], MEMORY_64K);
+        machine.set_reg(1, addr).unwrap();
+        assert!(machine.step().is_err());
+    }
+}
+
+#[test]
+fn execute_past_4k() {
+    // 8000: out_number r1
+    // 8002: exit
+    let mut memory = vec![0; 8000];
+    memory.extend([8, 1, 7]);
+    let mut machine = Machine::with_config(&memory, MEMORY_64K);
+    machine.set_reg(0, 8000).unwrap();
+    machine.set_reg(1, 42).unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"42"[..], &out[..]);
+    assert_eq!(8003, machine.regs()[0]);
+}
+
+#[test]
+fn exec_at_end_of_64k() {
+    let mut memory = vec![0; 65536];
+    memory[65535] = 7;
+    let mut machine = Machine::with_config(&memory, MEMORY_64K);
+    machine.set_reg(0, 65535).unwrap();
+    assert!(machine.step().unwrap());
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn big_stack_in_64k() {
+    // Skip the `loadimm r2 <- #4096` setting the stack top, and put the
+    // stack at the end of the 64 KiB memory instead (65536 does not fit in
+    // a loadimm immediate).
+    let mut machine = Machine::with_config(include_bytes!("push_pop.bin"), MEMORY_64K);
+    machine.set_reg(0, 4).unwrap();
+    machine.set_reg(2, 65536).unwrap();
+    machine.run().unwrap();
+    assert_eq!(26, machine.regs()[1]);
+    assert_eq!(15, machine.regs()[2]);
+}
+
+#[test]
+fn more_registers() {
+    // 0: loadimm r20 <- #7
+    // 4: sub r31 <- r0 - r20
+    // 8: exit
+    let code = [4, 20, 7, 0, 5, 31, 0, 20, 7];
+    let config = MachineConfig {
+        nregs: 32,
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&code, config);
+    machine.run().unwrap();
+    assert_eq!(32, machine.regs().len());
+    assert_eq!(1, machine.regs()[31]);
+    assert!(machine.set_reg(31, 0).is_ok());
+    assert!(machine.set_reg(32, 0).is_err());
+
+    // The same program is rejected with the default register count.
+    let mut machine = Machine::new(&code);
+    assert!(machine.step().is_err());
+}
+
+#[test]
+#[should_panic]
+fn too_many_registers() {
+    Machine::with_config(
+        &[],
+        MachineConfig {
+            nregs: 257,
+            ..MachineConfig::default()
+        },
+    );
+}
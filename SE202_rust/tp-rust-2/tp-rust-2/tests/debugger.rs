@@ -0,0 +1,217 @@
+use interpreter::assembler::assemble_program;
+use interpreter::debugger::Debugger;
+use interpreter::{Machine, MachineConfig};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn debug(source: &str, script: &str) -> (Debugger, String) {
+    let program = assemble_program(source).unwrap();
+    let config = MachineConfig {
+        input: true,
+        ..MachineConfig::default()
+    };
+    let machine = Machine::with_config(&program.code, config);
+    let mut debugger = Debugger::new(machine, program.labels);
+    let mut out = Vec::new();
+    debugger.repl(script.as_bytes(), &mut out, false).unwrap();
+    (debugger, String::from_utf8(out).unwrap())
+}
+
+const COUNTDOWN: &str = "
+    loadimm r1 <- #3
+    loadimm r2 <- #1
+    loadimm r5 <- #100
+loop:
+    out_number r1
+    sub r1 <- r1 - r2
+    store [r5] <- r1
+    loadimm r3 <- #loop
+    move r0 <- r3 if r1 != 0
+    exit
+";
+
+#[test]
+fn step_and_registers() {
+    let (debugger, out) = debug(COUNTDOWN, "step 3\nregs\n");
+    assert_eq!(12, debugger.machine().regs()[0]);
+    assert!(out.contains("r1  0x00000003           3\n"));
+    assert!(out.contains("r5  0x00000064         100\n"));
+}
+
+#[test]
+fn breakpoint_on_label() {
+    let (debugger, out) = debug(COUNTDOWN, "break loop\ncontinue\ncontinue\n");
+    assert_eq!(12, debugger.machine().regs()[0]);
+    assert_eq!(
+        "breakpoint at 0012 <loop>\n\
+         breakpoint hit\n\
+         stopped at 0012 <loop>: out_number r1\n\
+         3breakpoint hit\n\
+         stopped at 0012 <loop>: out_number r1\n",
+        out
+    );
+}
+
+#[test]
+fn breakpoint_on_address_and_delete() {
+    let (debugger, out) = debug(COUNTDOWN, "b 0x15\nc\nd 21\nc\nstep\n");
+    assert_eq!(
+        "breakpoint at 0021\n\
+         3breakpoint hit\n\
+         stopped at 0021 <loop+9>: loadimm r3 <- #12\n\
+         deleted breakpoint at 0021\n\
+         21program exited\n\
+         error: the program is not running\n",
+        out
+    );
+    assert_eq!(0, debugger.machine().regs()[1]);
+}
+
+#[test]
+fn watchpoint_on_memory() {
+    let (_, out) = debug(COUNTDOWN, "watch 100\ncontinue\nmem 96 8\n");
+    assert_eq!(
+        "watchpoint on 0100 (4 bytes)\n\
+         3watchpoint 0100 changed\n\
+         \x20 old: 00 00 00 00\n\
+         \x20 new: 02 00 00 00\n\
+         stopped at 0021 <loop+9>: loadimm r3 <- #12\n\
+         0096: 00 00 00 00 02 00 00 00\n",
+        out
+    );
+}
+
+#[test]
+fn report_machine_errors() {
+    let (_, out) = debug("loadimm r1 <- #-1\nload r2 <- [r1]\n", "continue\nregs\n");
+    assert!(out.starts_with("machine error: "));
+    assert!(out.contains("r1  0xffffffff          -1\n"));
+}
+
+#[test]
+fn set_register_and_disassemble() {
+    let (debugger, out) = debug(COUNTDOWN, "set r0 loop\ndisas loop 2\nfrob\nquit\nstep\n");
+    assert_eq!(12, debugger.machine().regs()[0]);
+    assert_eq!(
+        "r0 = 12\n\
+         loop:\n\
+         =>0012   out_number r1\n\
+         \x20 0014   sub r1 <- r1 - r2\n\
+         error: unknown command `frob`, try `help`\n",
+        out
+    );
+}
+
+#[test]
+fn step_back_and_reverse_continue() {
+    let (debugger, out) = debug(
+        COUNTDOWN,
+        "step 5\nback 2\nback 10\nc\nbreak loop\nrc\nregs\nwatch 100\nrc\n",
+    );
+    assert_eq!(18, debugger.machine().regs()[0]);
+    assert_eq!(
+        "3stopped at 0018 <loop+6>: store [r5] <- r1\n\
+         stopped at 0012 <loop>: out_number r1\n\
+         reached the start of the recorded history\n\
+         stopped at 0000: loadimm r1 <- #3\n\
+         321program exited\n\
+         breakpoint at 0012 <loop>\n\
+         breakpoint hit\n\
+         stopped at 0012 <loop>: out_number r1\n",
+        &out[..out.find("r0 ").unwrap()]
+    );
+    assert!(out.contains("r1  0x00000001           1\n"));
+    assert!(out.ends_with(
+        "watchpoint on 0100 (4 bytes)\n\
+         watchpoint 0100 changed\n\
+         \x20 old: 01 00 00 00\n\
+         \x20 new: 02 00 00 00\n\
+         stopped at 0018 <loop+6>: store [r5] <- r1\n"
+    ));
+}
+
+#[test]
+fn program_input() {
+    let guess = include_str!("../examples/guess.dis");
+    let (_, out) = debug(guess, "input 12\ninput   37\ncontinue\n");
+    assert_eq!(
+        "Guess? Too low\n\
+         Guess? Found in 2 tries\n\
+         program exited\n",
+        out
+    );
+
+    let (_, out) = debug(guess, "input 12\ncontinue\n");
+    assert_eq!(
+        "Guess? Too low\n\
+         Guess? machine error: invalid number in the input at 0032 [0a 04]\n",
+        out
+    );
+}
+
+#[test]
+fn scripted_binary() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vmdbg"))
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/rfact.bin"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"set r10 5\nbreak mult\ncontinue\ndelete mult\ncontinue\nregs\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout).unwrap();
+    assert!(out.contains("stopped at "));
+    assert!(out.contains("<mult>: "));
+    assert!(out.contains("program exited\n"));
+    assert!(out.contains("r11 0x00000078         120\n"));
+}
+
+#[test]
+fn scripted_with_stack() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_vmdbg"))
+        .args(["--isa", "base", "--stack", "r2"])
+        .arg(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/rfact_stack.dis"
+        ))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"set r10 5\nbreak mult_loop\ncontinue\ndelete mult_loop\ncontinue\nregs\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let out = String::from_utf8(output.stdout).unwrap();
+    assert!(out.contains("stopped at 0016 <mult_loop>: "));
+    assert!(out.contains("program exited\n"));
+    assert!(out.contains("r11 0x00000078         120\n"));
+}
+
+#[test]
+fn missing_program() {
+    for name in ["missing.dis", "missing.bin"] {
+        let path = std::env::temp_dir().join(name);
+        let output = Command::new(env!("CARGO_BIN_EXE_vmdbg"))
+            .arg(&path)
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert_eq!(Some(1), output.status.code());
+        let error = String::from_utf8(output.stderr).unwrap();
+        assert!(
+            error.starts_with(&format!("{}: ", path.display())),
+            "{error}"
+        );
+    }
+}
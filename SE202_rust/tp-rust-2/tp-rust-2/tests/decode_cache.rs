@@ -0,0 +1,121 @@
+use interpreter::assembler::assemble;
+use interpreter::{Isa, Machine, MachineConfig};
+
+const EXTENDED: MachineConfig = MachineConfig {
+    memory_size: 4096,
+    nregs: 16,
+    isa: Isa::Extended,
+    stack: None,
+    input: false,
+    traps: false,
+    syscalls: false,
+    protection: None,
+};
+
+/// Program printing the immediate of its first instruction twice, after
+/// replacing it with 7 the first time.
+const SELF_MODIFYING: &str = "
+start:
+    loadimm r1 <- #1
+    out_number r1
+    move r0 <- r5 if r6 != 0
+    loadimm r5 <- #end
+    loadimm r6 <- #1
+    loadimm r2 <- #2
+    loadimm r3 <- #7
+    store8 [r2] <- r3
+    loadimm r0 <- #start
+end:
+    exit
+";
+
+fn run(machine: &mut Machine) -> String {
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn self_modifying_code() {
+    let code = assemble(SELF_MODIFYING).unwrap();
+    let mut machine = Machine::with_config(&code, EXTENDED);
+    assert_eq!("17", run(&mut machine));
+
+    let mut machine = Machine::with_config(&code, EXTENDED);
+    machine.disable_decode_cache();
+    assert_eq!("17", run(&mut machine));
+}
+
+#[test]
+fn undone_writes() {
+    let code = assemble(SELF_MODIFYING).unwrap();
+    let mut machine = Machine::with_config(&code, EXTENDED);
+    machine.enable_history(1000);
+    assert_eq!("17", run(&mut machine));
+    assert!(!machine.run_back_until(|_| false));
+    assert_eq!(&code[..], &machine.memory()[..code.len()]);
+    assert_eq!("17", run(&mut machine));
+}
+
+#[test]
+fn syscall_writes() {
+    let code = assemble(
+        "
+    start:
+        loadimm r1 <- #1
+        out_number r1
+        move r0 <- r5 if r6 != 0
+        loadimm r5 <- #end
+        loadimm r6 <- #1
+        loadimm r1 <- #9
+        syscall
+        loadimm r0 <- #start
+    end:
+        exit
+    ",
+    )
+    .unwrap();
+    let config = MachineConfig {
+        syscalls: true,
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&code, config);
+    machine.register_syscall(
+        9,
+        Box::new(|_, memory| {
+            memory[2] = 7;
+            Ok(())
+        }),
+    );
+    assert_eq!("17", run(&mut machine));
+}
+
+#[test]
+fn restored_code() {
+    // 0: out_number r0
+    // 2: exit
+    let mut machine = Machine::new(&[8, 0, 7]);
+    assert_eq!("2", run(&mut machine));
+    // 0: out_number r1
+    // 2: exit
+    let mut other = Machine::new(&[8, 1, 7]);
+    other.set_reg(1, 42).unwrap();
+    machine.restore(&other.snapshot()).unwrap();
+    assert_eq!("42", run(&mut machine));
+}
+
+#[test]
+fn same_results_without_cache() {
+    let program = include_bytes!("../examples/99bottles.bin");
+    let mut cached = Machine::new(program);
+    let mut decoded = Machine::new(program);
+    decoded.disable_decode_cache();
+    assert_eq!(run(&mut decoded), run(&mut cached));
+    assert_eq!(decoded.regs(), cached.regs());
+    assert_eq!(decoded.memory(), cached.memory());
+
+    // The cache starts empty when enabled again.
+    decoded.enable_decode_cache();
+    decoded.restore(&Machine::new(program).snapshot()).unwrap();
+    assert_eq!(run(&mut Machine::new(program)), run(&mut decoded));
+}
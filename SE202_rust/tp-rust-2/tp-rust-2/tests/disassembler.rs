@@ -0,0 +1,125 @@
+use interpreter::assembler::assemble;
+use interpreter::disassembler::{
+    disassemble, disassemble_with_regs, jump_targets, listing, DecodedInstruction,
+};
+use interpreter::Instruction;
+
+const BINARIES: &[&[u8]] = &[
+    include_bytes!("../examples/99bottles.bin"),
+    include_bytes!("../examples/count.bin"),
+    include_bytes!("../examples/factorial.bin"),
+    include_bytes!("../examples/fibonacci.bin"),
+    include_bytes!("../examples/guess.bin"),
+    include_bytes!("../examples/hello_world.bin"),
+    include_bytes!("afact.bin"),
+    include_bytes!("fact.bin"),
+    include_bytes!("fibo.bin"),
+    include_bytes!("function.bin"),
+    include_bytes!("multiply.bin"),
+    include_bytes!("push_pop.bin"),
+    include_bytes!("rfact.bin"),
+    include_bytes!("rfact_tr.bin"),
+];
+
+#[test]
+fn disassemble_instructions_and_data() {
+    // 0: loadimm r1 <- #-2
+    // 4: out r1
+    // 6: b'Hi'
+    // 8: exit
+    let decoded = disassemble(&[4, 1, 0xfe, 0xff, 6, 1, b'H', b'i', 7]);
+    assert_eq!(
+        vec![
+            DecodedInstruction {
+                address: 0,
+                bytes: vec![4, 1, 0xfe, 0xff],
+                instruction: Some(Instruction::LoadImm { reg: 1, value: -2 })
+            },
+            DecodedInstruction {
+                address: 4,
+                bytes: vec![6, 1],
+                instruction: Some(Instruction::Out { reg: 1 })
+            },
+            DecodedInstruction {
+                address: 6,
+                bytes: vec![b'H', b'i'],
+                instruction: None
+            },
+            DecodedInstruction {
+                address: 8,
+                bytes: vec![7],
+                instruction: Some(Instruction::Exit)
+            },
+        ],
+        decoded
+    );
+    let text: Vec<String> = decoded.iter().map(|insn| insn.to_string()).collect();
+    assert_eq!(vec!["loadimm r1 <- #-2", "out r1", "b'Hi'", "exit"], text);
+}
+
+#[test]
+fn reject_invalid_operands() {
+    // Register out of range, then a truncated instruction.
+    let decoded = disassemble(&[6, 200, 5, 1, 2]);
+    assert_eq!(1, decoded.len());
+    assert_eq!(None, decoded[0].instruction);
+}
+
+#[test]
+fn configured_register_count() {
+    // 0: loadimm r20 <- #8
+    // 4: move r0 <- r20 if r20 != 0
+    // 8: exit
+    let code = [4, 20, 8, 0, 1, 0, 20, 20, 7];
+    let decoded = disassemble_with_regs(&code, 32);
+    assert_eq!(3, decoded.len());
+    assert!(decoded.iter().all(|insn| insn.instruction.is_some()));
+    assert_eq!(
+        Some(&"label_0008".to_string()),
+        jump_targets(&decoded).get(&8)
+    );
+
+    let decoded = disassemble(&code);
+    assert_eq!(None, decoded[0].instruction);
+}
+
+#[test]
+fn listing_format() {
+    let source = r#"  0000   loadimm r2 <- #4096
+loop:
+  0004   sub r1 <- r1 - r3
+  0008   loadimm r5 <- #done
+  0012   move r0 <- r5 if r1 != 0
+  0016   loadimm r0 <- #loop
+done:
+  0020   store [r2] <- r1
+  0023   load r4 <- [r2]
+  0026   out_number r4
+  0028   exit
+  ???? b"I'm\x00\x00"
+"#;
+    let code = assemble(source).unwrap();
+    let expected = source
+        .replace("loop", "label_0004")
+        .replace("done", "label_0020");
+    assert_eq!(expected, listing(&disassemble(&code)));
+}
+
+#[test]
+fn jump_target_labels() {
+    let targets = jump_targets(&disassemble(include_bytes!("../examples/factorial.bin")));
+    // `loop`, `ite_then_1` and `print`
+    for address in [91, 407, 640] {
+        assert_eq!(Some(&format!("label_{address:04}")), targets.get(&address));
+    }
+    // `str_1` is only loaded into r10, not jumped to.
+    assert!(!targets.contains_key(&699));
+}
+
+#[test]
+fn listing_round_trip() {
+    for binary in BINARIES {
+        let text = listing(&disassemble(binary));
+        assert_eq!(*binary, &assemble(&text).unwrap()[..]);
+    }
+}
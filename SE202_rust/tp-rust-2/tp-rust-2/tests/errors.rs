@@ -0,0 +1,167 @@
+use interpreter::assembler::assemble;
+use interpreter::{Access, FaultSite, Instruction, Machine, MachineError};
+use std::error::Error;
+use std::process::Command;
+
+fn fault(code: &[u8]) -> MachineError {
+    Machine::new(code).run_on(&mut Vec::new()).unwrap_err()
+}
+
+fn site(ip: u32, bytes: &[u8]) -> Option<FaultSite> {
+    Some(FaultSite {
+        ip,
+        bytes: bytes.to_vec(),
+    })
+}
+
+#[test]
+fn faults_report_the_instruction() {
+    // 0: loadimm r1 <- #4094
+    // 4: load r2 <- [r1]
+    let error = fault(&[4, 1, 0xfe, 0x0f, 3, 2, 1]);
+    assert!(matches!(
+        error,
+        MachineError::MemoryOutOfBounds {
+            addr: 4094,
+            width: 4,
+            access: Access::Load,
+            ..
+        }
+    ));
+    assert_eq!(site(4, &[3, 2, 1]).as_ref(), error.site());
+
+    // 0: sub r1 <- r2 - r20
+    let error = fault(&[5, 1, 2, 20]);
+    assert!(matches!(
+        error,
+        MachineError::InvalidRegister { reg: 20, .. }
+    ));
+    assert_eq!(site(0, &[5, 1, 2, 20]).as_ref(), error.site());
+
+    // 0: out r1
+    // 2: invalid opcode
+    let error = fault(&[6, 1, 200, 1, 2]);
+    assert!(matches!(
+        error,
+        MachineError::InvalidInstruction { opcode: 200, .. }
+    ));
+    assert_eq!(site(2, &[200]).as_ref(), error.site());
+}
+
+#[test]
+fn ip_out_of_bounds() {
+    // Truncated instruction at the end of the memory.
+    let mut code = vec![0; 4096];
+    code[4094..].copy_from_slice(&[4, 1]);
+    let mut machine = Machine::new(&code);
+    machine.set_reg(0, 4094).unwrap();
+    let error = machine.step_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(error, MachineError::IpOutOfBounds { .. }));
+    assert_eq!(site(4094, &[4, 1]).as_ref(), error.site());
+
+    // Outside of the memory.
+    let mut machine = Machine::new(&[]);
+    machine.set_reg(0, 5000).unwrap();
+    let error = machine.step_on(&mut Vec::new()).unwrap_err();
+    assert_eq!(site(5000, &[]).as_ref(), error.site());
+}
+
+#[test]
+fn errors_outside_of_steps_have_no_site() {
+    let mut machine = Machine::new(&[]);
+    let error = machine.set_reg(16, 0).unwrap_err();
+    assert!(matches!(
+        error,
+        MachineError::InvalidRegister {
+            reg: 16,
+            site: None
+        }
+    ));
+
+    let error = machine
+        .execute(Instruction::Out { reg: 99 }, &mut Vec::new())
+        .unwrap_err();
+    assert!(error.site().is_none());
+
+    let error = machine.run_with_limit(&mut Vec::new(), 0).unwrap_err();
+    assert!(error.site().is_none());
+}
+
+#[test]
+fn display() {
+    let error = fault(&[5, 1, 2, 20]);
+    assert_eq!(
+        "invalid register r20 at 0000 [05 01 02 14]",
+        error.to_string()
+    );
+
+    let error = fault(&assemble("loadimm r1 <- #4093\nstore [r1] <- r2").unwrap());
+    assert_eq!(
+        "4-byte store to address 4093 out of memory at 0004 [02 01 02]",
+        error.to_string()
+    );
+
+    let error = Machine::new(&[0])
+        .run_with_limit(&mut Vec::new(), 0)
+        .unwrap_err();
+    assert_eq!("step limit exceeded after 0 steps", error.to_string());
+}
+
+/// Device whose output always fails.
+struct Broken;
+
+impl std::io::Write for Broken {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("broken pipe"))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn io_errors_have_a_source() {
+    // 0: out_number r1
+    let error = Machine::new(&[8, 1]).run_on(&mut Broken).unwrap_err();
+    assert!(matches!(error, MachineError::IoError { .. }));
+    assert_eq!("broken pipe", error.source().unwrap().to_string());
+    assert_eq!(
+        "input/output error: broken pipe at 0000 [08 01]",
+        error.to_string()
+    );
+}
+
+#[test]
+fn command_line_crash_report() {
+    let path = std::env::temp_dir().join(format!("errors-{}.bin", std::process::id()));
+    let code = assemble(
+        "
+        loadimm r1 <- #42
+        loadimm r15 <- #-1
+        out_number r1
+        store [r15] <- r1
+        ",
+    )
+    .unwrap();
+    std::fs::write(&path, code).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(&b"42"[..], &output.stdout[..]);
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(
+        "error: 4-byte store to address 4294967295 out of memory at 0010 [02 0f 01]\n\
+         instruction: 0010   store [r15] <- r1\n\
+         registers:\n   \
+         r0 = 0x0000000d   r1 = 0x0000002a   r2 = 0x00000000   r3 = 0x00000000\n   \
+         r4 = 0x00000000   r5 = 0x00000000   r6 = 0x00000000   r7 = 0x00000000\n   \
+         r8 = 0x00000000   r9 = 0x00000000  r10 = 0x00000000  r11 = 0x00000000\n  \
+         r12 = 0x00000000  r13 = 0x00000000  r14 = 0x00000000  r15 = 0xffffffff\n",
+        report
+    );
+}
@@ -0,0 +1,262 @@
+use interpreter::assembler::assemble;
+use interpreter::{AluOp, Instruction, Isa, Machine, MachineConfig, MachineError};
+
+const EXTENDED: MachineConfig = MachineConfig {
+    memory_size: 4096,
+    nregs: 16,
+    isa: Isa::Extended,
+    stack: None,
+    input: false,
+    traps: false,
+    syscalls: false,
+    protection: None,
+};
+
+/// Run the 4-byte instruction `[opcode, 1, 2, 3]` with r2 and r3 set to
+/// `b` and `c`, and return r1.
+fn run_op(opcode: u8, b: u32, c: u32) -> Result<u32, MachineError> {
+    let mut machine = Machine::with_config(&[opcode, 1, 2, 3], EXTENDED);
+    machine.set_reg(2, b).unwrap();
+    machine.set_reg(3, c).unwrap();
+    assert!(!machine.step_on(&mut Vec::new())?);
+    assert_eq!(4, machine.regs()[0]);
+    Ok(machine.regs()[1])
+}
+
+fn op(opcode: u8, b: i32, c: i32) -> i32 {
+    run_op(opcode, b as u32, c as u32).unwrap() as i32
+}
+
+#[test]
+fn test_add() {
+    // 0: add r1 <- r2 + r3
+    assert_eq!(5, op(11, 2, 3));
+    assert_eq!(-1, op(11, 2, -3));
+    assert_eq!(i32::MIN, op(11, i32::MAX, 1));
+}
+
+#[test]
+fn test_and() {
+    // 0: and r1 <- r2 & r3
+    assert_eq!(0b1000, op(12, 0b1100, 0b1010));
+    assert_eq!(0xff, op(12, -1, 0xff));
+}
+
+#[test]
+fn test_or() {
+    // 0: or r1 <- r2 | r3
+    assert_eq!(0b1110, op(13, 0b1100, 0b1010));
+    assert_eq!(-1, op(13, -256, 0xff));
+}
+
+#[test]
+fn test_xor() {
+    // 0: xor r1 <- r2 ^ r3
+    assert_eq!(0b0110, op(14, 0b1100, 0b1010));
+    assert_eq!(0, op(14, 12345, 12345));
+}
+
+#[test]
+fn test_shl() {
+    // 0: shl r1 <- r2 << r3
+    assert_eq!(40, op(15, 5, 3));
+    assert_eq!(i32::MIN, op(15, 1, 31));
+    assert_eq!(0, op(15, 1, 32));
+    assert_eq!(0, op(15, 1, -1));
+}
+
+#[test]
+fn test_shr() {
+    // 0: shr r1 <- r2 >> r3
+    assert_eq!(5, op(16, 40, 3));
+    assert_eq!(0x7fff_ffff, op(16, -1, 1));
+    assert_eq!(0, op(16, -1, 32));
+}
+
+#[test]
+fn test_sar() {
+    // 0: sar r1 <- r2 >> r3
+    assert_eq!(5, op(17, 40, 3));
+    assert_eq!(-5, op(17, -40, 3));
+    assert_eq!(-1, op(17, -1, 1));
+    assert_eq!(-1, op(17, i32::MIN, 100));
+    assert_eq!(0, op(17, i32::MAX, 100));
+}
+
+#[test]
+fn test_slt() {
+    // 0: slt r1 <- r2 < r3
+    assert_eq!(1, op(18, -1, 0));
+    assert_eq!(0, op(18, 0, -1));
+    assert_eq!(0, op(18, 7, 7));
+    assert_eq!(1, op(18, i32::MIN, i32::MAX));
+}
+
+#[test]
+fn test_sltu() {
+    // 0: sltu r1 <- r2 < r3
+    assert_eq!(0, op(19, -1, 0));
+    assert_eq!(1, op(19, 0, -1));
+    assert_eq!(0, op(19, 7, 7));
+    assert_eq!(1, op(19, 6, 7));
+}
+
+#[test]
+fn test_mul() {
+    // 0: mul r1 <- r2 * r3
+    assert_eq!(42, op(20, 6, 7));
+    assert_eq!(-42, op(20, -6, 7));
+    assert_eq!(42, op(20, -6, -7));
+    assert_eq!(0, op(20, 0x10000, 0x10000));
+}
+
+#[test]
+fn test_div() {
+    // 0: div r1 <- r2 / r3
+    assert_eq!(6, op(21, 42, 7));
+    assert_eq!(-3, op(21, -7, 2));
+    assert_eq!(-3, op(21, 7, -2));
+    assert_eq!(i32::MIN, op(21, i32::MIN, -1));
+    assert!(matches!(
+        run_op(21, 42, 0),
+        Err(MachineError::DivisionByZero { .. })
+    ));
+}
+
+#[test]
+fn test_mod() {
+    // 0: mod r1 <- r2 % r3
+    assert_eq!(0, op(22, 42, 7));
+    assert_eq!(-1, op(22, -7, 2));
+    assert_eq!(1, op(22, 7, -2));
+    assert_eq!(0, op(22, i32::MIN, -1));
+    assert!(matches!(
+        run_op(22, 42, 0),
+        Err(MachineError::DivisionByZero { .. })
+    ));
+}
+
+#[test]
+fn test_alu_out_of_bounds() {
+    for opcode in 11..=22 {
+        for bytes in [
+            [opcode, 100, 0, 0],
+            [opcode, 0, 100, 0],
+            [opcode, 0, 0, 100],
+        ] {
+            let mut machine = Machine::with_config(&bytes, EXTENDED);
+            assert!(matches!(
+                machine.step_on(&mut Vec::new()),
+                Err(MachineError::InvalidRegister { reg: 100, .. })
+            ));
+        }
+    }
+}
+
+#[test]
+fn base_isa_rejects_extended_instructions() {
+    for opcode in 11..=22 {
+        let mut machine = Machine::new(&[opcode, 1, 2, 3]);
+        assert!(matches!(
+            machine.step_on(&mut Vec::new()),
+            Err(MachineError::UnsupportedInstruction { .. })
+        ));
+    }
+    // execute checks it as well.
+    let add = Instruction::Alu {
+        op: AluOp::Add,
+        a: 1,
+        b: 2,
+        c: 3,
+    };
+    let mut machine = Machine::new(&[]);
+    assert!(matches!(
+        machine.execute(add, &mut Vec::new()),
+        Err(MachineError::UnsupportedInstruction { .. })
+    ));
+    let mut machine = Machine::with_config(&[], EXTENDED);
+    machine.set_reg(2, 40).unwrap();
+    machine.set_reg(3, 2).unwrap();
+    machine.execute(add, &mut Vec::new()).unwrap();
+    assert_eq!(42, machine.regs()[1]);
+}
+
+#[test]
+fn syntax() {
+    for op in AluOp::ALL {
+        let instruction = Instruction::Alu {
+            op,
+            a: 1,
+            b: 2,
+            c: 13,
+        };
+        let text = instruction.to_string();
+        assert_eq!(
+            format!("{} r1 <- r2 {} r13", op.mnemonic(), op.operator()),
+            text
+        );
+        assert_eq!(instruction.encode(), assemble(&text).unwrap());
+        assert_eq!(
+            (instruction, 4),
+            Instruction::decode(&instruction.encode()).unwrap()
+        );
+    }
+    assert!(assemble("add r1 <- r2 - r3").is_err());
+    assert!(assemble("shl r1 <- r2 >> r3").is_err());
+}
+
+#[test]
+fn factorial_with_mul() {
+    // Same interface as fact.bin: argument in r10, result in r11.
+    let code = assemble(
+        "
+        loadimm r1 <- #1
+        loadimm r11 <- #1
+    loop:
+        slt r4 <- r10 < r1
+        loadimm r3 <- #done
+        move r0 <- r3 if r4 != 0
+        mul r11 <- r11 * r10
+        sub r10 <- r10 - r1
+        loadimm r0 <- #loop
+    done:
+        exit
+        ",
+    )
+    .unwrap();
+    for (n, expected) in [(0, 1), (1, 1), (5, 120), (12, 479001600)] {
+        let mut machine = Machine::with_config(&code, EXTENDED);
+        machine.set_reg(10, n).unwrap();
+        machine.run_on(&mut Vec::new()).unwrap();
+        assert_eq!(expected, machine.regs()[11]);
+    }
+}
+
+#[test]
+fn command_line_isa() {
+    let path = std::env::temp_dir().join(format!("extended-{}.bin", std::process::id()));
+    // 0: mul r1 <- r0 * r0
+    // 4: out_number r1
+    // 6: exit
+    std::fs::write(&path, [20, 1, 0, 0, 8, 1, 7]).unwrap();
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--isa", "extended"]);
+    assert!(output.status.success());
+    assert_eq!(&b"16"[..], &output.stdout[..]);
+
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("instruction not supported"));
+
+    let output = run(&["--isa", "fancy"]);
+    assert_eq!(Some(2), output.status.code());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,110 @@
+//! Equivalent of the `machine` fuzz target of `fuzz/`: arbitrary memory
+//! images and register states must never make the machine panic.
+
+use interpreter::{
+    Console, Instruction, Isa, Machine, MachineConfig, Permissions, Protection, Region, StackConfig,
+};
+use proptest::prelude::*;
+
+const MAX_STEPS: u64 = 1000;
+
+fn permissions() -> impl Strategy<Value = Permissions> {
+    (any::<bool>(), any::<bool>(), any::<bool>())
+        .prop_map(|(read, write, execute)| Permissions::new(read, write, execute))
+}
+
+fn protection() -> impl Strategy<Value = Protection> {
+    let region =
+        (any::<u32>(), any::<u32>(), permissions()).prop_map(|(start, end, permissions)| Region {
+            range: start % 2048..end % 2048,
+            permissions,
+        });
+    (
+        permissions(),
+        prop::collection::vec(region, 0..4),
+        any::<bool>(),
+    )
+        .prop_map(|(default, regions, protect_image)| Protection {
+            default,
+            regions,
+            protect_image,
+        })
+}
+
+fn config() -> impl Strategy<Value = MachineConfig> {
+    (
+        0..2048usize,
+        1..=256usize,
+        any::<bool>(),
+        prop::option::of((any::<u8>(), any::<u32>())),
+        any::<bool>(),
+        any::<bool>(),
+        any::<bool>(),
+        prop::option::of(protection()),
+    )
+        .prop_map(
+            |(memory_size, nregs, extended, stack, input, traps, syscalls, protection)| {
+                MachineConfig {
+                    memory_size,
+                    nregs,
+                    isa: if extended { Isa::Extended } else { Isa::Base },
+                    // The stack pointer must be a register other than the IP.
+                    stack: stack.filter(|_| nregs > 1).map(|(sp, limit)| StackConfig {
+                        sp: 1 + sp % (nregs - 1) as u8,
+                        limit,
+                    }),
+                    input,
+                    traps,
+                    syscalls,
+                    protection,
+                }
+            },
+        )
+}
+
+/// Register values, often addresses in memory.
+fn regs() -> impl Strategy<Value = Vec<u32>> {
+    prop::collection::vec(prop_oneof![0..2100u32, any::<u32>()], 0..32)
+}
+
+proptest! {
+    #[test]
+    fn decode_round_trip(bytes in prop::collection::vec(any::<u8>(), 0..8)) {
+        if let Ok((instruction, size)) = Instruction::decode(&bytes) {
+            prop_assert_eq!(&bytes[..size], &instruction.encode()[..]);
+        }
+    }
+
+    #[test]
+    fn random_states(
+        config in config(),
+        regs in regs(),
+        memory in prop::collection::vec(any::<u8>(), 0..2048),
+        input in prop::collection::vec(any::<u8>(), 0..16),
+        traps in any::<bool>(),
+        history in any::<bool>(),
+    ) {
+        let memory = &memory[..memory.len().min(config.memory_size)];
+        let mut machine = Machine::with_config(memory, config);
+        for (reg, &value) in regs.iter().enumerate() {
+            let _ = machine.set_reg(reg, value);
+        }
+        if history {
+            machine.enable_history(64);
+        }
+        let mut console = Console::new(&input[..], Vec::new());
+        if traps {
+            for _ in 0..MAX_STEPS {
+                match machine.step_with_traps(&mut console) {
+                    Ok(false) => (),
+                    Ok(true) | Err(_) => break,
+                }
+            }
+        } else {
+            let _ = machine.run_with_limit(&mut console, MAX_STEPS);
+        }
+        if history {
+            machine.run_back_until(|_| false);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use interpreter::tracer::JsonTracer;
+use interpreter::Machine;
+
+fn rfact_machine(n: u32) -> Machine {
+    let mut machine = Machine::new(include_bytes!("rfact.bin"));
+    machine.set_reg(10, n).unwrap();
+    machine
+}
+
+#[test]
+fn disabled_by_default() {
+    let mut machine = rfact_machine(3);
+    machine.step().unwrap();
+    assert_eq!(0, machine.history_len());
+    assert!(!machine.step_back());
+    assert_eq!(4, machine.regs()[0]);
+}
+
+#[test]
+fn step_back_through_whole_run() {
+    let mut machine = rfact_machine(5);
+    machine.enable_history(usize::MAX);
+    let mut states = vec![machine.snapshot()];
+    while !machine.step_on(&mut Vec::new()).unwrap() {
+        states.push(machine.snapshot());
+    }
+    assert_eq!(120, machine.regs()[11]);
+    assert_eq!(states.len(), machine.history_len());
+
+    while let Some(state) = states.pop() {
+        assert!(machine.step_back());
+        assert_eq!(state, machine.snapshot());
+    }
+    assert!(!machine.step_back());
+
+    // Running again from the start gives the same result.
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(120, machine.regs()[11]);
+}
+
+#[test]
+fn bounded_history() {
+    let mut machine = rfact_machine(5);
+    machine.enable_history(10);
+    machine.run_with_limit(&mut Vec::new(), 30).unwrap_err();
+    let after_20_steps = {
+        let mut machine = rfact_machine(5);
+        machine.run_with_limit(&mut Vec::new(), 20).unwrap_err();
+        machine.snapshot()
+    };
+    assert_eq!(10, machine.history_len());
+    assert!(!machine.run_back_until(|_| false));
+    assert_eq!(after_20_steps, machine.snapshot());
+
+    // Shrinking the capacity drops the oldest steps.
+    machine.run_with_limit(&mut Vec::new(), 10).unwrap_err();
+    machine.enable_history(4);
+    assert_eq!(4, machine.history_len());
+}
+
+#[test]
+fn find_memory_write() {
+    // Find the last time the stack top, at 4092, was written.
+    let mut machine = rfact_machine(4);
+    machine.enable_history(1000);
+    machine.run_on(&mut Vec::new()).unwrap();
+    let final_value = machine.memory()[4092..].to_vec();
+    assert!(machine.run_back_until(|m| m.memory()[4092..] != final_value[..]));
+    // The following step is the store.
+    let mut out = Vec::new();
+    let mut tracer = JsonTracer::new(&mut out);
+    machine.step_traced(&mut Vec::new(), &mut tracer).unwrap();
+    drop(tracer);
+    let line = String::from_utf8(out).unwrap();
+    assert!(line.contains(r#""insn":"store [r2] <- "#), "{line}");
+    assert!(line.contains(r#""addr":4092"#), "{line}");
+    assert_eq!(final_value, &machine.memory()[4092..]);
+}
+
+#[test]
+fn undo_failed_step() {
+    // 0: store [r1] <- r2
+    let mut machine = Machine::new(&[2, 1, 2]);
+    machine.enable_history(10);
+    machine.set_reg(1, 4094).unwrap();
+    assert!(machine.step().is_err());
+    assert_eq!(3, machine.regs()[0]);
+    assert!(machine.step_back());
+    assert_eq!(0, machine.regs()[0]);
+
+    // Nothing changed: the failed step is not recorded.
+    let mut machine = Machine::new(&[0]);
+    machine.enable_history(10);
+    assert!(machine.step().is_err());
+    assert_eq!(0, machine.history_len());
+}
+
+#[test]
+fn restore_clears_history() {
+    let mut machine = rfact_machine(3);
+    machine.enable_history(10);
+    let snapshot = machine.snapshot();
+    machine.step().unwrap();
+    machine.restore(&snapshot).unwrap();
+    assert_eq!(0, machine.history_len());
+    machine.step().unwrap();
+    assert_eq!(1, machine.history_len());
+    machine.disable_history();
+    assert!(!machine.step_back());
+}
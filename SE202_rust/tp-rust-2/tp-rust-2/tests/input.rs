@@ -0,0 +1,211 @@
+use interpreter::{Console, IoDevice, Machine, MachineConfig, MachineError};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Machine running `program` with the input instructions enabled.
+fn with_input(program: &[u8]) -> Machine {
+    let config = MachineConfig {
+        input: true,
+        ..MachineConfig::default()
+    };
+    Machine::with_config(program, config)
+}
+
+/// Run `machine` with `input` as standard input, and return the output.
+fn run_with_input(machine: &mut Machine, input: &str) -> Result<String, MachineError> {
+    let mut console = Console::new(input.as_bytes(), Vec::new());
+    machine.run_on(&mut console)?;
+    Ok(String::from_utf8(console.into_output()).unwrap())
+}
+
+#[test]
+fn in_reads_bytes() {
+    // 0: in r1
+    // 2: in r2
+    // 4: in r3
+    // 6: exit
+    let mut machine = with_input(&[9, 1, 9, 2, 9, 3, 7]);
+    run_with_input(&mut machine, "A\u{e9}").unwrap();
+    assert_eq!(&[7, 65, 0xc3, 0xa9], &machine.regs()[..4]);
+
+    // -1 at the end of the input.
+    let mut machine = with_input(&[9, 1, 9, 2, 9, 3, 7]);
+    run_with_input(&mut machine, "z").unwrap();
+    assert_eq!(&[7, 122, 0xffffffff, 0xffffffff], &machine.regs()[..4]);
+}
+
+#[test]
+fn input_is_optional() {
+    // 0: in r1, or in_number r1
+    for program in [[9, 1], [10, 1]] {
+        let mut machine = Machine::new(&program);
+        assert!(matches!(
+            run_with_input(&mut machine, "1"),
+            Err(MachineError::UnsupportedInstruction { .. })
+        ));
+        assert_eq!(0, machine.regs()[1]);
+
+        let mut machine = with_input(&program);
+        let mut console = Console::new(&b"1"[..], Vec::new());
+        assert!(!machine.step_on(&mut console).unwrap());
+        assert_ne!(0, machine.regs()[1]);
+    }
+}
+
+#[test]
+fn echo() {
+    // 0: loadimm r5 <- #-1
+    // 4: in r1
+    // 6: sub r3 <- r1 - r5   (0 at the end of the input)
+    // 10: loadimm r4 <- #19
+    // 14: move r0 <- r4 if r3 != 0
+    // 18: exit
+    // 19: out r1
+    // 21: loadimm r0 <- #4
+    let program = [
+        4, 5, 0xff, 0xff, 9, 1, 5, 3, 1, 5, 4, 4, 19, 0, 1, 0, 4, 3, 7, 6, 1, 4, 0, 4, 0,
+    ];
+    let mut machine = with_input(&program);
+    let out = run_with_input(&mut machine, "echo this\n").unwrap();
+    assert_eq!("echo this\n", out);
+}
+
+#[test]
+fn in_number() {
+    // 0: in_number r1
+    // 2: in_number r2
+    // 4: in r3
+    // 6: exit
+    let program = [10, 1, 10, 2, 9, 3, 7];
+    let cases: &[(&str, u32, u32, u32)] = &[
+        ("12 34\n", 12, 34, b'\n' as u32),
+        ("  -7\n\n+8x", -7i32 as u32, 8, b'x' as u32),
+        ("4294967295 -2147483648", u32::MAX, 0x8000_0000, u32::MAX),
+        ("0\t007", 0, 7, u32::MAX),
+    ];
+    for &(input, r1, r2, r3) in cases {
+        let mut machine = with_input(&program);
+        run_with_input(&mut machine, input).unwrap();
+        assert_eq!(&[r1, r2, r3], &machine.regs()[1..4], "input {input:?}");
+    }
+}
+
+#[test]
+fn in_number_errors() {
+    // 0: in_number r1
+    for input in ["", "  \n", "x", "-", "- 3", "4294967296", "-2147483649"] {
+        let mut machine = with_input(&[10, 1]);
+        assert!(
+            matches!(
+                run_with_input(&mut machine, input),
+                Err(MachineError::InvalidInput { .. })
+            ),
+            "input {input:?}"
+        );
+    }
+
+    // 0: in_number r16
+    let mut machine = with_input(&[10, 16]);
+    assert!(matches!(
+        run_with_input(&mut machine, "1"),
+        Err(MachineError::InvalidRegister { reg: 16, .. })
+    ));
+}
+
+#[test]
+fn writers_have_no_input() {
+    // 0: in r1
+    // 2: exit
+    let mut machine = with_input(&[9, 1, 7]);
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(u32::MAX, machine.regs()[1]);
+
+    let mut machine = with_input(&[10, 1, 7]);
+    assert!(matches!(
+        machine.run_on(&mut out),
+        Err(MachineError::InvalidInput { .. })
+    ));
+}
+
+/// Device recording the order of input and output operations.
+#[derive(Default)]
+struct Recorder {
+    input: Vec<u8>,
+    log: Vec<String>,
+}
+
+impl IoDevice for Recorder {
+    fn write_output(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.log
+            .push(format!("out {}", String::from_utf8_lossy(bytes)));
+        Ok(())
+    }
+
+    fn peek_input(&mut self) -> std::io::Result<Option<u8>> {
+        Ok(self.input.first().copied())
+    }
+
+    fn read_input(&mut self) -> std::io::Result<Option<u8>> {
+        let byte = self.peek_input()?;
+        if byte.is_some() {
+            let byte = self.input.remove(0);
+            self.log.push(format!("in {}", byte as char));
+        }
+        Ok(byte)
+    }
+}
+
+#[test]
+fn custom_device() {
+    // 0: in_number r1
+    // 2: out_number r1
+    // 4: in r1
+    // 6: out r1
+    // 8: exit
+    let mut machine = with_input(&[10, 1, 8, 1, 9, 1, 6, 1, 7]);
+    let mut device = Recorder {
+        input: b"42!".to_vec(),
+        ..Recorder::default()
+    };
+    machine.run_on(&mut device).unwrap();
+    assert_eq!(vec!["in 4", "in 2", "out 42", "in !", "out !"], device.log);
+}
+
+#[test]
+fn number_guessing() {
+    let mut machine = with_input(include_bytes!("../examples/guess.bin"));
+    let out = run_with_input(&mut machine, "50\n20\n 36 38\n37\n").unwrap();
+    assert_eq!(
+        "Guess? Too high\n\
+         Guess? Too low\n\
+         Guess? Too low\n\
+         Guess? Too high\n\
+         Guess? Found in 5 tries\n",
+        out
+    );
+
+    let mut machine = with_input(include_bytes!("../examples/guess.bin"));
+    assert!(matches!(
+        run_with_input(&mut machine, "50\n"),
+        Err(MachineError::InvalidInput { .. })
+    ));
+}
+
+#[test]
+fn command_line_reads_stdin() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .arg("--input")
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/guess.bin"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"1\n37\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        &b"Guess? Too low\nGuess? Found in 2 tries\n"[..],
+        &output.stdout[..]
+    );
+}
@@ -0,0 +1,132 @@
+use interpreter::{Instruction, Machine, MachineError};
+
+#[test]
+fn decode_and_encode() {
+    let cases: &[(&[u8], Instruction)] = &[
+        (&[1, 1, 2, 3], Instruction::MoveIf { a: 1, b: 2, c: 3 }),
+        (&[2, 2, 3], Instruction::Store { a: 2, b: 3 }),
+        (&[3, 1, 2], Instruction::Load { a: 1, b: 2 }),
+        (
+            &[4, 1, 0x11, 0x70],
+            Instruction::LoadImm {
+                reg: 1,
+                value: 0x7011,
+            },
+        ),
+        (
+            &[4, 1, 0x11, 0xd0],
+            Instruction::LoadImm {
+                reg: 1,
+                value: -12271,
+            },
+        ),
+        (&[5, 10, 2, 1], Instruction::Sub { a: 10, b: 2, c: 1 }),
+        (&[6, 5], Instruction::Out { reg: 5 }),
+        (&[7], Instruction::Exit),
+        (&[8, 3], Instruction::OutNumber { reg: 3 }),
+        (&[9, 4], Instruction::In { reg: 4 }),
+        (&[10, 12], Instruction::InNumber { reg: 12 }),
+    ];
+    for &(bytes, instruction) in cases {
+        let (decoded, size) = Instruction::decode(bytes).unwrap();
+        assert_eq!(instruction, decoded);
+        assert_eq!(bytes.len(), size);
+        assert_eq!(bytes.len(), instruction.size());
+        assert_eq!(bytes, &instruction.encode()[..]);
+    }
+}
+
+#[test]
+fn decode_ignores_following_bytes() {
+    let (instruction, size) = Instruction::decode(&[6, 1, 7, 7]).unwrap();
+    assert_eq!(Instruction::Out { reg: 1 }, instruction);
+    assert_eq!(2, size);
+}
+
+#[test]
+fn decode_errors() {
+    assert!(matches!(
+        Instruction::decode(&[0]),
+        Err(MachineError::InvalidInstruction { opcode: 0, .. })
+    ));
+    assert!(matches!(
+        Instruction::decode(&[255, 1, 2, 3]),
+        Err(MachineError::InvalidInstruction { opcode: 255, .. })
+    ));
+    assert!(matches!(
+        Instruction::decode(&[5, 1, 2]),
+        Err(MachineError::IpOutOfBounds { .. })
+    ));
+    assert!(matches!(
+        Instruction::decode(&[]),
+        Err(MachineError::IpOutOfBounds { .. })
+    ));
+}
+
+#[test]
+fn display() {
+    assert_eq!(
+        "move r1 <- r2 if r3 != 0",
+        Instruction::MoveIf { a: 1, b: 2, c: 3 }.to_string()
+    );
+    assert_eq!(
+        "loadimm r3 <- #-4",
+        Instruction::LoadImm { reg: 3, value: -4 }.to_string()
+    );
+    assert_eq!(
+        "store [r2] <- r10",
+        Instruction::Store { a: 2, b: 10 }.to_string()
+    );
+}
+
+#[test]
+fn execute_does_not_move_ip() {
+    let mut machine = Machine::new(&[]);
+    let mut out = Vec::new();
+    machine
+        .execute(Instruction::LoadImm { reg: 1, value: 65 }, &mut out)
+        .unwrap();
+    assert!(!machine
+        .execute(Instruction::Out { reg: 1 }, &mut out)
+        .unwrap());
+    assert!(machine.execute(Instruction::Exit, &mut out).unwrap());
+    assert_eq!(0, machine.regs()[0]);
+    assert_eq!(65, machine.regs()[1]);
+    assert_eq!(&b"A"[..], &out[..]);
+    assert!(machine
+        .execute(Instruction::Sub { a: 16, b: 0, c: 0 }, &mut out)
+        .is_err());
+}
+
+#[test]
+fn truncated_instruction_at_end_of_memory() {
+    // memory_size-2: loadimm r1 <- ...
+    let memory_size = Machine::new(&[]).memory().len();
+    let mut memory = vec![0; memory_size - 2];
+    memory.extend([4, 1]);
+    let mut machine = Machine::new(&memory);
+    machine.set_reg(0, (memory_size - 2) as u32).unwrap();
+    assert!(matches!(
+        machine.step(),
+        Err(MachineError::IpOutOfBounds { .. })
+    ));
+    assert_eq!(0, machine.regs()[1]);
+    assert_eq!((memory_size - 2) as u32, machine.regs()[0]);
+}
+
+#[test]
+fn missing_operands_are_not_zero_filled() {
+    // memory_size-1: out r0, its register byte past the end of memory.
+    // Reading it as 0 would print the IP.
+    let memory_size = Machine::new(&[]).memory().len();
+    let mut memory = vec![0; memory_size - 1];
+    memory.push(6);
+    let mut machine = Machine::new(&memory);
+    machine.set_reg(0, (memory_size - 1) as u32).unwrap();
+    let mut out = Vec::new();
+    assert!(matches!(
+        machine.step_on(&mut out),
+        Err(MachineError::IpOutOfBounds { .. })
+    ));
+    assert!(out.is_empty());
+}
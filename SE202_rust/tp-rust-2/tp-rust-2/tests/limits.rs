@@ -0,0 +1,98 @@
+use interpreter::{Machine, MachineError};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+// 0: loadimm r0 <- #0
+const INFINITE_LOOP: &[u8] = &[4, 0, 0, 0];
+
+#[test]
+fn step_limit_stops_infinite_loop() {
+    let mut machine = Machine::new(INFINITE_LOOP);
+    match machine.run_with_limit(&mut Vec::new(), 1000) {
+        Err(MachineError::StepLimitExceeded { steps }) => assert_eq!(1000, steps),
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn step_limit_is_inclusive() {
+    // 0: sub r1 <- r1 - r0
+    // 4: exit
+    let mut machine = Machine::new(&[5, 1, 1, 0, 7]);
+    machine.run_with_limit(&mut Vec::new(), 2).unwrap();
+    assert_eq!(5, machine.regs()[0]);
+
+    let mut machine = Machine::new(&[5, 1, 1, 0, 7]);
+    assert!(matches!(
+        machine.run_with_limit(&mut Vec::new(), 1),
+        Err(MachineError::StepLimitExceeded { steps: 1 })
+    ));
+}
+
+#[test]
+fn step_limit_on_real_program() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin"));
+    machine.set_reg(10, 5).unwrap();
+    machine.run_with_limit(&mut Vec::new(), 100_000).unwrap();
+    assert_eq!(120, machine.regs()[11]);
+}
+
+#[test]
+fn deadline_stops_infinite_loop() {
+    let mut machine = Machine::new(INFINITE_LOOP);
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(50);
+    match machine.run_with_deadline(&mut Vec::new(), deadline) {
+        Err(MachineError::DeadlineExceeded { steps }) => assert!(steps > 0),
+        _ => panic!(),
+    }
+    assert!(Instant::now() >= deadline);
+}
+
+#[test]
+fn expired_deadline() {
+    let mut machine = Machine::new(&[7]);
+    assert!(matches!(
+        machine.run_with_deadline(&mut Vec::new(), Instant::now()),
+        Err(MachineError::DeadlineExceeded { steps: 0 })
+    ));
+}
+
+#[test]
+fn errors_take_precedence_over_limits() {
+    let mut machine = Machine::new(&[0]);
+    assert!(matches!(
+        machine.run_with_limits(
+            &mut Vec::new(),
+            Some(10),
+            Some(Instant::now() + Duration::from_secs(10))
+        ),
+        Err(MachineError::InvalidInstruction { .. })
+    ));
+}
+
+#[test]
+fn command_line_limits() {
+    let path = std::env::temp_dir().join(format!("infinite-loop-{}.bin", std::process::id()));
+    std::fs::write(&path, INFINITE_LOOP).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--max-steps", "500"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("step limit exceeded after 500 steps"));
+
+    let output = run(&["--timeout", "0.05"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("deadline exceeded"));
+
+    let output = run(&["--timeout"]);
+    assert_eq!(Some(2), output.status.code());
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,339 @@
+use interpreter::assembler::{assemble, assemble_object};
+use interpreter::linker::{link, LinkError};
+use interpreter::{Machine, MachineConfig, Object, ObjectError, Section, SectionKind};
+use std::process::Command;
+
+const MAIN: &str = "
+    .global start
+start:
+    loadimm r3 <- #message
+    loadimm r5 <- #back
+    loadimm r0 <- #print
+back:
+    exit
+    .data
+message:
+    b'hello\\n\\0'
+";
+
+const LIB: &str = "
+    .global print
+print:
+    loadimm r1 <- #1
+    syscall
+    move r0 <- r5 if r5 != 0
+";
+
+fn objects(sources: &[(&str, &str)]) -> Vec<(String, Object)> {
+    sources
+        .iter()
+        .map(|(name, source)| (name.to_string(), assemble_object(source).unwrap()))
+        .collect()
+}
+
+fn run(object: &Object) -> Vec<u8> {
+    let config = MachineConfig {
+        syscalls: true,
+        ..MachineConfig::default()
+    };
+    let mut machine =
+        Machine::from_object_with_config(&object.to_bytes().unwrap(), config).unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    out
+}
+
+#[test]
+fn link_a_library() {
+    let linked = link(&objects(&[("main.obj", MAIN), ("lib.obj", LIB)]), 4096).unwrap();
+    assert_eq!(&b"hello\n"[..], &run(&linked.object)[..]);
+    assert!(linked.object.relocations.is_empty());
+    assert_eq!(
+        vec![(SectionKind::Code, 0, 22), (SectionKind::Data, 22, 7)],
+        linked
+            .object
+            .sections
+            .iter()
+            .map(|section| (section.kind, section.address, section.bytes.len()))
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        "Entry point: 0000\n\
+         \n\
+         Sections:\n  \
+         0000-0013  code  main.obj (from 0000)\n  \
+         0013-0022  code  lib.obj (from 0000)\n  \
+         0022-0029  data  main.obj (from 0013)\n\
+         \n\
+         Symbols:\n  \
+         0000  global  start    main.obj\n  \
+         0012  local   back     main.obj\n  \
+         0013  global  print    lib.obj\n  \
+         0022  local   message  main.obj\n",
+        linked.map()
+    );
+
+    // The entry point is found whatever the order of the objects.
+    let linked = link(&objects(&[("lib.obj", LIB), ("main.obj", MAIN)]), 4096).unwrap();
+    assert_eq!(9, linked.object.entry);
+    assert_eq!(&b"hello\n"[..], &run(&linked.object)[..]);
+}
+
+#[test]
+fn unlinked_objects_cannot_be_loaded() {
+    let object = assemble_object(MAIN).unwrap();
+    assert_eq!(
+        Some(ObjectError::UndefinedSymbol("print".to_string())),
+        Machine::from_object(&object.to_bytes().unwrap()).err()
+    );
+    // Objects without external references can.
+    let object = assemble_object("loadimm r0 <- #end\nend: exit").unwrap();
+    assert_eq!(1, object.relocations.len());
+    assert!(Machine::from_object(&object.to_bytes().unwrap()).is_ok());
+}
+
+#[test]
+fn local_symbols() {
+    let first = "
+        .global start
+    start:
+        loadimm r1 <- #loop
+        loadimm r0 <- #count
+    loop:
+        exit
+    ";
+    let second = "
+        .global count
+    count:
+        loadimm r2 <- #loop
+        out_number r1
+        out_number r2
+        exit
+    loop:
+        exit
+    ";
+    let linked = link(&objects(&[("a", first), ("b", second)]), 4096).unwrap();
+    // Each object uses its own `loop`.
+    assert_eq!(&b"818"[..], &run(&linked.object)[..]);
+    let symbols = &linked.object.symbols;
+    assert_eq!(Some(&8), symbols.get("a:loop"));
+    assert_eq!(Some(&18), symbols.get("b:loop"));
+    assert_eq!(None, symbols.get("loop"));
+    assert_eq!(Some(&9), symbols.get("count"));
+}
+
+#[test]
+fn long_input_paths() {
+    let source = "
+        .global start
+    start:
+        loadimm r0 <- #loop
+    loop:
+        exit
+    ";
+    let path = format!("/tmp/{}/a.obj", "d".repeat(250));
+    let result = link(&objects(&[(&path, source), ("b.obj", "loop: exit")]), 4096);
+    assert_eq!(
+        Err(LinkError::SymbolNameTooLong {
+            symbol: "loop".to_string(),
+            input: path.clone()
+        }),
+        result
+    );
+    assert!(result
+        .unwrap_err()
+        .to_string()
+        .ends_with("a.obj: the linked name of `loop` is longer than 255 bytes"));
+
+    // Local names which do not clash are kept as they are.
+    let linked = link(&objects(&[(&path, source)]), 4096).unwrap();
+    assert!(linked.object.to_bytes().is_ok());
+}
+
+#[test]
+fn link_errors() {
+    let result = link(&objects(&[("main.obj", MAIN)]), 4096);
+    assert_eq!(
+        Err(LinkError::UndefinedSymbol {
+            symbol: "print".to_string(),
+            input: "main.obj".to_string()
+        }),
+        result
+    );
+    assert_eq!(
+        "main.obj: symbol `print` is not defined",
+        result.unwrap_err().to_string()
+    );
+
+    let result = link(&objects(&[("a", LIB), ("main", MAIN), ("b", LIB)]), 4096);
+    assert_eq!(
+        Err(LinkError::DuplicateSymbol {
+            symbol: "print".to_string(),
+            first: "a".to_string(),
+            second: "b".to_string()
+        }),
+        result
+    );
+
+    let result = link(&objects(&[("main.obj", MAIN), ("lib.obj", LIB)]), 28);
+    assert_eq!(
+        Err(LinkError::DoesNotFit {
+            size: 29,
+            memory_size: 28
+        }),
+        result
+    );
+
+    // A relocation must point to a loadimm or a call.
+    let mut object = assemble_object("loadimm r1 <- #a\na: exit").unwrap();
+    object.relocations[0].address = 4;
+    assert_eq!(
+        Err(LinkError::InvalidRelocation {
+            address: 4,
+            input: "x".to_string()
+        }),
+        link(&[("x".to_string(), object)], 4096)
+    );
+}
+
+#[test]
+fn addresses_above_32k() {
+    // `far` is placed after the code of `main` and 32 KiB of data.
+    let far = Object {
+        sections: vec![Section {
+            kind: SectionKind::Data,
+            address: 0,
+            bytes: vec![0; 0x8000],
+        }],
+        symbols: [("far".to_string(), 0x8000)].into(),
+        globals: ["far".to_string()].into(),
+        ..Object::default()
+    };
+    let link_with = |source: &str| {
+        let inputs = vec![
+            ("main".to_string(), assemble_object(source).unwrap()),
+            ("far".to_string(), far.clone()),
+        ];
+        link(&inputs, 0x10000)
+    };
+
+    // A loadimm would sign-extend the address.
+    assert_eq!(
+        Err(LinkError::AddressOutOfRange {
+            symbol: "far".to_string(),
+            input: "main".to_string(),
+            address: 0x8004
+        }),
+        link_with("loadimm r1 <- #far")
+    );
+    // A call takes it as it is.
+    let linked = link_with("call #far").unwrap();
+    assert_eq!(vec![25, 0x03, 0x80], linked.object.sections[0].bytes);
+}
+
+#[test]
+fn entry_point_without_start() {
+    let mut object = Object {
+        entry: 2,
+        sections: vec![
+            Section {
+                kind: SectionKind::Data,
+                address: 0,
+                bytes: vec![255, 255],
+            },
+            Section {
+                kind: SectionKind::Code,
+                address: 2,
+                bytes: vec![7],
+            },
+        ],
+        ..Object::default()
+    };
+    let linked = link(&[("x".to_string(), object.clone())], 4096).unwrap();
+    // The code moved before the data.
+    assert_eq!(0, linked.object.entry);
+
+    object.entry = 1;
+    let linked = link(&[("x".to_string(), object)], 4096).unwrap();
+    assert_eq!(2, linked.object.entry);
+}
+
+#[test]
+fn global_directive() {
+    let object = assemble_object(".global a, b\na: exit\nb: exit").unwrap();
+    assert_eq!(2, object.globals.len());
+    assert!(assemble(".global missing").is_err());
+    assert!(assemble(".global").is_err());
+    // Undefined labels are only accepted in objects.
+    assert!(assemble("loadimm r1 <- #missing").is_err());
+    assert_eq!(
+        vec![4, 1, 0, 0],
+        assemble_object("loadimm r1 <- #missing").unwrap().sections[0].bytes
+    );
+}
+
+#[test]
+fn command_line() {
+    let dir = std::env::temp_dir().join(format!("linker-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    std::fs::write(path("main.dis"), MAIN).unwrap();
+    std::fs::write(path("lib.dis"), LIB).unwrap();
+    for name in ["main", "lib"] {
+        let status = Command::new(env!("CARGO_BIN_EXE_vmas"))
+            .args([path(&format!("{name}.dis")), path(&format!("{name}.obj"))])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+    let status = Command::new(env!("CARGO_BIN_EXE_vmld"))
+        .args(["-o", &path("hello.obj"), "-m", &path("hello.map")])
+        .args([path("main.obj"), path("lib.obj")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(std::fs::read_to_string(path("hello.map"))
+        .unwrap()
+        .contains("global  print"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .arg("--syscalls")
+        .arg(path("hello.obj"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(&b"hello\n"[..], &output.stdout[..]);
+
+    // Undefined symbols.
+    let output = Command::new(env!("CARGO_BIN_EXE_vmld"))
+        .args(["-o", &path("hello.obj"), &path("main.obj")])
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+
+    // Local symbols named after a long path.
+    let long = dir.join("d".repeat(250));
+    std::fs::create_dir_all(&long).unwrap();
+    let long = long.join("main.obj").to_str().unwrap().to_string();
+    std::fs::copy(path("main.obj"), &long).unwrap();
+    std::fs::write(path("back.dis"), "back: exit").unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_vmas"))
+        .args([path("back.dis"), path("back.obj")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let output = Command::new(env!("CARGO_BIN_EXE_vmld"))
+        .args([
+            "-o",
+            &path("hello.obj"),
+            &long,
+            &path("lib.obj"),
+            &path("back.obj"),
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("the linked name of `back`"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
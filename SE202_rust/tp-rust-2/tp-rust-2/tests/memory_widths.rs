@@ -0,0 +1,210 @@
+use interpreter::assembler::assemble;
+use interpreter::{Access, Devices, Instruction, Isa, Machine, MachineConfig, MachineError, Width};
+
+const EXTENDED: MachineConfig = MachineConfig {
+    memory_size: 4096,
+    nregs: 16,
+    isa: Isa::Extended,
+    stack: None,
+    input: false,
+    traps: false,
+    syscalls: false,
+    protection: None,
+};
+
+fn extended_machine(source: &str) -> Machine {
+    Machine::with_config(&assemble(source).unwrap(), EXTENDED)
+}
+
+#[test]
+fn narrow_stores() {
+    let mut machine = extended_machine(
+        "
+        loadimm r1 <- #1000
+        loadimm r2 <- #-1
+        store [r1] <- r2
+        loadimm r2 <- #0x1234
+        store8 [r1] <- r2
+        loadimm r1 <- #1002
+        store16 [r1] <- r2
+        exit
+        ",
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(&[0x34, 0xff, 0x34, 0x12, 0], &machine.memory()[1000..1005]);
+}
+
+#[test]
+fn narrow_loads() {
+    let mut machine = extended_machine(
+        "
+        loadimm r1 <- #data
+        load8 r2 <- [r1]
+        load8s r3 <- [r1]
+        load16 r4 <- [r1]
+        load16s r5 <- [r1]
+        loadimm r1 <- #positive
+        load8s r6 <- [r1]
+        load16s r7 <- [r1]
+        exit
+    data:
+        [0xf0, 0x9c, 0, 0]
+    positive:
+        [0x70, 0x1c, 0xff, 0xff]
+        ",
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(
+        &[0xf0, 0xffff_fff0, 0x9cf0, 0xffff_9cf0, 0x70, 0x1c70],
+        &machine.regs()[2..8]
+    );
+}
+
+#[test]
+fn bounds_depend_on_the_width() {
+    let run = |source: &str, addr: u32| {
+        let mut machine = extended_machine(source);
+        machine.set_reg(1, addr).unwrap();
+        machine.step_on(&mut Vec::new())
+    };
+    for (source, width) in [
+        ("store8 [r1] <- r2", 1),
+        ("store16 [r1] <- r2", 2),
+        ("store [r1] <- r2", 4),
+    ] {
+        assert!(run(source, 4096 - width).is_ok(), "{source}");
+        assert!(
+            matches!(
+                run(source, 4097 - width),
+                Err(MachineError::MemoryOutOfBounds {
+                    access: Access::Store,
+                    ..
+                })
+            ),
+            "{source}"
+        );
+    }
+    for (source, width) in [
+        ("load8 r2 <- [r1]", 1),
+        ("load8s r2 <- [r1]", 1),
+        ("load16 r2 <- [r1]", 2),
+        ("load16s r2 <- [r1]", 2),
+        ("load r2 <- [r1]", 4),
+    ] {
+        assert!(run(source, 4096 - width).is_ok(), "{source}");
+        assert!(
+            matches!(
+                run(source, 4097 - width),
+                Err(MachineError::MemoryOutOfBounds {
+                    access: Access::Load,
+                    ..
+                })
+            ),
+            "{source}"
+        );
+    }
+    assert!(matches!(
+        run("load8 r2 <- [r1]", u32::MAX),
+        Err(MachineError::MemoryOutOfBounds {
+            access: Access::Load,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn base_isa_rejects_narrow_accesses() {
+    for opcode in 27..=32 {
+        let mut machine = Machine::new(&[opcode, 1, 2]);
+        assert!(matches!(
+            machine.step_on(&mut Vec::new()),
+            Err(MachineError::UnsupportedInstruction { .. })
+        ));
+    }
+}
+
+#[test]
+fn narrow_device_accesses() {
+    let mut machine = extended_machine(
+        "
+        loadimm r1 <- #4080
+        loadimm r2 <- #0x4f4b
+        store8 [r1] <- r2
+        load8s r3 <- [r1]
+        exit
+        ",
+    );
+    machine.attach_bus(Box::new(Devices::default()));
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"K"[..], &out[..]);
+    // The end of the input reads as -1 whatever the width.
+    assert_eq!(u32::MAX, machine.regs()[3]);
+}
+
+#[test]
+fn syntax() {
+    for (instruction, text) in [
+        (
+            Instruction::StoreNarrow {
+                width: Width::Byte,
+                a: 1,
+                b: 2,
+            },
+            "store8 [r1] <- r2",
+        ),
+        (
+            Instruction::StoreNarrow {
+                width: Width::Half,
+                a: 3,
+                b: 4,
+            },
+            "store16 [r3] <- r4",
+        ),
+        (
+            Instruction::LoadNarrow {
+                width: Width::Byte,
+                signed: false,
+                a: 5,
+                b: 6,
+            },
+            "load8 r5 <- [r6]",
+        ),
+        (
+            Instruction::LoadNarrow {
+                width: Width::Byte,
+                signed: true,
+                a: 7,
+                b: 8,
+            },
+            "load8s r7 <- [r8]",
+        ),
+        (
+            Instruction::LoadNarrow {
+                width: Width::Half,
+                signed: false,
+                a: 9,
+                b: 10,
+            },
+            "load16 r9 <- [r10]",
+        ),
+        (
+            Instruction::LoadNarrow {
+                width: Width::Half,
+                signed: true,
+                a: 11,
+                b: 12,
+            },
+            "load16s r11 <- [r12]",
+        ),
+    ] {
+        assert_eq!(text, instruction.to_string());
+        assert_eq!(instruction.encode(), assemble(text).unwrap());
+        assert_eq!(
+            (instruction, 3),
+            Instruction::decode(&instruction.encode()).unwrap()
+        );
+    }
+    assert!(assemble("load32 r1 <- [r2]").is_err());
+    assert!(assemble("store8s [r1] <- r2").is_err());
+}
@@ -0,0 +1,278 @@
+use interpreter::assembler::{assemble, assemble_program};
+use interpreter::{
+    Access, Instruction, Machine, MachineConfig, MachineError, Object, ObjectError, Protection,
+    Section, SectionKind, OBJECT_MAGIC,
+};
+use std::collections::BTreeMap;
+use std::process::Command;
+
+/// Program printing `h` from a data section, entered at `start`.
+fn example() -> Object {
+    let program = assemble_program(
+        "
+        .data
+    message:
+        b'hi'
+        .code
+    start:
+        loadimm r1 <- #message
+        load r2 <- [r1]
+        out r2
+        exit
+        ",
+    )
+    .unwrap();
+    let mut object = Object::from_program(&program);
+    object.entry = object.symbols["start"];
+    object
+}
+
+#[test]
+fn sections_from_the_assembler() {
+    let object = example();
+    assert_eq!(2, object.entry);
+    assert_eq!(
+        vec![SectionKind::Data, SectionKind::Code],
+        object
+            .sections
+            .iter()
+            .map(|section| section.kind)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(b"hi", &object.sections[0].bytes[..]);
+    assert_eq!(2, object.sections[1].address);
+    assert_eq!(
+        BTreeMap::from([("message".to_string(), 0), ("start".to_string(), 2)]),
+        object.symbols
+    );
+
+    // Without directives, everything is code.
+    let program = assemble_program("exit").unwrap();
+    assert!(program.data.is_empty());
+    let object = Object::from_program(&program);
+    assert_eq!(
+        vec![Section {
+            kind: SectionKind::Code,
+            address: 0,
+            bytes: vec![7]
+        }],
+        object.sections
+    );
+    assert!(assemble(".text").is_err());
+    assert!(assemble(".data exit").is_err());
+}
+
+#[test]
+fn round_trip() {
+    let object = example();
+    let bytes = object.to_bytes().unwrap();
+    assert!(bytes.starts_with(OBJECT_MAGIC));
+    assert_eq!(object, Object::from_bytes(&bytes).unwrap());
+}
+
+#[test]
+fn invalid_objects() {
+    let bytes = example().to_bytes().unwrap();
+    assert_eq!(Err(ObjectError::BadMagic), Object::from_bytes(&[7]));
+    assert_eq!(
+        Err(ObjectError::Truncated),
+        Object::from_bytes(&bytes[..12])
+    );
+
+    let mut corrupted = bytes.clone();
+    corrupted[30] ^= 1;
+    assert!(matches!(
+        Object::from_bytes(&corrupted),
+        Err(ObjectError::ChecksumMismatch { .. })
+    ));
+
+    let mut newer = bytes.clone();
+    newer[8] = 99;
+    assert_eq!(
+        Err(ObjectError::UnsupportedVersion(99)),
+        Object::from_bytes(&newer)
+    );
+
+    let mut object = example();
+    object.sections.push(Section {
+        kind: SectionKind::Code,
+        address: 4000,
+        bytes: vec![0; 97],
+    });
+    assert_eq!(
+        Some(ObjectError::DoesNotFit {
+            address: 4000,
+            size: 97
+        }),
+        Machine::from_object(&object.to_bytes().unwrap()).err()
+    );
+    object.sections[2].address = 5;
+    assert_eq!(
+        Some(ObjectError::OverlappingSections { address: 5 }),
+        Machine::from_object(&object.to_bytes().unwrap()).err()
+    );
+}
+
+#[test]
+fn long_symbol_names() {
+    let mut object = example();
+    let longest = "s".repeat(255);
+    object.symbols.insert(longest.clone(), 0);
+    let bytes = object.to_bytes().unwrap();
+    assert_eq!(
+        Some(&0),
+        Object::from_bytes(&bytes).unwrap().symbols.get(&longest)
+    );
+
+    let too_long = "s".repeat(256);
+    object.symbols.insert(too_long.clone(), 0);
+    assert_eq!(
+        Err(ObjectError::SymbolNameTooLong(too_long)),
+        object.to_bytes()
+    );
+}
+
+#[test]
+fn load_and_run() {
+    let mut machine = Machine::from_object(&example().to_bytes().unwrap()).unwrap();
+    assert_eq!(2, machine.regs()[0]);
+    assert_eq!(2, machine.symbols()["start"]);
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"h"[..], &out[..]);
+}
+
+#[test]
+fn raw_images() {
+    // 0: out_number r1
+    // 2: exit
+    let mut machine = Machine::from_object(&[8, 1, 7]).unwrap();
+    assert!(machine.symbols().is_empty());
+    assert_eq!(&[8, 1, 7, 0], &machine.memory()[..4]);
+    machine.run_on(&mut Vec::new()).unwrap();
+
+    assert_eq!(
+        Some(ObjectError::DoesNotFit {
+            address: 0,
+            size: 5000
+        }),
+        Machine::from_object(&[0; 5000]).err()
+    );
+}
+
+#[test]
+fn only_code_sections_are_protected() {
+    let config = MachineConfig {
+        protection: Some(Protection::default()),
+        ..MachineConfig::default()
+    };
+    let object = Object {
+        entry: 4,
+        sections: vec![
+            Section {
+                kind: SectionKind::Data,
+                address: 0,
+                bytes: vec![0; 4],
+            },
+            Section {
+                kind: SectionKind::Code,
+                address: 4,
+                bytes: vec![7],
+            },
+        ],
+        ..Object::default()
+    };
+    let mut machine = Machine::load_object(&object, config).unwrap();
+    let mut store = |addr| {
+        machine.set_reg(1, addr).unwrap();
+        machine.execute(Instruction::Store { a: 1, b: 2 }, &mut Vec::new())
+    };
+    assert!(store(0).is_ok());
+    assert!(matches!(
+        store(2),
+        Err(MachineError::ProtectionFault {
+            addr: 4,
+            kind: Access::Store,
+            ..
+        })
+    ));
+    // Past the sections, the default permissions apply.
+    assert!(store(5).is_ok());
+}
+
+#[test]
+fn symbolize() {
+    let machine = Machine::from_object(&example().to_bytes().unwrap()).unwrap();
+    assert_eq!(Some("message".to_string()), machine.symbolize(0));
+    assert_eq!(Some("message+1".to_string()), machine.symbolize(1));
+    assert_eq!(Some("start+4".to_string()), machine.symbolize(6));
+    assert_eq!(None, Machine::new(&[]).symbolize(6));
+}
+
+#[test]
+fn command_line() {
+    let dir = std::env::temp_dir();
+    let source = dir.join(format!("object-{}.dis", std::process::id()));
+    let object = source.with_extension("obj");
+    std::fs::write(
+        &source,
+        "
+        loadimm r1 <- #42
+        out_number r1
+        loadimm r0 <- #crash
+        .data
+        [0, 0]
+        .code
+    crash:
+        loadimm r15 <- #-1
+        store [r15] <- r1
+        ",
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_vmas"))
+        .arg(&source)
+        .arg(&object)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(std::fs::read(&object).unwrap().starts_with(OBJECT_MAGIC));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .arg(&object)
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(&b"42"[..], &output.stdout[..]);
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        report.contains("instruction: 0016 <crash+4>   store [r15] <- r1\n"),
+        "{report}"
+    );
+
+    // Raw images.
+    let output = Command::new(env!("CARGO_BIN_EXE_vmas"))
+        .arg("--raw")
+        .arg(&source)
+        .arg(&object)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        assemble(&std::fs::read_to_string(&source).unwrap()).unwrap(),
+        std::fs::read(&object).unwrap()
+    );
+
+    // Labels too long for an object file.
+    std::fs::write(&source, format!("{}: exit", "l".repeat(300))).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_vmas"))
+        .arg(&source)
+        .arg(&object)
+        .output()
+        .unwrap();
+    assert_eq!(Some(1), output.status.code());
+    let error = String::from_utf8(output.stderr).unwrap();
+    assert!(error.contains(": line 1, column 1: label `lll"), "{error}");
+
+    std::fs::remove_file(&source).unwrap();
+    std::fs::remove_file(&object).unwrap();
+}
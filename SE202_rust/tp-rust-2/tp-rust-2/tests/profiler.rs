@@ -0,0 +1,152 @@
+use interpreter::assembler::assemble_program;
+use interpreter::profiler::Profiler;
+use interpreter::{Console, Machine, MachineConfig, MachineError, StackConfig};
+use std::collections::BTreeMap;
+use std::process::Command;
+
+fn profile(machine: &mut Machine, labels: &BTreeMap<String, u32>) -> (Profiler, String) {
+    let mut profiler = Profiler::new(labels);
+    let mut console = Console::new(&b""[..], Vec::new());
+    machine.run_traced(&mut console, &mut profiler).unwrap();
+    (profiler, String::from_utf8(console.into_output()).unwrap())
+}
+
+#[test]
+fn factorial() {
+    let program = assemble_program(include_str!("../examples/factorial.dis")).unwrap();
+    assert_eq!(
+        &include_bytes!("../examples/factorial.bin")[..],
+        program.code
+    );
+    let mut machine = Machine::new(&program.code);
+    let (profiler, output) = profile(&mut machine, &program.labels);
+    assert!(output.ends_with("fact(10) = 3628800\nI'm done!\n"));
+
+    assert_eq!(5040, profiler.steps());
+    assert_eq!(5040, profiler.executions().values().sum::<u64>());
+    assert_eq!(Some(&210), profiler.executions().get(&507));
+    assert_eq!(
+        Some((Some("ite_then_4".to_string()), 1043)),
+        profiler.symbol_counts().first().cloned()
+    );
+
+    // The return addresses and saved registers on the stack.
+    assert_eq!(Some(&77), profiler.reads().get(&4088));
+    assert_eq!(Some(&77), profiler.writes().get(&4088));
+    // The strings printed by `print`.
+    assert_eq!(Some(&10), profiler.reads().get(&program.labels["str_2"]));
+    assert_eq!(None, profiler.writes().get(&program.labels["str_2"]));
+
+    // `print` is called from the entry point, `mult` from `fact`.
+    assert_eq!(
+        "0000 888\n\
+         0000;fact 695\n\
+         0000;fact;mult 1860\n\
+         0000;print 1597\n",
+        profiler.folded()
+    );
+}
+
+#[test]
+fn call_and_ret() {
+    let program = assemble_program(include_str!("rfact_stack.dis")).unwrap();
+    let config = MachineConfig {
+        stack: Some(StackConfig { sp: 2, limit: 0 }),
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&program.code, config);
+    machine.set_reg(10, 3).unwrap();
+    let (profiler, _) = profile(&mut machine, &program.labels);
+    let stacks: Vec<String> = profiler
+        .stacks()
+        .into_iter()
+        .map(|(stack, count)| format!("{} {count}", stack.join(";")))
+        .collect();
+    assert_eq!(
+        vec![
+            "0000 3",
+            "0000;rfact 11",
+            "0000;rfact;mult 23",
+            "0000;rfact;rfact 11",
+            "0000;rfact;rfact;mult 15",
+            "0000;rfact;rfact;rfact 6",
+        ],
+        stacks
+    );
+    assert_eq!(69, profiler.steps());
+}
+
+#[test]
+fn step_limit() {
+    let program = assemble_program(include_str!("../examples/factorial.dis")).unwrap();
+    let mut machine = Machine::new(&program.code);
+    let mut profiler = Profiler::new(&program.labels);
+    let mut console = Console::new(&b""[..], Vec::new());
+    assert!(matches!(
+        machine.run_with_limits_traced(&mut console, &mut profiler, Some(100), None),
+        Err(MachineError::StepLimitExceeded { steps: 100 })
+    ));
+    // The profile covers the steps executed before the limit.
+    assert_eq!(100, profiler.steps());
+}
+
+#[test]
+fn report() {
+    let program = assemble_program(
+        "
+        loadimm r1 <- #3
+        loadimm r2 <- #1
+    loop:
+        sub r1 <- r1 - r2
+        load r3 <- [r4]
+        move r0 <- r5 if r1 != 0
+        exit
+    ",
+    )
+    .unwrap();
+    let mut machine = Machine::new(&program.code);
+    machine.set_reg(4, 100).unwrap();
+    machine.set_reg(5, program.labels["loop"]).unwrap();
+    let (profiler, _) = profile(&mut machine, &program.labels);
+    assert_eq!(
+        "12 instructions executed\n\
+         \n\
+         instructions:\n\
+         \x20          3  25.00%  0008   sub r1 <- r1 - r2            <loop>\n\
+         \x20          3  25.00%  0012   load r3 <- [r4]              <loop+4>\n\
+         \x20          3  25.00%  0015   move r0 <- r5 if r1 != 0     <loop+7>\n\
+         \x20          1   8.33%  0000   loadimm r1 <- #3\n\
+         \x20          1   8.33%  0004   loadimm r2 <- #1\n\
+         \x20          1   8.33%  0019   exit                         <loop+11>\n\
+         \n\
+         labels:\n\
+         \x20         10  83.33%  loop\n\
+         \x20          2  16.67%  (before the first label)\n\
+         \n\
+         memory:\n\
+         \x20       reads     writes  address\n\
+         \x20          3          0  0100\n",
+        profiler.report()
+    );
+}
+
+#[test]
+fn command_line() {
+    let folded = std::env::temp_dir().join(format!("factorial-{}.folded", std::process::id()));
+    let output = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .arg("--profile")
+        .arg("--profile-folded")
+        .arg(&folded)
+        .arg("examples/factorial.bin")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).ends_with("I'm done!\n"));
+    // Labels come from the listing next to the program.
+    let report = String::from_utf8(output.stderr).unwrap();
+    assert!(report.starts_with("5040 instructions executed\n"));
+    assert!(report.contains("0507   loadimm r8 <- #1             <mult_loop>"));
+    let folded_stacks = std::fs::read_to_string(&folded).unwrap();
+    std::fs::remove_file(&folded).unwrap();
+    assert!(folded_stacks.contains("0000;fact;mult 1860\n"));
+}
@@ -0,0 +1,320 @@
+use interpreter::assembler::assemble;
+use interpreter::{
+    read_file_syscall, Access, Instruction, Machine, MachineConfig, MachineError, Permissions,
+    Protection, Region, SnapshotError, TrapCause, CTL_TRAP_CAUSE, CTL_TRAP_VALUE, SYS_READ_FILE,
+};
+use std::process::Command;
+
+fn protected(source: &str, protection: Protection) -> Machine {
+    let config = MachineConfig {
+        protection: Some(protection),
+        ..MachineConfig::default()
+    };
+    Machine::with_config(&assemble(source).unwrap(), config)
+}
+
+/// Permissions with `regions`, the initial image being left writable.
+fn with_regions(regions: Vec<Region>) -> Protection {
+    Protection {
+        regions,
+        protect_image: false,
+        ..Protection::default()
+    }
+}
+
+#[test]
+fn read_only_image() {
+    // A stray store into the code.
+    let source = "
+        loadimm r1 <- #next
+        store [r1] <- r2
+    next:
+        exit
+        ";
+    let mut machine = protected(source, Protection::default());
+    let error = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        error,
+        MachineError::ProtectionFault {
+            addr: 7,
+            kind: Access::Store,
+            ..
+        }
+    ));
+    assert_eq!(4, error.site().unwrap().ip);
+    assert_eq!(
+        "protection fault: store to address 7 not permitted at 0004 [02 01 02]",
+        error.to_string()
+    );
+    assert_eq!(7, machine.memory()[7]);
+
+    // Without permissions, the code gets overwritten.
+    let mut machine = Machine::new(&assemble(source).unwrap());
+    assert!(matches!(
+        machine.run_on(&mut Vec::new()),
+        Err(MachineError::InvalidInstruction { opcode: 0, .. })
+    ));
+}
+
+#[test]
+fn data_after_the_image() {
+    let mut machine = protected(
+        "
+        loadimm r1 <- #8
+        load r3 <- [r1]
+        loadimm r1 <- #1000
+        store [r1] <- r3
+        load r4 <- [r1]
+        exit
+        ",
+        Protection::default(),
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(machine.regs()[3], machine.regs()[4]);
+    assert_ne!(0, machine.regs()[4]);
+}
+
+#[test]
+fn regions() {
+    let regions = vec![
+        Region {
+            range: 1000..2000,
+            permissions: Permissions::READ_ONLY,
+        },
+        Region {
+            range: 1500..1600,
+            permissions: Permissions::NONE,
+        },
+        Region {
+            range: 3000..4096,
+            permissions: Permissions::READ_WRITE,
+        },
+    ];
+    let run = |source: &str, addr: u32| {
+        let mut machine = protected(source, with_regions(regions.clone()));
+        machine.set_reg(1, addr).unwrap();
+        machine.run_on(&mut Vec::new())
+    };
+    let fault = |result: Result<(), MachineError>| match result {
+        Err(MachineError::ProtectionFault { addr, kind, .. }) => Some((addr, kind)),
+        Ok(()) => None,
+        Err(e) => panic!("{e}"),
+    };
+
+    assert_eq!(None, fault(run("load r2 <- [r1]\nexit", 1000)));
+    assert_eq!(
+        Some((1000, Access::Store)),
+        fault(run("store [r1] <- r2\nexit", 1000))
+    );
+    // The last region wins.
+    assert_eq!(
+        Some((1500, Access::Load)),
+        fault(run("load r2 <- [r1]\nexit", 1500))
+    );
+    // Every byte of the access is checked.
+    assert_eq!(
+        Some((1500, Access::Load)),
+        fault(run("load r2 <- [r1]\nexit", 1498))
+    );
+    assert_eq!(None, fault(run("store [r1] <- r2\nexit", 3000)));
+    assert_eq!(
+        Some((3000, Access::Fetch)),
+        fault(run("loadimm r0 <- #3000", 0))
+    );
+    // Out of memory accesses keep their own error.
+    assert!(matches!(
+        run("load r2 <- [r1]\nexit", 4094),
+        Err(MachineError::MemoryOutOfBounds { .. })
+    ));
+}
+
+#[test]
+fn instructions_are_fetched_entirely() {
+    // The loadimm instruction at 1000 ends in the non-executable region.
+    let mut code = assemble("loadimm r0 <- #1000").unwrap();
+    code.resize(1000, 0);
+    code.extend(assemble("loadimm r1 <- #1").unwrap());
+    let config = MachineConfig {
+        protection: Some(with_regions(vec![Region {
+            range: 1003..1004,
+            permissions: Permissions::READ_WRITE,
+        }])),
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&code, config);
+    let error = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        error,
+        MachineError::ProtectionFault {
+            addr: 1003,
+            kind: Access::Fetch,
+            ..
+        }
+    ));
+    assert_eq!(1000, error.site().unwrap().ip);
+    assert_eq!(1000, machine.regs()[0]);
+}
+
+#[test]
+fn printing_unreadable_strings() {
+    // The string starts at 10.
+    let source = "
+        loadimm r1 <- #1
+        loadimm r3 <- #string
+        syscall
+        exit
+    string:
+        b'abcd\\0'
+    ";
+    let config = MachineConfig {
+        syscalls: true,
+        protection: Some(with_regions(vec![Region {
+            range: 12..13,
+            permissions: Permissions::NONE,
+        }])),
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&assemble(source).unwrap(), config);
+    let mut out = Vec::new();
+    assert!(matches!(
+        machine.run_on(&mut out),
+        Err(MachineError::ProtectionFault {
+            addr: 12,
+            kind: Access::Load,
+            ..
+        })
+    ));
+    assert!(out.is_empty());
+}
+
+#[test]
+fn reading_files_into_the_image() {
+    let dir = std::env::temp_dir().join(format!("protection-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("file.txt"), "content").unwrap();
+    let source = "
+        loadimm r1 <- #3
+        loadimm r3 <- #path
+        loadimm r4 <- #0
+        loadimm r5 <- #100
+        syscall
+        exit
+    path:
+        b'file.txt\\0'
+    ";
+    let config = MachineConfig {
+        syscalls: true,
+        protection: Some(Protection::default()),
+        ..MachineConfig::default()
+    };
+    let code = assemble(source).unwrap();
+    let mut machine = Machine::with_config(&code, config);
+    machine.register_syscall(SYS_READ_FILE, read_file_syscall(&dir));
+    let result = machine.run_on(&mut Vec::new());
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(
+        result,
+        Err(MachineError::ProtectionFault {
+            addr: 0,
+            kind: Access::Store,
+            ..
+        })
+    ));
+    assert_eq!(&code[..], &machine.memory()[..code.len()]);
+}
+
+#[test]
+fn protection_faults_are_trapped() {
+    let source = "
+        loadimm r1 <- #handler
+        setctl c0 <- r1
+        store [r1] <- r1
+    handler:
+        exit
+    ";
+    let config = MachineConfig {
+        traps: true,
+        protection: Some(Protection::default()),
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&assemble(source).unwrap(), config);
+    machine.run_with_traps(&mut Vec::new()).unwrap();
+    let control = machine.control_regs();
+    assert_eq!(
+        TrapCause::MemoryFault.code(),
+        control[CTL_TRAP_CAUSE as usize]
+    );
+    assert_eq!(10, control[CTL_TRAP_VALUE as usize]);
+}
+
+#[test]
+fn snapshots_smaller_than_the_image() {
+    let tiny = MachineConfig {
+        memory_size: 4,
+        ..MachineConfig::default()
+    };
+    let snapshot = Machine::with_config(&[], tiny).snapshot();
+    let mut machine = protected("loadimm r1 <- #1\nexit", Protection::default());
+    assert_eq!(
+        Err(SnapshotError::ImageDoesNotFit {
+            memory_size: 4,
+            image_size: 5
+        }),
+        machine.restore(&snapshot)
+    );
+    assert_eq!(4096, machine.memory().len());
+}
+
+#[test]
+fn larger_snapshots() {
+    let larger = MachineConfig {
+        memory_size: 8192,
+        ..MachineConfig::default()
+    };
+    let snapshot = Machine::with_config(&[], larger).snapshot();
+    let mut machine = protected("exit", Protection::default());
+    machine.restore(&snapshot).unwrap();
+    // The new memory gets the default permissions, and the image stays
+    // protected.
+    let mut store = |addr| {
+        machine.set_reg(1, addr).unwrap();
+        machine.execute(Instruction::Store { a: 1, b: 2 }, &mut Vec::new())
+    };
+    assert!(store(5000).is_ok());
+    assert!(matches!(
+        store(0),
+        Err(MachineError::ProtectionFault { .. })
+    ));
+}
+
+#[test]
+fn command_line_protect_image() {
+    let path = std::env::temp_dir().join(format!("protection-{}.bin", std::process::id()));
+    let code = assemble(
+        "
+        loadimm r1 <- #0
+        store [r1] <- r1
+        out_number r1
+        exit
+        ",
+    )
+    .unwrap();
+    std::fs::write(&path, code).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&[]);
+    assert!(output.status.success());
+    let output = run(&["--protect-image"]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .starts_with("error: protection fault: store to address 0 not permitted"));
+
+    std::fs::remove_file(&path).unwrap();
+}
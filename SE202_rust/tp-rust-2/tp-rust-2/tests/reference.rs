@@ -0,0 +1,241 @@
+use interpreter::reference::{lockstep, Difference, End, Reference};
+use interpreter::{Isa, Machine, MachineConfig, MachineError, StackConfig};
+use proptest::prelude::*;
+
+const EXTENDED: MachineConfig = MachineConfig {
+    memory_size: 4096,
+    nregs: 16,
+    isa: Isa::Extended,
+    stack: Some(StackConfig { sp: 2, limit: 0 }),
+    input: true,
+    traps: true,
+    syscalls: true,
+    protection: None,
+};
+
+/// Machine and reference running `program`, with `regs` as initial
+/// registers.
+fn pair(program: &[u8], config: MachineConfig, regs: &[(usize, u32)]) -> (Machine, Reference) {
+    let mut machine = Machine::with_config(program, config.clone());
+    let mut reference = Reference::new(program, config);
+    for &(reg, value) in regs {
+        machine.set_reg(reg, value).unwrap();
+        reference.set_reg(reg, value).unwrap();
+    }
+    (machine, reference)
+}
+
+#[test]
+fn shipped_programs() {
+    let programs: &[(&[u8], &[u8])] = &[
+        (include_bytes!("../examples/99bottles.bin"), b""),
+        (include_bytes!("../examples/count.bin"), b""),
+        (include_bytes!("../examples/factorial.bin"), b""),
+        (include_bytes!("../examples/fibonacci.bin"), b""),
+        (include_bytes!("../examples/guess.bin"), b"50 25 37"),
+        (include_bytes!("../examples/hello_world.bin"), b""),
+        (include_bytes!("afact.bin"), b""),
+        (include_bytes!("fact.bin"), b""),
+        (include_bytes!("fibo.bin"), b""),
+        (include_bytes!("function.bin"), b""),
+        (include_bytes!("multiply.bin"), b""),
+        (include_bytes!("push_pop.bin"), b""),
+        (include_bytes!("rfact.bin"), b""),
+        (include_bytes!("rfact_stack.bin"), b""),
+        (include_bytes!("rfact_tr.bin"), b""),
+    ];
+    for (program, input) in programs {
+        let (mut machine, mut reference) = pair(program, EXTENDED, &[(10, 7), (11, 6), (12, 3)]);
+        match lockstep(&mut machine, &mut reference, input, 10_000_000) {
+            Ok(agreement) => assert!(matches!(agreement.end, End::Exit)),
+            Err(divergence) => panic!("{divergence}"),
+        }
+    }
+}
+
+#[test]
+fn faults_agree() {
+    // 0: loadimm r1 <- #-1
+    // 4: load r2 <- [r1]
+    let (mut machine, mut reference) = pair(&[4, 1, 255, 255, 3, 2, 1], EXTENDED, &[]);
+    let agreement = lockstep(&mut machine, &mut reference, b"", 10).unwrap();
+    assert_eq!(2, agreement.steps);
+    match agreement.end {
+        End::Fault(MachineError::MemoryOutOfBounds { addr, .. }) => assert_eq!(u32::MAX, addr),
+        end => panic!("unexpected end {end:?}"),
+    }
+
+    // 0: loadimm r0 <- #0
+    let (mut machine, mut reference) = pair(&[4, 0, 0, 0], EXTENDED, &[]);
+    let agreement = lockstep(&mut machine, &mut reference, b"", 10).unwrap();
+    assert!(matches!(agreement.end, End::StepLimit));
+    assert_eq!(10, agreement.steps);
+
+    // Syscalls are left out of the comparison.
+    // 0: out_number r1
+    // 2: syscall
+    let (mut machine, mut reference) = pair(&[8, 1, 36], EXTENDED, &[(1, 5)]);
+    let agreement = lockstep(&mut machine, &mut reference, b"", 10).unwrap();
+    assert!(matches!(agreement.end, End::NotModelled));
+    assert_eq!(1, agreement.steps);
+    assert_eq!(b"5", &agreement.output[..]);
+}
+
+#[test]
+fn divergence_report() {
+    // 0: loadimm r2 <- #5
+    // 4: add r1 <- r2 + r2
+    // 8: exit
+    let program = [4, 2, 5, 0, 11, 1, 2, 2, 7];
+    let mut machine = Machine::with_config(&program, EXTENDED);
+    let base = MachineConfig {
+        isa: Isa::Base,
+        ..EXTENDED
+    };
+    let mut reference = Reference::new(&program, base);
+    let divergence = lockstep(&mut machine, &mut reference, b"", 10).unwrap_err();
+    assert_eq!(1, divergence.step);
+    assert_eq!(4, divergence.ip);
+    assert_eq!(
+        vec![
+            Difference::Outcome {
+                machine: "continue".to_string(),
+                reference: "UnsupportedInstruction".to_string()
+            },
+            Difference::Register {
+                reg: 1,
+                machine: 10,
+                reference: 0
+            },
+        ],
+        divergence.differences
+    );
+    assert_eq!(
+        "divergence at step 1 (IP 0004)\n  \
+         outcome: machine `continue`, reference `UnsupportedInstruction`\n  \
+         r1: machine 10, reference 0",
+        divergence.to_string()
+    );
+
+    // 0: store [r1] <- r2
+    let mut machine = Machine::new(&[2, 1, 2]);
+    let mut reference = Reference::new(&[2, 1, 2], MachineConfig::default());
+    for (reg, machine_value, reference_value) in [(1, 100, 100), (2, 1, 2)] {
+        machine.set_reg(reg, machine_value).unwrap();
+        reference.set_reg(reg, reference_value).unwrap();
+    }
+    let divergence = lockstep(&mut machine, &mut reference, b"", 10).unwrap_err();
+    assert_eq!(
+        vec![
+            Difference::Register {
+                reg: 2,
+                machine: 1,
+                reference: 2
+            },
+            Difference::Memory {
+                addr: 100,
+                machine: 1,
+                reference: 2
+            },
+        ],
+        divergence.differences
+    );
+
+    // Output is compared too.
+    // 0: out_number r1
+    // 2: exit
+    let mut machine = Machine::new(&[8, 1, 7]);
+    let mut reference = Reference::new(&[8, 1, 7], MachineConfig::default());
+    machine.set_reg(1, 12).unwrap();
+    reference.set_reg(1, 13).unwrap();
+    let divergence = lockstep(&mut machine, &mut reference, b"", 10).unwrap_err();
+    assert_eq!(0, divergence.step);
+    assert_eq!(
+        Some(&Difference::Output {
+            machine: b"12".to_vec(),
+            reference: b"13".to_vec()
+        }),
+        divergence.differences.last()
+    );
+}
+
+/// Register operand, usually an existing one.
+fn reg() -> impl Strategy<Value = u8> {
+    prop_oneof![30 => 0..16u8, 1 => any::<u8>()]
+}
+
+/// Instruction with a valid opcode most of the time. Registers usually
+/// exist, and immediates are often addresses in memory so that jumps and
+/// memory accesses do not always fail.
+fn instruction() -> impl Strategy<Value = Vec<u8>> {
+    let immediate = prop_oneof![0..1024u16, any::<u16>()];
+    (
+        prop_oneof![50 => 1..=32u8, 1 => 33..=36u8, 1 => any::<u8>()],
+        reg(),
+        reg(),
+        reg(),
+        immediate,
+    )
+        .prop_map(|(opcode, a, b, c, immediate)| {
+            let [lo, hi] = immediate.to_le_bytes();
+            match opcode {
+                4 => vec![opcode, a, lo, hi],
+                25 => vec![opcode, lo, hi],
+                7 | 26 | 35 | 36 => vec![opcode],
+                6 | 8 | 9 | 10 | 23 | 24 => vec![opcode, a],
+                2 | 3 | 27..=34 => vec![opcode, a, b],
+                1 | 5 | 11..=22 => vec![opcode, a, b, c],
+                _ => vec![opcode],
+            }
+        })
+}
+
+fn config() -> impl Strategy<Value = MachineConfig> {
+    let stack = prop::option::weighted(
+        0.75,
+        (1..8u8, 0..64u32).prop_map(|(sp, limit)| StackConfig { sp, limit }),
+    );
+    (
+        prop_oneof![Just(256), Just(1024)],
+        8..=16usize,
+        prop_oneof![1 => Just(Isa::Base), 3 => Just(Isa::Extended)],
+        stack,
+        prop::bool::weighted(0.75),
+        prop::bool::weighted(0.75),
+        prop::bool::weighted(0.75),
+    )
+        .prop_map(
+            |(memory_size, nregs, isa, stack, input, traps, syscalls)| MachineConfig {
+                memory_size,
+                nregs,
+                isa,
+                stack,
+                input,
+                traps,
+                syscalls,
+                protection: None,
+            },
+        )
+}
+
+proptest! {
+    #[test]
+    fn random_programs(
+        program in prop::collection::vec(instruction(), 1..40),
+        config in config(),
+        regs in prop::collection::vec(prop_oneof![0..300u32, any::<u32>()], 16),
+        input in prop::collection::vec(
+            prop_oneof![b'0'..=b'9', Just(b' '), Just(b'-'), any::<u8>()],
+            0..16,
+        ),
+    ) {
+        let program = program.concat();
+        let nregs = config.nregs;
+        // The IP stays at 0 so that the program starts.
+        let regs: Vec<(usize, u32)> = regs.into_iter().enumerate().skip(1).take(nregs - 1).collect();
+        let (mut machine, mut reference) = pair(&program, config, &regs);
+        if let Err(divergence) = lockstep(&mut machine, &mut reference, &input, 500) {
+            prop_assert!(false, "{}", divergence);
+        }
+    }
+}
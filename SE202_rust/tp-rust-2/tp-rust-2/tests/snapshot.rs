@@ -0,0 +1,225 @@
+use interpreter::{
+    Machine, MachineConfig, MachineError, Snapshot, SnapshotError, StackConfig, SNAPSHOT_MAGIC,
+};
+use std::process::Command;
+
+fn rfact_machine(n: u32) -> Machine {
+    let mut machine = Machine::new(include_bytes!("rfact.bin"));
+    machine.set_reg(10, n).unwrap();
+    machine
+}
+
+#[test]
+fn round_trip() {
+    let mut machine = rfact_machine(5);
+    machine.set_reg(15, 0xdeadbeef).unwrap();
+    let snapshot = machine.snapshot();
+    let bytes = snapshot.to_bytes();
+    assert_eq!(SNAPSHOT_MAGIC, &bytes[..8]);
+    assert_eq!(20 + 16 * 4 + 4096 + 4, bytes.len());
+    let decoded = Snapshot::from_bytes(&bytes).unwrap();
+    assert_eq!(snapshot, decoded);
+    assert_eq!(machine.regs(), decoded.regs());
+    assert_eq!(machine.memory(), decoded.memory());
+}
+
+#[test]
+fn pause_and_resume() {
+    let mut reference = rfact_machine(6);
+    reference.run().unwrap();
+
+    let mut machine = rfact_machine(6);
+    assert!(matches!(
+        machine.run_with_limit(&mut Vec::new(), 50),
+        Err(MachineError::StepLimitExceeded { steps: 50 })
+    ));
+    let bytes = machine.snapshot().to_bytes();
+
+    let mut resumed = Machine::new(&[]);
+    resumed
+        .restore(&Snapshot::from_bytes(&bytes).unwrap())
+        .unwrap();
+    assert_eq!(machine.regs(), resumed.regs());
+    resumed.run().unwrap();
+    assert_eq!(720, resumed.regs()[11]);
+    assert_eq!(reference.regs(), resumed.regs());
+    assert_eq!(reference.memory(), resumed.memory());
+}
+
+#[test]
+fn restore_adopts_snapshot_config() {
+    let config = MachineConfig {
+        memory_size: 65536,
+        nregs: 32,
+        ..MachineConfig::default()
+    };
+    let mut large = Machine::with_config(&[7], config);
+    large.set_reg(31, 12).unwrap();
+    let snapshot = Snapshot::from_bytes(&large.snapshot().to_bytes()).unwrap();
+
+    let mut machine = Machine::new(&[]);
+    machine.restore(&snapshot).unwrap();
+    assert_eq!(65536, machine.memory().len());
+    assert_eq!(32, machine.regs().len());
+    assert_eq!(12, machine.regs()[31]);
+    assert!(machine.step().unwrap());
+}
+
+#[test]
+fn restore_without_stack_pointer() {
+    let one_register = MachineConfig {
+        nregs: 1,
+        ..MachineConfig::default()
+    };
+    let snapshot = Machine::with_config(&[], one_register).snapshot();
+    let with_stack = MachineConfig {
+        stack: Some(StackConfig::default()),
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&[23, 1, 7], with_stack);
+    assert_eq!(
+        Err(SnapshotError::MissingStackPointer { nregs: 1, sp: 2 }),
+        machine.restore(&snapshot)
+    );
+    // The machine is left as it was.
+    assert_eq!(16, machine.regs().len());
+    assert_eq!(&[23, 1, 7], &machine.memory()[..3]);
+}
+
+#[test]
+fn invalid_snapshots() {
+    let bytes = rfact_machine(3).snapshot().to_bytes();
+
+    assert_eq!(Err(SnapshotError::BadMagic), Snapshot::from_bytes(&[]));
+    assert_eq!(
+        Err(SnapshotError::BadMagic),
+        Snapshot::from_bytes(include_bytes!("rfact.bin"))
+    );
+    assert_eq!(
+        Err(SnapshotError::Truncated),
+        Snapshot::from_bytes(&bytes[..12])
+    );
+    assert_eq!(
+        Err(SnapshotError::Truncated),
+        Snapshot::from_bytes(&bytes[..bytes.len() - 1])
+    );
+
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(
+        Err(SnapshotError::TrailingData),
+        Snapshot::from_bytes(&longer)
+    );
+
+    let mut future = bytes.clone();
+    future[8] = 2;
+    assert_eq!(
+        Err(SnapshotError::UnsupportedVersion(2)),
+        Snapshot::from_bytes(&future)
+    );
+
+    let mut no_regs = bytes.clone();
+    no_regs[12] = 0;
+    assert_eq!(
+        Err(SnapshotError::InvalidRegisterCount(0)),
+        Snapshot::from_bytes(&no_regs)
+    );
+
+    let mut corrupted = bytes.clone();
+    corrupted[100] ^= 1;
+    assert!(matches!(
+        Snapshot::from_bytes(&corrupted),
+        Err(SnapshotError::ChecksumMismatch { .. })
+    ));
+}
+
+#[test]
+fn command_line_snapshots() {
+    let dir = std::env::temp_dir();
+    let id = std::process::id();
+    let program = dir.join(format!("snapshot-{id}.bin"));
+    let first = dir.join(format!("snapshot-{id}-1.snap"));
+    let second = dir.join(format!("snapshot-{id}-2.snap"));
+    // 0: loadimm r1 <- #42
+    // 4: out_number r1
+    // 6: out_number r1
+    // 8: exit
+    std::fs::write(&program, [4, 1, 42, 0, 8, 1, 8, 1, 7]).unwrap();
+    let run = |args: &[&std::ffi::OsStr]| {
+        Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    // Pause after the first output.
+    let output = run(&[
+        "--max-steps".as_ref(),
+        "2".as_ref(),
+        "--save-snapshot-on-exit".as_ref(),
+        first.as_ref(),
+        program.as_ref(),
+    ]);
+    assert!(!output.status.success());
+    assert_eq!(&b"42"[..], &output.stdout[..]);
+    let snapshot = Snapshot::from_bytes(&std::fs::read(&first).unwrap()).unwrap();
+    assert_eq!(6, snapshot.regs()[0]);
+
+    // Resume until the end.
+    let output = run(&[
+        "--load-snapshot".as_ref(),
+        first.as_ref(),
+        "--save-snapshot-on-exit".as_ref(),
+        second.as_ref(),
+    ]);
+    assert!(output.status.success());
+    assert_eq!(&b"42"[..], &output.stdout[..]);
+    let snapshot = Snapshot::from_bytes(&std::fs::read(&second).unwrap()).unwrap();
+    assert_eq!(9, snapshot.regs()[0]);
+
+    // A program and a snapshot cannot be given together.
+    let output = run(&["--load-snapshot".as_ref(), first.as_ref(), program.as_ref()]);
+    assert_eq!(Some(2), output.status.code());
+
+    // The program is not a snapshot.
+    let output = run(&["--load-snapshot".as_ref(), program.as_ref()]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not a snapshot file"));
+
+    // The snapshot does not exist.
+    let missing = dir.join(format!("snapshot-{id}-missing.snap"));
+    let output = run(&["--load-snapshot".as_ref(), missing.as_ref()]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with(missing.to_str().unwrap()));
+
+    // The snapshot cannot be written.
+    let unwritable = dir.join(format!("snapshot-{id}-missing")).join("x.snap");
+    let output = run(&[
+        "--save-snapshot-on-exit".as_ref(),
+        unwritable.as_ref(),
+        program.as_ref(),
+    ]);
+    assert_eq!(Some(1), output.status.code());
+    assert_eq!(&b"4242"[..], &output.stdout[..]);
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with(unwritable.to_str().unwrap()));
+
+    // The snapshot has no stack pointer.
+    let one_register = MachineConfig {
+        nregs: 1,
+        ..MachineConfig::default()
+    };
+    let snapshot = Machine::with_config(&[7], one_register).snapshot();
+    std::fs::write(&first, snapshot.to_bytes()).unwrap();
+    let output = run(&[
+        "--stack".as_ref(),
+        "r2".as_ref(),
+        "--load-snapshot".as_ref(),
+        first.as_ref(),
+    ]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no stack pointer r2"));
+
+    for path in [program, first, second] {
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use interpreter::assembler::assemble;
+use interpreter::disassembler::{disassemble, listing};
+use interpreter::{Instruction, Isa, Machine, MachineConfig, MachineError, StackConfig};
+use std::process::Command;
+
+const WITH_STACK: MachineConfig = MachineConfig {
+    memory_size: 4096,
+    nregs: 16,
+    isa: Isa::Base,
+    stack: Some(StackConfig { sp: 2, limit: 0 }),
+    input: false,
+    traps: false,
+    syscalls: false,
+    protection: None,
+};
+
+fn machine_with_stack(source: &str) -> Machine {
+    Machine::with_config(&assemble(source).unwrap(), WITH_STACK)
+}
+
+/// Run `machine` until it exits and return the number of executed steps.
+fn count_steps(machine: &mut Machine) -> u32 {
+    let mut steps = 1;
+    while !machine.step_on(&mut Vec::new()).unwrap() {
+        steps += 1;
+    }
+    steps
+}
+
+#[test]
+fn push_and_pop() {
+    let mut machine = machine_with_stack(
+        "
+        loadimm r2 <- #4096
+        loadimm r1 <- #10
+        push r1
+        loadimm r1 <- #-2
+        push r1
+        pop r3
+        pop r4
+        exit
+        ",
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(&[0xffff_fffe, 4096, 0xffff_fffe, 10], &machine.regs()[1..5]);
+    assert_eq!(
+        &[0xfe, 0xff, 0xff, 0xff, 10, 0, 0, 0],
+        &machine.memory()[4088..]
+    );
+}
+
+#[test]
+fn push_the_stack_pointer() {
+    // The value pushed is the stack pointer before the push, and popping
+    // into the stack pointer sets it to the popped value.
+    let mut machine = machine_with_stack(
+        "
+        loadimm r2 <- #100
+        push r2
+        pop r2
+        push r2
+        load r5 <- [r2]
+        pop r2
+        exit
+        ",
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(100, machine.regs()[2]);
+    assert_eq!(100, machine.regs()[5]);
+}
+
+#[test]
+fn call_and_ret() {
+    let mut machine = machine_with_stack(
+        "
+        loadimm r2 <- #4096
+        call #twice
+        call #twice
+        out_number r1
+        exit
+    twice:
+        call #once
+        call #once
+        ret
+    once:
+        loadimm r3 <- #-1
+        sub r1 <- r1 - r3
+        ret
+        ",
+    );
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"4"[..], &out[..]);
+    assert_eq!(4096, machine.regs()[2]);
+}
+
+#[test]
+fn stack_overflow() {
+    let config = MachineConfig {
+        stack: Some(StackConfig { sp: 5, limit: 4088 }),
+        ..WITH_STACK
+    };
+    // 0: push r1
+    let mut machine = Machine::with_config(&[23, 1, 23, 1, 23, 1], config);
+    machine.set_reg(5, 4096).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert!(matches!(
+        machine.step_on(&mut Vec::new()),
+        Err(MachineError::StackOverflow { .. })
+    ));
+    assert_eq!(4088, machine.regs()[5]);
+
+    // Below address 0.
+    let mut machine = Machine::with_config(&[23, 1], WITH_STACK);
+    machine.set_reg(2, 3).unwrap();
+    assert!(matches!(
+        machine.step_on(&mut Vec::new()),
+        Err(MachineError::StackOverflow { .. })
+    ));
+    assert_eq!(3, machine.regs()[2]);
+
+    // Recursion without an end, with the limit protecting the code.
+    let config = MachineConfig {
+        stack: Some(StackConfig { sp: 2, limit: 64 }),
+        ..WITH_STACK
+    };
+    let code = assemble(
+        "
+        loadimm r2 <- #4096
+    forever:
+        call #forever
+        ",
+    )
+    .unwrap();
+    let mut machine = Machine::with_config(&code, config);
+    assert!(matches!(
+        machine.run_on(&mut Vec::new()),
+        Err(MachineError::StackOverflow { .. })
+    ));
+}
+
+#[test]
+fn stack_underflow() {
+    for source in ["loadimm r2 <- #4096\n pop r1", "loadimm r2 <- #4094\n ret"] {
+        let mut machine = machine_with_stack(source);
+        assert!(matches!(
+            machine.run_on(&mut Vec::new()),
+            Err(MachineError::StackUnderflow { .. })
+        ));
+    }
+
+    let mut machine = machine_with_stack(
+        "
+        loadimm r2 <- #4096
+        push r1
+        pop r1
+        pop r1
+        ",
+    );
+    assert!(matches!(
+        machine.run_on(&mut Vec::new()),
+        Err(MachineError::StackUnderflow { .. })
+    ));
+    assert_eq!(4096, machine.regs()[2]);
+}
+
+#[test]
+fn stack_instructions_need_a_stack() {
+    for code in [&[23, 1][..], &[24, 1], &[25, 0, 0], &[26]] {
+        let mut machine = Machine::new(code);
+        machine.set_reg(2, 4092).unwrap();
+        assert!(matches!(
+            machine.step_on(&mut Vec::new()),
+            Err(MachineError::UnsupportedInstruction { .. })
+        ));
+    }
+}
+
+#[test]
+#[should_panic]
+fn ip_cannot_be_the_stack_pointer() {
+    let config = MachineConfig {
+        stack: Some(StackConfig { sp: 0, limit: 0 }),
+        ..WITH_STACK
+    };
+    Machine::with_config(&[], config);
+}
+
+#[test]
+fn undo_stack_instructions() {
+    let mut machine = machine_with_stack(
+        "
+        loadimm r2 <- #4096
+        loadimm r1 <- #77
+        call #sub
+        exit
+    sub:
+        push r1
+        ret
+        ",
+    );
+    machine.enable_history(10);
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    let before = (machine.regs().to_vec(), machine.memory().to_vec());
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(77, machine.memory()[4088]);
+    assert!(machine.step_back());
+    assert!(machine.step_back());
+    assert_eq!(before, (machine.regs().to_vec(), machine.memory().to_vec()));
+}
+
+#[test]
+fn syntax() {
+    for (instruction, text) in [
+        (Instruction::Push { reg: 3 }, "push r3"),
+        (Instruction::Pop { reg: 15 }, "pop r15"),
+        (Instruction::Call { target: 0xfff0 }, "call #65520"),
+        (Instruction::Ret, "ret"),
+    ] {
+        assert_eq!(text, instruction.to_string());
+        assert_eq!(instruction.encode(), assemble(text).unwrap());
+        assert_eq!(
+            (instruction, instruction.size()),
+            Instruction::decode(&instruction.encode()).unwrap()
+        );
+    }
+    assert!(assemble("call #-1").is_err());
+    assert!(assemble("call #65536").is_err());
+    assert!(assemble("call r1").is_err());
+
+    // Call targets get a label in listings.
+    let code = assemble("call #f\nexit\nf: ret").unwrap();
+    assert_eq!(
+        "  0000   call #label_0004\n  0003   exit\nlabel_0004:\n  0004   ret\n",
+        listing(&disassemble(&code))
+    );
+}
+
+#[test]
+fn rfact_with_stack_instructions() {
+    for (n, expected) in [(1, 1), (5, 120), (12, 479001600)] {
+        let mut original = Machine::new(include_bytes!("rfact.bin"));
+        original.set_reg(10, n).unwrap();
+        let original_steps = count_steps(&mut original);
+
+        let mut machine = Machine::with_config(include_bytes!("rfact_stack.bin"), WITH_STACK);
+        machine.set_reg(10, n).unwrap();
+        let steps = count_steps(&mut machine);
+
+        assert_eq!(expected, original.regs()[11]);
+        assert_eq!(expected, machine.regs()[11]);
+        assert!(steps < original_steps, "{steps} >= {original_steps}");
+    }
+}
+
+#[test]
+fn command_line_stack() {
+    let path = std::env::temp_dir().join(format!("stack-{}.bin", std::process::id()));
+    let code = assemble(
+        "
+        loadimm r7 <- #4096
+        loadimm r1 <- #42
+        push r1
+        pop r3
+        out_number r3
+        exit
+        ",
+    )
+    .unwrap();
+    std::fs::write(&path, code).unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+            .args(args)
+            .arg(&path)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--stack", "r7"]);
+    assert!(output.status.success());
+    assert_eq!(&b"42"[..], &output.stdout[..]);
+
+    let output = run(&[]);
+    assert!(!output.status.success());
+
+    for register in ["r0", "r16", "7"] {
+        assert_eq!(Some(2), run(&["--stack", register]).status.code());
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,281 @@
+use interpreter::assembler::assemble;
+use interpreter::tracer::Tracer;
+use interpreter::{
+    read_file_syscall, Instruction, Machine, MachineConfig, MachineError, SYS_PRINT, SYS_READ_FILE,
+};
+use std::cell::RefCell;
+use std::process::Command;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Machine running `code` with the syscall instruction enabled.
+fn with_syscalls(code: &[u8]) -> Machine {
+    let config = MachineConfig {
+        syscalls: true,
+        ..MachineConfig::default()
+    };
+    Machine::with_config(code, config)
+}
+
+fn assembled(source: &str) -> Machine {
+    with_syscalls(&assemble(source).unwrap())
+}
+
+#[test]
+fn print() {
+    let mut machine = assembled(
+        "
+        loadimm r1 <- #1
+        loadimm r3 <- #hello
+        syscall
+        exit
+    hello:
+        b'Hello, world!\\n\\0'
+        ",
+    );
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"Hello, world!\n"[..], &out[..]);
+    assert_eq!(14, machine.regs()[1]);
+
+    // A string must end before the end of the memory.
+    let mut code = assemble("loadimm r1 <- #1\nloadimm r3 <- #4095\nsyscall").unwrap();
+    code.resize(4096, 255);
+    let mut machine = with_syscalls(&code);
+    assert!(matches!(
+        machine.run_on(&mut Vec::new()),
+        Err(MachineError::MemoryOutOfBounds { addr: 4095, .. })
+    ));
+}
+
+#[test]
+fn syscalls_are_optional() {
+    let mut machine = Machine::new(&assemble("loadimm r1 <- #2\nsyscall\nexit").unwrap());
+    assert!(matches!(
+        machine.run_on(&mut Vec::new()),
+        Err(MachineError::UnsupportedInstruction { .. })
+    ));
+    assert_eq!(2, machine.regs()[1]);
+
+    // Registered handlers do not make it available either.
+    let mut machine = Machine::new(&assemble("loadimm r1 <- #100\nsyscall\nexit").unwrap());
+    machine.register_syscall(100, Box::new(|_, _| panic!("syscall run")));
+    assert!(matches!(
+        machine.run_on(&mut Vec::new()),
+        Err(MachineError::UnsupportedInstruction { .. })
+    ));
+}
+
+#[test]
+fn time() {
+    let mut machine = assembled("loadimm r1 <- #2\nsyscall\nexit");
+    let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    let after = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let seconds = machine.regs()[1] as u64;
+    assert!(before.as_secs() as u32 as u64 <= seconds && seconds <= after.as_secs() as u32 as u64);
+    assert!(machine.regs()[3] < 1000);
+}
+
+#[test]
+fn host_handlers() {
+    let mut machine = assembled(
+        "
+        loadimm r1 <- #100
+        loadimm r3 <- #20
+        syscall
+        out_number r1
+        loadimm r1 <- #1
+        syscall
+        exit
+        ",
+    );
+    // Double r3 into r1.
+    machine.register_syscall(
+        100,
+        Box::new(|regs, _| {
+            regs[1] = regs[3] * 2;
+            Ok(())
+        }),
+    );
+    // Replace the built-in print.
+    let calls = Rc::new(RefCell::new(0));
+    let counter = calls.clone();
+    machine.register_syscall(
+        SYS_PRINT,
+        Box::new(move |_, _| {
+            *counter.borrow_mut() += 1;
+            Ok(())
+        }),
+    );
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(&b"40"[..], &out[..]);
+    assert_eq!(1, *calls.borrow());
+}
+
+#[test]
+fn handler_errors() {
+    let mut machine = assembled("loadimm r1 <- #7\nsyscall");
+    machine.register_syscall(
+        7,
+        Box::new(|_, _| Err(MachineError::DivisionByZero { site: None })),
+    );
+    let error = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(error, MachineError::DivisionByZero { .. }));
+    assert_eq!(4, error.site().unwrap().ip);
+
+    let mut machine = assembled("loadimm r1 <- #8\nsyscall");
+    let error = machine.run_on(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        error,
+        MachineError::UnknownSyscall { number: 8, .. }
+    ));
+    assert_eq!("unknown syscall 8 at 0004 [24]", error.to_string());
+}
+
+/// Tracer remembering the memory writes.
+#[derive(Default)]
+struct Writes(Vec<(u32, Vec<u8>, Vec<u8>)>);
+
+impl Tracer for Writes {
+    fn on_step(&mut self, _: u32, _: &[u8], _: &[u32], _: &[u32]) {}
+
+    fn on_mem_write(&mut self, addr: u32, old: &[u8], new: &[u8]) {
+        self.0.push((addr, old.to_vec(), new.to_vec()));
+    }
+}
+
+#[test]
+fn handler_writes_are_recorded() {
+    let mut machine = assembled("loadimm r1 <- #9\nsyscall\nexit");
+    machine.register_syscall(
+        9,
+        Box::new(|_, memory| {
+            memory[100..102].copy_from_slice(&[1, 2]);
+            memory[200] = 3;
+            Ok(())
+        }),
+    );
+    machine.enable_history(10);
+    let mut writes = Writes::default();
+    machine.run_traced(&mut Vec::new(), &mut writes).unwrap();
+    assert_eq!(
+        vec![(100, vec![0, 0], vec![1, 2]), (200, vec![0], vec![3])],
+        writes.0
+    );
+    assert!(machine.step_back());
+    assert!(machine.step_back());
+    assert_eq!(&[0, 0], &machine.memory()[100..102]);
+    assert_eq!(0, machine.memory()[200]);
+}
+
+#[test]
+fn read_files() {
+    let dir = std::env::temp_dir().join(format!("syscalls-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join("sub/file.txt"), "content").unwrap();
+    std::fs::write(dir.with_extension("secret"), "secret").unwrap();
+
+    let read = |path: &str, capacity: u32, sandbox: bool| {
+        let mut code = assemble(
+            "
+            loadimm r1 <- #3
+            loadimm r3 <- #path
+            loadimm r4 <- #1000
+            syscall
+            exit
+        path:
+            ",
+        )
+        .unwrap();
+        code.extend(path.as_bytes());
+        code.push(0);
+        let mut machine = with_syscalls(&code);
+        machine.set_reg(5, capacity).unwrap();
+        if sandbox {
+            machine.register_syscall(SYS_READ_FILE, read_file_syscall(&dir));
+        }
+        machine.run_on(&mut Vec::new()).map(|_| {
+            let read = machine.regs()[1];
+            let len = if read == u32::MAX { 0 } else { read as usize };
+            (read, machine.memory()[1000..1000 + len].to_vec())
+        })
+    };
+
+    assert_eq!(
+        (7, b"content".to_vec()),
+        read("sub/file.txt", 100, true).unwrap()
+    );
+    assert_eq!(
+        (4, b"cont".to_vec()),
+        read("./sub/file.txt", 4, true).unwrap()
+    );
+    let secret = format!(
+        "../{}",
+        dir.with_extension("secret")
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+    );
+    for path in [
+        "missing",
+        "sub",
+        &secret,
+        "/etc/passwd",
+        "sub/../sub/file.txt",
+    ] {
+        assert_eq!(u32::MAX, read(path, 100, true).unwrap().0, "{path}");
+    }
+    assert!(matches!(
+        read("sub/file.txt", 4000, true),
+        Err(MachineError::MemoryOutOfBounds { addr: 1000, .. })
+    ));
+    assert!(matches!(
+        read("sub/file.txt", 100, false),
+        Err(MachineError::UnknownSyscall { number: 3, .. })
+    ));
+
+    // From the command line.
+    let code = assemble(
+        "
+        loadimm r1 <- #3
+        loadimm r3 <- #path
+        loadimm r4 <- #1000
+        loadimm r5 <- #100
+        syscall
+        loadimm r1 <- #1
+        loadimm r3 <- #1000
+        syscall
+        exit
+    path:
+        b'sub/file.txt\\0'
+        ",
+    )
+    .unwrap();
+    let program = dir.with_extension("bin");
+    std::fs::write(&program, code).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_tp-rust-2"))
+        .arg("--sandbox")
+        .arg(&dir)
+        .arg(&program)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(&b"content"[..], &output.stdout[..]);
+
+    std::fs::remove_file(program).unwrap();
+    std::fs::remove_file(dir.with_extension("secret")).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn syntax() {
+    assert_eq!("syscall", Instruction::Syscall.to_string());
+    assert_eq!(vec![36], assemble("syscall").unwrap());
+    assert_eq!(
+        (Instruction::Syscall, 1),
+        Instruction::decode(&[36]).unwrap()
+    );
+}
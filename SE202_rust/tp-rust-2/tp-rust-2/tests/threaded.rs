@@ -0,0 +1,292 @@
+use interpreter::assembler::assemble;
+use interpreter::{
+    Console, Devices, Isa, Machine, MachineConfig, MachineError, StackConfig, SYS_TIME,
+};
+use proptest::prelude::*;
+
+const EXTENDED: MachineConfig = MachineConfig {
+    memory_size: 4096,
+    nregs: 16,
+    isa: Isa::Extended,
+    stack: Some(StackConfig { sp: 2, limit: 0 }),
+    input: true,
+    traps: true,
+    syscalls: true,
+    protection: None,
+};
+
+type Outcome = (Result<(), MachineError>, Vec<u8>);
+
+/// Run `machine` on `input`, threaded or not, with an optional step limit.
+fn run(machine: &mut Machine, input: &[u8], threaded: bool, max_steps: Option<u64>) -> Outcome {
+    let mut console = Console::new(input, Vec::new());
+    let result = if threaded {
+        machine.run_threaded_with_limits(&mut console, max_steps, None)
+    } else {
+        machine.run_with_limits(&mut console, max_steps, None)
+    };
+    (result, console.into_output())
+}
+
+/// Run a copy of `machine` with each engine and check that they end in the
+/// same state.
+fn compare(machine: impl Fn() -> Machine, input: &[u8], max_steps: Option<u64>) -> Outcome {
+    let mut interpreted = machine();
+    let mut threaded = machine();
+    let expected = run(&mut interpreted, input, false, max_steps);
+    let outcome = run(&mut threaded, input, true, max_steps);
+    // Errors cannot be compared, their descriptions can.
+    assert_eq!(format!("{:?}", expected.0), format!("{:?}", outcome.0));
+    assert_eq!(expected.1, outcome.1);
+    assert_eq!(interpreted.regs(), threaded.regs());
+    assert_eq!(interpreted.memory(), threaded.memory());
+    assert_eq!(interpreted.control_regs(), threaded.control_regs());
+    outcome
+}
+
+#[test]
+fn shipped_programs() {
+    let programs: &[(&[u8], &[u8])] = &[
+        (include_bytes!("../examples/99bottles.bin"), b""),
+        (include_bytes!("../examples/count.bin"), b""),
+        (include_bytes!("../examples/factorial.bin"), b""),
+        (include_bytes!("../examples/fibonacci.bin"), b""),
+        (include_bytes!("../examples/guess.bin"), b"50 25 37"),
+        (include_bytes!("../examples/hello_world.bin"), b""),
+        (include_bytes!("afact.bin"), b""),
+        (include_bytes!("fact.bin"), b""),
+        (include_bytes!("fibo.bin"), b""),
+        (include_bytes!("function.bin"), b""),
+        (include_bytes!("multiply.bin"), b""),
+        (include_bytes!("push_pop.bin"), b""),
+        (include_bytes!("rfact.bin"), b""),
+        (include_bytes!("rfact_stack.bin"), b""),
+        (include_bytes!("rfact_tr.bin"), b""),
+    ];
+    for (program, input) in programs {
+        let machine = || {
+            let mut machine = Machine::with_config(program, EXTENDED);
+            for (reg, value) in [(10, 7), (11, 6), (12, 3)] {
+                machine.set_reg(reg, value).unwrap();
+            }
+            machine
+        };
+        let (result, _) = compare(machine, input, None);
+        assert!(result.is_ok());
+    }
+}
+
+#[test]
+fn step_limits() {
+    let program = include_bytes!("../examples/99bottles.bin");
+    for max_steps in [0, 1, 2, 3, 10, 1000, 1023, 1024, 1025] {
+        let (result, _) = compare(|| Machine::new(program), b"", Some(max_steps));
+        assert!(matches!(
+            result,
+            Err(MachineError::StepLimitExceeded { steps }) if steps == max_steps
+        ));
+    }
+
+    // The limits are checked after every step, even within a block.
+    // 0: loadimm r1 <- #1
+    // 4: loadimm r2 <- #2
+    // 8: loadimm r0 <- #0
+    let program = [4, 1, 1, 0, 4, 2, 2, 0, 4, 0, 0, 0];
+    let mut machine = Machine::new(&program);
+    let (result, _) = run(&mut machine, b"", true, Some(5));
+    assert!(matches!(
+        result,
+        Err(MachineError::StepLimitExceeded { steps: 5 })
+    ));
+    assert_eq!(&[8, 1, 2], &machine.regs()[..3]);
+}
+
+#[test]
+fn self_modifying_code() {
+    // Prints the immediate of its first instruction twice, after replacing
+    // it with 7 the first time.
+    let code = assemble(
+        "
+    start:
+        loadimm r1 <- #1
+        out_number r1
+        move r0 <- r5 if r6 != 0
+        loadimm r5 <- #end
+        loadimm r6 <- #1
+        loadimm r2 <- #2
+        loadimm r3 <- #7
+        store8 [r2] <- r3
+        loadimm r0 <- #start
+    end:
+        exit
+    ",
+    )
+    .unwrap();
+    let (_, output) = compare(|| Machine::with_config(&code, EXTENDED), b"", None);
+    assert_eq!(b"17", &output[..]);
+
+    // Modifying the rest of the running block: the `out_number` becomes an
+    // `out`.
+    let code = assemble(
+        "
+        loadimm r1 <- #11
+        loadimm r2 <- #6
+        store8 [r1] <- r2
+        out_number r3
+        exit
+    ",
+    )
+    .unwrap();
+    let machine = || {
+        let mut machine = Machine::with_config(&code, EXTENDED);
+        machine.set_reg(3, 65).unwrap();
+        machine
+    };
+    let (result, output) = compare(machine, b"", None);
+    assert!(result.is_ok());
+    assert_eq!(b"A", &output[..]);
+}
+
+#[test]
+fn rewriting_code_in_a_loop() {
+    // Increments the immediate of its first instruction until it reaches
+    // 200, which makes the translated code change at every iteration.
+    let code = assemble(
+        "
+    start:
+        loadimm r1 <- #0
+        loadimm r2 <- #1
+        add r1 <- r1 + r2
+        loadimm r3 <- #2
+        store8 [r3] <- r1
+        loadimm r4 <- #200
+        sub r4 <- r4 - r1
+        loadimm r5 <- #start
+        move r0 <- r5 if r4 != 0
+        out_number r1
+        exit
+    ",
+    )
+    .unwrap();
+    let (_, output) = compare(|| Machine::with_config(&code, EXTENDED), b"", None);
+    assert_eq!(b"200", &output[..]);
+}
+
+#[test]
+fn faults() {
+    let programs: &[&[u8]] = &[
+        // 0: loadimm r1 <- #-1
+        // 4: load r2 <- [r1]
+        &[4, 1, 255, 255, 3, 2, 1],
+        // 0: loadimm r1 <- #1
+        // 4: sub r20 <- r1 - r1
+        &[4, 1, 1, 0, 5, 20, 1, 1],
+        // 0: loadimm r1 <- #1
+        // 4: add r1 <- r1 + r1
+        &[4, 1, 1, 0, 11, 1, 1, 1],
+        // 0: loadimm r1 <- #1
+        // 4: push r1
+        &[4, 1, 1, 0, 23, 1],
+        // 0: loadimm r1 <- #1
+        // 4: invalid opcode
+        &[4, 1, 1, 0, 99],
+        // 0: loadimm r1 <- #1, running off the end of memory
+        &[4, 1, 1, 0],
+    ];
+    for program in programs {
+        let (result, _) = compare(|| Machine::new(program), b"", None);
+        assert!(result.is_err());
+    }
+}
+
+#[test]
+fn devices_and_syscalls() {
+    // Prints a character through the console port, then the time of a
+    // host syscall.
+    let code = assemble(
+        "
+        loadimm r1 <- #4080
+        loadimm r2 <- #66
+        store [r1] <- r2
+        loadimm r1 <- #2
+        syscall
+        out_number r1
+        exit
+    ",
+    )
+    .unwrap();
+    let machine = || {
+        let config = MachineConfig {
+            syscalls: true,
+            ..MachineConfig::default()
+        };
+        let mut machine = Machine::with_config(&code, config);
+        machine.attach_bus(Box::new(Devices::default()));
+        machine.register_syscall(
+            SYS_TIME,
+            Box::new(|regs, _| {
+                regs[1] = 1234;
+                Ok(())
+            }),
+        );
+        machine
+    };
+    let (_, output) = compare(machine, b"", None);
+    assert_eq!(b"B1234", &output[..]);
+}
+
+/// Register operand, usually an existing one.
+fn reg() -> impl Strategy<Value = u8> {
+    prop_oneof![30 => 0..16u8, 1 => any::<u8>()]
+}
+
+/// Instruction with a valid opcode most of the time, writing the IP less
+/// often than others so that blocks get longer.
+fn instruction() -> impl Strategy<Value = Vec<u8>> {
+    let immediate = prop_oneof![0..256u16, any::<u16>()];
+    let first = prop_oneof![5 => 1..16u8, 1 => reg()];
+    (
+        prop_oneof![50 => 1..=36u8, 1 => any::<u8>()],
+        first,
+        reg(),
+        reg(),
+        immediate,
+    )
+        .prop_map(|(opcode, a, b, c, immediate)| {
+            let [lo, hi] = immediate.to_le_bytes();
+            match opcode {
+                4 => vec![opcode, a, lo, hi],
+                25 => vec![opcode, lo, hi],
+                7 | 26 | 35 | 36 => vec![opcode],
+                6 | 8 | 9 | 10 | 23 | 24 => vec![opcode, a],
+                2 | 3 | 27..=34 => vec![opcode, a, b],
+                1 | 5 | 11..=22 => vec![opcode, a, b, c],
+                _ => vec![opcode],
+            }
+        })
+}
+
+proptest! {
+    #[test]
+    fn random_programs(
+        program in prop::collection::vec(instruction(), 1..40),
+        extended in any::<bool>(),
+        regs in prop::collection::vec(prop_oneof![0..256u32, any::<u32>()], 16),
+        input in prop::collection::vec(any::<u8>(), 0..16),
+    ) {
+        let program = program.concat();
+        let config = MachineConfig {
+            memory_size: 256,
+            isa: if extended { Isa::Extended } else { Isa::Base },
+            ..EXTENDED
+        };
+        let machine = || {
+            let mut machine = Machine::with_config(&program, config.clone());
+            for (reg, &value) in regs.iter().enumerate().skip(1) {
+                machine.set_reg(reg, value).unwrap();
+            }
+            machine
+        };
+        let _ = compare(machine, &input, Some(500));
+    }
+}
@@ -0,0 +1,141 @@
+use interpreter::assembler::assemble;
+use interpreter::tracer::{JsonTracer, TextTracer, Tracer};
+use interpreter::{Machine, MachineConfig};
+
+// IP, instruction bytes, registers before and after
+type Step = (u32, Vec<u8>, Vec<u32>, Vec<u32>);
+
+#[derive(Default)]
+struct Recorder {
+    steps: Vec<Step>,
+    writes: Vec<(u32, Vec<u8>, Vec<u8>)>,
+    reads: Vec<(u32, usize)>,
+}
+
+impl Tracer for Recorder {
+    fn on_step(&mut self, ip: u32, insn: &[u8], before: &[u32], after: &[u32]) {
+        self.steps
+            .push((ip, insn.to_vec(), before.to_vec(), after.to_vec()));
+    }
+
+    fn on_mem_write(&mut self, addr: u32, old: &[u8], new: &[u8]) {
+        self.writes.push((addr, old.to_vec(), new.to_vec()));
+    }
+
+    fn on_mem_read(&mut self, addr: u32, width: usize) {
+        self.reads.push((addr, width));
+    }
+}
+
+// 0: loadimm r1 <- #258
+// 4: store [r2] <- r1
+// 7: out_number r1
+// 9: exit
+const PROGRAM: &[u8] = &[4, 1, 2, 1, 2, 2, 1, 8, 1, 7];
+
+#[test]
+fn custom_tracer() {
+    let mut machine = Machine::new(PROGRAM);
+    machine.set_reg(2, 100).unwrap();
+    let mut tracer = Recorder::default();
+    let mut out = Vec::new();
+    machine.run_traced(&mut out, &mut tracer).unwrap();
+    assert_eq!(&b"258"[..], &out[..]);
+
+    let ips: Vec<u32> = tracer.steps.iter().map(|step| step.0).collect();
+    assert_eq!(vec![0, 4, 7, 9], ips);
+    let (_, insn, before, after) = &tracer.steps[0];
+    assert_eq!(&[4, 1, 2, 1], &insn[..]);
+    assert_eq!((0, 0), (before[0], before[1]));
+    assert_eq!((4, 258), (after[0], after[1]));
+    assert_eq!(vec![(100, vec![0; 4], vec![2, 1, 0, 0])], tracer.writes);
+}
+
+#[test]
+fn memory_reads() {
+    let code = assemble(
+        "
+        loadimm r2 <- #100
+        load r4 <- [r2]
+        loadimm r1 <- #1
+        loadimm r3 <- #hello
+        syscall
+        exit
+    hello:
+        b'Hi\\0'
+    ",
+    )
+    .unwrap();
+    let config = MachineConfig {
+        syscalls: true,
+        ..MachineConfig::default()
+    };
+    let mut machine = Machine::with_config(&code, config);
+    let mut tracer = Recorder::default();
+    let mut out = Vec::new();
+    machine.run_traced(&mut out, &mut tracer).unwrap();
+    assert_eq!(&b"Hi"[..], &out[..]);
+    // The word loaded, then the string printed with its terminator.
+    assert_eq!(vec![(100, 4), (17, 3)], tracer.reads);
+    assert!(tracer.writes.is_empty());
+}
+
+#[test]
+fn no_step_reported_on_error() {
+    // 0: out_number r1
+    // 2: invalid
+    let mut machine = Machine::new(&[8, 1, 0]);
+    let mut tracer = Recorder::default();
+    assert!(machine.run_traced(&mut Vec::new(), &mut tracer).is_err());
+    assert_eq!(1, tracer.steps.len());
+}
+
+#[test]
+fn json_tracer() {
+    let mut machine = Machine::new(PROGRAM);
+    machine.set_reg(2, 100).unwrap();
+    let mut tracer = JsonTracer::new(Vec::new());
+    machine.run_traced(&mut Vec::new(), &mut tracer).unwrap();
+    let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+    let lines: Vec<&str> = trace.lines().collect();
+    assert_eq!(
+        vec![
+            r#"{"step":0,"ip":0,"insn":"loadimm r1 <- #258","bytes":[4,1,2,1],"regs":{"r0":4,"r1":258},"writes":[]}"#,
+            r#"{"step":1,"ip":4,"insn":"store [r2] <- r1","bytes":[2,2,1],"regs":{"r0":7},"writes":[{"addr":100,"old":[0,0,0,0],"new":[2,1,0,0]}]}"#,
+            r#"{"step":2,"ip":7,"insn":"out_number r1","bytes":[8,1],"regs":{"r0":9},"writes":[]}"#,
+            r#"{"step":3,"ip":9,"insn":"exit","bytes":[7],"regs":{"r0":10},"writes":[]}"#,
+        ],
+        lines
+    );
+}
+
+#[test]
+fn text_tracer() {
+    let mut machine = Machine::new(PROGRAM);
+    machine.set_reg(2, 100).unwrap();
+    let mut tracer = TextTracer::new(Vec::new());
+    machine.run_traced(&mut Vec::new(), &mut tracer).unwrap();
+    let trace = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+    assert_eq!(
+        "  0000   loadimm r1 <- #258           r0=4 r1=258\n\
+         \x20 0004   store [r2] <- r1             r0=7 [100]=02 01 00 00\n\
+         \x20 0007   out_number r1                r0=9\n\
+         \x20 0009   exit                         r0=10\n",
+        trace
+    );
+}
+
+#[test]
+fn identical_traces_for_identical_runs() {
+    let trace = |left: i32| {
+        let mut machine = Machine::new(include_bytes!("multiply.bin"));
+        machine.set_reg(11, left as u32).unwrap();
+        machine.set_reg(12, 3).unwrap();
+        let mut tracer = JsonTracer::new(Vec::new());
+        machine.run_traced(&mut Vec::new(), &mut tracer).unwrap();
+        assert_eq!(left * 3, machine.regs()[11] as i32);
+        tracer.into_inner().unwrap()
+    };
+    assert_eq!(trace(7), trace(7));
+    assert_ne!(trace(7), trace(-7));
+}
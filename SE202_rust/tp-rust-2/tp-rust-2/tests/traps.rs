@@ -0,0 +1,185 @@
+use interpreter::assembler::assemble;
+use interpreter::{
+    Instruction, Isa, Machine, MachineConfig, MachineError, TrapCause, CTL_TIMER_INTERVAL,
+    CTL_TRAP_CAUSE, CTL_TRAP_IP, CTL_TRAP_VALUE, CTL_TRAP_VECTOR,
+};
+
+const TRAPS: MachineConfig = MachineConfig {
+    memory_size: 4096,
+    nregs: 16,
+    isa: Isa::Base,
+    stack: None,
+    input: false,
+    traps: true,
+    syscalls: false,
+    protection: None,
+};
+
+fn assembled(source: &str) -> Machine {
+    Machine::with_config(&assemble(source).unwrap(), TRAPS)
+}
+
+#[test]
+fn skip_invalid_instructions() {
+    let mut machine = assembled(
+        "
+        loadimm r1 <- #handler
+        setctl c0 <- r1
+        loadimm r3 <- #-1
+        [200]
+        loadimm r6 <- #42
+        exit
+    handler:
+        getctl r4 <- c2
+        getctl r5 <- c3
+        getctl r2 <- c1
+        sub r2 <- r2 - r3
+        setctl c1 <- r2
+        iret
+        ",
+    );
+    machine.run_with_traps(&mut Vec::new()).unwrap();
+    assert_eq!(TrapCause::InvalidInstruction.code(), machine.regs()[4]);
+    assert_eq!(200, machine.regs()[5]);
+    assert_eq!(42, machine.regs()[6]);
+    assert!(!machine.in_trap_handler());
+}
+
+#[test]
+fn memory_faults() {
+    let mut machine = assembled(
+        "
+        loadimm r1 <- #handler
+        setctl c0 <- r1
+        loadimm r1 <- #5000
+        load r2 <- [r1]
+    handler:
+        exit
+        ",
+    );
+    machine.run_with_traps(&mut Vec::new()).unwrap();
+    let control = machine.control_regs();
+    assert_eq!(11, control[CTL_TRAP_IP as usize]);
+    assert_eq!(
+        TrapCause::MemoryFault.code(),
+        control[CTL_TRAP_CAUSE as usize]
+    );
+    assert_eq!(5000, control[CTL_TRAP_VALUE as usize]);
+    assert!(machine.in_trap_handler());
+}
+
+#[test]
+fn timer_interrupts() {
+    let mut machine = assembled(
+        "
+        loadimm r1 <- #handler
+        setctl c0 <- r1
+        loadimm r3 <- #-1
+        loadimm r1 <- #10
+        setctl c4 <- r1
+    loop:
+        loadimm r0 <- #loop
+    handler:
+        sub r9 <- r9 - r3
+        iret
+        ",
+    );
+    let mut interrupts = 0;
+    for _ in 0..1000 {
+        assert!(!machine.step_with_traps(&mut Vec::new()).unwrap());
+        if machine.in_trap_handler() && machine.regs()[0] == 22 {
+            interrupts += 1;
+            let control = machine.control_regs();
+            assert_eq!(TrapCause::Timer.code(), control[CTL_TRAP_CAUSE as usize]);
+            assert_eq!(18, control[CTL_TRAP_IP as usize]);
+        }
+    }
+    assert!(interrupts > 80, "{interrupts}");
+    assert_eq!(interrupts, machine.regs()[9]);
+
+    // Without an interval, the loop is never interrupted.
+    machine.set_control(CTL_TIMER_INTERVAL, 0).unwrap();
+    for _ in 0..100 {
+        machine.step_with_traps(&mut Vec::new()).unwrap();
+    }
+    assert!(!machine.in_trap_handler());
+    assert_eq!(interrupts, machine.regs()[9]);
+}
+
+#[test]
+fn faults_without_a_handler() {
+    // No trap vector.
+    let mut machine = assembled("[200]");
+    assert!(matches!(
+        machine.run_with_traps(&mut Vec::new()),
+        Err(MachineError::InvalidInstruction { opcode: 200, .. })
+    ));
+
+    // Fault inside the handler.
+    let mut machine = assembled(
+        "
+        loadimm r1 <- #handler
+        setctl c0 <- r1
+        [200]
+    handler:
+        [201]
+        ",
+    );
+    let error = machine.run_with_traps(&mut Vec::new()).unwrap_err();
+    assert!(matches!(
+        error,
+        MachineError::InvalidInstruction { opcode: 201, .. }
+    ));
+    assert_eq!(8, error.site().unwrap().ip);
+
+    // Only run_with_traps takes traps.
+    let mut machine = assembled("loadimm r1 <- #6\nsetctl c0 <- r1\n[200]\nexit");
+    assert!(machine.run_on(&mut Vec::new()).is_err());
+    assert_eq!(6, machine.control_regs()[CTL_TRAP_VECTOR as usize]);
+}
+
+#[test]
+fn traps_are_optional() {
+    // getctl r1 <- c0, setctl c0 <- r1 and iret
+    for program in [&[33, 1, 0][..], &[34, 0, 1], &[35]] {
+        let mut machine = Machine::new(program);
+        machine.set_reg(1, 7).unwrap();
+        assert!(matches!(
+            machine.step_on(&mut Vec::new()),
+            Err(MachineError::UnsupportedInstruction { .. })
+        ));
+        assert_eq!(7, machine.regs()[1]);
+        assert_eq!(0, machine.control_regs()[CTL_TRAP_VECTOR as usize]);
+
+        let mut machine = Machine::with_config(program, TRAPS);
+        assert!(!machine.step_on(&mut Vec::new()).unwrap());
+    }
+}
+
+#[test]
+fn invalid_control_registers() {
+    let mut machine = assembled("getctl r1 <- c5");
+    assert!(matches!(
+        machine.step_on(&mut Vec::new()),
+        Err(MachineError::InvalidControlRegister { reg: 5, .. })
+    ));
+    assert!(machine.set_control(5, 0).is_err());
+}
+
+#[test]
+fn syntax() {
+    for (instruction, text) in [
+        (Instruction::GetCtl { a: 3, c: 1 }, "getctl r3 <- c1"),
+        (Instruction::SetCtl { c: 4, a: 15 }, "setctl c4 <- r15"),
+        (Instruction::Iret, "iret"),
+    ] {
+        assert_eq!(text, instruction.to_string());
+        assert_eq!(instruction.encode(), assemble(text).unwrap());
+        assert_eq!(
+            (instruction, instruction.size()),
+            Instruction::decode(&instruction.encode()).unwrap()
+        );
+    }
+    assert!(assemble("getctl r1 <- r2").is_err());
+    assert!(assemble("setctl r1 <- r2").is_err());
+}